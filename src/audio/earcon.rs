@@ -0,0 +1,118 @@
+use std::{f32::consts::PI, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    loudness::{self, LoudnessSettings},
+    Pcm, SAMPLE_RATE,
+};
+
+/// How much quieter than speech the chime is, in LU.
+const CHIME_ATTENUATION_LU: f64 = 8.0;
+/// How long to wait after the chime before speaking.
+const CHIME_PAUSE: Duration = Duration::from_millis(80);
+/// The notes of the built in chime, as (frequency, start, length) in Hz and seconds.
+const BUILTIN_NOTES: [(f32, f32, f32); 2] = [(1046.5, 0.0, 0.25), (1318.5, 0.1, 0.3)];
+/// How quickly each note dies away, in e-folds per second.
+const BUILTIN_DECAY: f32 = 14.0;
+
+/// The sound played before each utterance, if any.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Chime {
+    #[default]
+    Off,
+    Builtin,
+    /// A clip the guild uploaded.
+    Custom,
+}
+
+impl Chime {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Builtin => "builtin",
+            Self::Custom => "custom",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Self::Off),
+            "builtin" => Some(Self::Builtin),
+            "custom" => Some(Self::Custom),
+            _ => None,
+        }
+    }
+}
+
+/// How a guild wants consecutive utterances set apart from each other.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct EarconSettings {
+    pub chime: Chime,
+    /// How much silence to leave after each utterance before the next one.
+    pub gap_milliseconds: u64,
+}
+
+impl EarconSettings {
+    pub fn gap(&self) -> Duration {
+        Duration::from_millis(self.gap_milliseconds)
+    }
+}
+
+/// A soft two note chime, made up on the spot rather than shipped as a file.
+pub fn builtin_chime() -> Pcm {
+    let rate = SAMPLE_RATE as f32;
+    let length = BUILTIN_NOTES
+        .iter()
+        .map(|&(_, start, length)| start + length)
+        .fold(0.0, f32::max);
+    let mut samples = vec![0f32; (length * rate) as usize];
+    for (frequency, start, length) in BUILTIN_NOTES {
+        let offset = (start * rate) as usize;
+        for i in 0..(length * rate) as usize {
+            let t = i as f32 / rate;
+            let envelope = (-BUILTIN_DECAY * t).exp();
+            // a touch of the octave above makes it sound more like a bell than a beep.
+            let tone = (2.0 * PI * frequency * t).sin() + 0.3 * (4.0 * PI * frequency * t).sin();
+            samples[offset + i] += 0.3 * envelope * tone;
+        }
+    }
+
+    let mut chime = Pcm {
+        sample_rate: SAMPLE_RATE,
+        channels: 1,
+        samples: Vec::new(),
+    };
+    chime.set_channels(&[samples]);
+    chime
+}
+
+/// Level a chime to sit a little under the guild's speech.
+pub fn level_chime(chime: &mut Pcm, settings: &LoudnessSettings) {
+    loudness::level(
+        chime,
+        &LoudnessSettings {
+            target_lufs: settings.target_lufs - CHIME_ATTENUATION_LU,
+            ..settings.clone()
+        },
+    );
+}
+
+fn silence(pcm: &Pcm, duration: Duration) -> Vec<i16> {
+    let frames = (duration.as_secs_f64() * pcm.sample_rate as f64) as usize;
+    vec![0; frames * pcm.channels as usize]
+}
+
+/// Play `chime` before the audio, if there is one, and leave `gap` of silence after it.
+pub fn surround(pcm: &mut Pcm, chime: Option<&Pcm>, gap: Duration) {
+    if let Some(chime) = chime {
+        let mut samples = chime.converted(pcm.sample_rate, pcm.channels).samples;
+        samples.extend(silence(pcm, CHIME_PAUSE));
+        samples.append(&mut pcm.samples);
+        pcm.samples = samples;
+    }
+    let gap = silence(pcm, gap);
+    pcm.samples.extend(gap);
+}
@@ -0,0 +1,428 @@
+use std::{f32::consts::PI, fmt, sync::Arc};
+
+use super::{Biquad, Pcm};
+
+/// The most effects that can be chained onto one thing being said.
+pub const MAX_EFFECTS: usize = 5;
+/// How far the pitch can be shifted either way, in semitones.
+const MAX_PITCH_SHIFT: i32 = 12;
+
+/// How fast the robot voice's carrier wobbles. Lower sounds more like a Dalek.
+const ROBOT_FREQUENCY: f32 = 50.0;
+const ECHO_DELAY_SECONDS: f32 = 0.3;
+const ECHO_FEEDBACK: f32 = 0.4;
+/// How many echoes to leave room for after the audio would otherwise end.
+const ECHO_REPEATS: usize = 4;
+/// The delays of the reverb's parallel comb filters, picked so they don't line up.
+const REVERB_COMBS_SECONDS: [f32; 4] = [0.0297, 0.0371, 0.0411, 0.0437];
+const REVERB_FEEDBACK: f32 = 0.8;
+/// The delays of the all-pass filters that diffuse the reverb, along with their gain.
+const REVERB_ALL_PASSES: [(f32, f32); 2] = [(0.005, 0.7), (0.0017, 0.7)];
+const REVERB_TAIL_SECONDS: f32 = 1.0;
+const REVERB_MIX: f32 = 0.3;
+/// How long a stretch of audio the pitch shifter crossfades over. Longer is smoother but
+/// smears the speech more.
+const PITCH_WINDOW_SECONDS: f32 = 0.04;
+/// Roughly the range of a telephone or two-way radio.
+const RADIO_LOW_HZ: f32 = 300.0;
+const RADIO_HIGH_HZ: f32 = 3400.0;
+const RADIO_DRIVE: f32 = 2.5;
+
+/// Something done to synthesized audio before it's played, without another trip to the
+/// TTS API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Effect {
+    /// Ring modulation, for a robotic voice.
+    Robot,
+    Echo,
+    Reverb,
+    /// Shift the pitch by this many semitones, without speeding up or slowing down.
+    Pitch(i32),
+    /// Band-passed and slightly overdriven, like it's coming over a radio.
+    Radio,
+    Reverse,
+}
+
+/// What effects are available, for showing to people who asked for one that isn't.
+pub const EFFECTS_HELP: &str =
+    "`robot`, `echo`, `reverb`, `radio`, `reverse`, and `pitch+N` or `pitch-N` to shift the pitch by N semitones";
+
+/// Why a chain of effects couldn't be understood, in a way that can be shown to whoever
+/// asked for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EffectError {
+    Unknown(String),
+    TooMany,
+}
+
+impl fmt::Display for EffectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(name) => write!(
+                f,
+                "I don't know the `{}` effect. You can use {}.",
+                name, EFFECTS_HELP
+            ),
+            Self::TooMany => write!(f, "At most {} effects can be chained.", MAX_EFFECTS),
+        }
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Robot => write!(f, "robot"),
+            Self::Echo => write!(f, "echo"),
+            Self::Reverb => write!(f, "reverb"),
+            Self::Pitch(semitones) => write!(f, "pitch{:+}", semitones),
+            Self::Radio => write!(f, "radio"),
+            Self::Reverse => write!(f, "reverse"),
+        }
+    }
+}
+
+impl Effect {
+    fn from_name(name: &str) -> Result<Self, EffectError> {
+        let lower = name.to_lowercase();
+        Ok(match lower.as_str() {
+            "robot" => Self::Robot,
+            "echo" => Self::Echo,
+            "reverb" => Self::Reverb,
+            "radio" => Self::Radio,
+            "reverse" => Self::Reverse,
+            _ => match lower.strip_prefix("pitch").map(str::parse::<i32>) {
+                Some(Ok(n)) if n != 0 && n.abs() <= MAX_PITCH_SHIFT => Self::Pitch(n),
+                _ => return Err(EffectError::Unknown(name.to_owned())),
+            },
+        })
+    }
+
+    /// Run a single channel of audio, with samples between -1 and 1, through this effect.
+    fn process(&self, mut samples: Vec<f32>, rate: f32) -> Vec<f32> {
+        match *self {
+            Self::Robot => {
+                let step = 2.0 * PI * ROBOT_FREQUENCY / rate;
+                for (i, s) in samples.iter_mut().enumerate() {
+                    *s *= (i as f32 * step).sin();
+                }
+                samples
+            }
+            Self::Echo => {
+                let delay = (ECHO_DELAY_SECONDS * rate) as usize;
+                samples.resize(samples.len() + delay * ECHO_REPEATS, 0.0);
+                for i in delay..samples.len() {
+                    samples[i] += samples[i - delay] * ECHO_FEEDBACK;
+                }
+                samples
+            }
+            Self::Reverb => reverb(samples, rate),
+            Self::Pitch(semitones) => pitch_shift(&samples, semitones, rate),
+            Self::Radio => {
+                Biquad::high_pass(RADIO_LOW_HZ, rate).run(&mut samples);
+                Biquad::low_pass(RADIO_HIGH_HZ, rate).run(&mut samples);
+                let normalize = RADIO_DRIVE.tanh();
+                for s in samples.iter_mut() {
+                    *s = (*s * RADIO_DRIVE).tanh() / normalize;
+                }
+                samples
+            }
+            Self::Reverse => {
+                samples.reverse();
+                samples
+            }
+        }
+    }
+}
+
+/// Parse a chain of effects separated by commas or spaces, like `robot, echo`. They're
+/// applied in the order given.
+pub fn parse_chain(input: &str) -> Result<Vec<Effect>, EffectError> {
+    let effects = input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+        .map(Effect::from_name)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if effects.len() > MAX_EFFECTS {
+        return Err(EffectError::TooMany);
+    }
+    Ok(effects)
+}
+
+/// Ways to finish off a partly typed chain of effects, for autocomplete.
+pub fn complete_chain(typed: &str) -> Vec<String> {
+    let split = typed
+        .rfind(|c: char| c == ',' || c.is_whitespace())
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let (done, partial) = typed.split_at(split);
+    let partial = partial.to_lowercase();
+
+    let mut suggestions = [
+        Effect::Robot,
+        Effect::Echo,
+        Effect::Reverb,
+        Effect::Radio,
+        Effect::Reverse,
+        Effect::Pitch(4),
+        Effect::Pitch(-4),
+    ]
+    .iter()
+    .map(Effect::to_string)
+    .filter(|name| name.starts_with(&partial))
+    .map(|name| format!("{}{}", done, name))
+    .collect::<Vec<_>>();
+    // whatever's typed may already be a finished chain, like a pitch shift we don't suggest.
+    if !partial.is_empty() && parse_chain(typed).is_ok() && !suggestions.iter().any(|s| s == typed)
+    {
+        suggestions.insert(0, typed.to_owned());
+    }
+    suggestions
+}
+
+/// Describe a chain of effects the way `parse_chain` would read it back.
+pub fn describe_chain(effects: &[Effect]) -> String {
+    effects
+        .iter()
+        .map(Effect::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Run synthesized WAV audio through a chain of effects, returning new WAV audio. The
+/// audio is handed back untouched if there are no effects. The effects run on a blocking
+/// thread, since a long clip through reverb takes a while.
+pub async fn apply(audio: &Arc<[u8]>, effects: &[Effect]) -> anyhow::Result<Arc<[u8]>> {
+    if effects.is_empty() {
+        return Ok(audio.clone());
+    }
+
+    let audio = audio.clone();
+    let effects = effects.to_vec();
+    tokio::task::spawn_blocking(move || run_chain(&audio, &effects))
+        .await?
+        .map(Into::into)
+}
+
+fn run_chain(audio: &[u8], effects: &[Effect]) -> anyhow::Result<Vec<u8>> {
+    let mut pcm = Pcm::from_wav(audio)?;
+    let rate = pcm.sample_rate as f32;
+    let mut split = pcm.to_channels();
+
+    for effect in effects {
+        split = split
+            .into_iter()
+            .map(|channel| effect.process(channel, rate))
+            .collect();
+    }
+
+    // echoes and reverb pile up on top of the original, so bring it back down rather than clip.
+    let peak = split
+        .iter()
+        .flatten()
+        .fold(0f32, |peak, s| peak.max(s.abs()));
+    if peak > 1.0 {
+        split.iter_mut().flatten().for_each(|s| *s /= peak);
+    }
+    pcm.set_channels(&split);
+
+    Ok(pcm.to_wav())
+}
+
+/// A Schroeder reverb: parallel comb filters for the reflections, then all-pass filters
+/// to diffuse them.
+fn reverb(mut dry: Vec<f32>, rate: f32) -> Vec<f32> {
+    dry.resize(dry.len() + (REVERB_TAIL_SECONDS * rate) as usize, 0.0);
+
+    let mut wet = vec![0.0; dry.len()];
+    for seconds in REVERB_COMBS_SECONDS {
+        let delay = (seconds * rate) as usize;
+        let mut comb = vec![0.0; dry.len()];
+        for i in 0..dry.len() {
+            let feedback = if i >= delay { comb[i - delay] } else { 0.0 };
+            comb[i] = dry[i] + feedback * REVERB_FEEDBACK;
+            wet[i] += comb[i] / REVERB_COMBS_SECONDS.len() as f32;
+        }
+    }
+
+    for (seconds, gain) in REVERB_ALL_PASSES {
+        let delay = (seconds * rate) as usize;
+        let input = wet.clone();
+        for i in delay..wet.len() {
+            wet[i] = -gain * input[i] + input[i - delay] + gain * wet[i - delay];
+        }
+    }
+
+    dry.iter()
+        .zip(wet)
+        .map(|(dry, wet)| dry + wet * REVERB_MIX)
+        .collect()
+}
+
+/// Shift the pitch without changing the tempo, by reading the audio back through two
+/// delay lines whose delay sweeps at the rate needed for the new pitch. Each one jumps
+/// back when it runs out of window, so they're crossfaded half a window apart to hide it.
+fn pitch_shift(samples: &[f32], semitones: i32, rate: f32) -> Vec<f32> {
+    let ratio = 2f32.powf(semitones as f32 / 12.0);
+    let window = PITCH_WINDOW_SECONDS * rate;
+    let step = (1.0 - ratio) / window;
+
+    let read = |position: f32| {
+        if position < 0.0 {
+            return 0.0;
+        }
+        let index = position as usize;
+        let fraction = position - index as f32;
+        let a = samples.get(index).copied().unwrap_or(0.0);
+        let b = samples.get(index + 1).copied().unwrap_or(0.0);
+        a + (b - a) * fraction
+    };
+
+    let mut phase = 0f32;
+    let mut shifted = Vec::with_capacity(samples.len());
+    for i in 0..samples.len() {
+        let mut sample = 0.0;
+        for offset in [0.0, 0.5] {
+            let p = (phase + offset).fract();
+            // sin² and cos² always add up to one, so the two taps crossfade without a dip.
+            sample += (PI * p).sin().powi(2) * read(i as f32 - p * window);
+        }
+        shifted.push(sample);
+        phase = (phase + step).rem_euclid(1.0);
+    }
+    shifted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48_000.0;
+
+    fn sine(frequency: f32, seconds: f32, amplitude: f32) -> Vec<f32> {
+        (0..(seconds * RATE) as usize)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f32 / RATE).sin())
+            .collect()
+    }
+
+    /// The frequency of a steady tone, from how often it crosses zero.
+    fn frequency(samples: &[f32]) -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count();
+        crossings as f32 / 2.0 / (samples.len() as f32 / RATE)
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn parses_chains() {
+        assert_eq!(
+            parse_chain("Robot, echo pitch-3"),
+            Ok(vec![Effect::Robot, Effect::Echo, Effect::Pitch(-3)])
+        );
+        assert_eq!(
+            parse_chain("pitch+13"),
+            Err(EffectError::Unknown("pitch+13".into()))
+        );
+        assert_eq!(
+            parse_chain("pitch0").unwrap_err(),
+            EffectError::Unknown("pitch0".into())
+        );
+        assert_eq!(
+            parse_chain("echo echo echo echo echo echo"),
+            Err(EffectError::TooMany)
+        );
+        assert_eq!(
+            parse_chain(&describe_chain(&[Effect::Radio, Effect::Pitch(4)])),
+            Ok(vec![Effect::Radio, Effect::Pitch(4)])
+        );
+    }
+
+    #[test]
+    fn echo_repeats_an_impulse() {
+        let mut impulse = vec![0.0; 100];
+        impulse[0] = 1.0;
+        let echoed = Effect::Echo.process(impulse, RATE);
+
+        let delay = (ECHO_DELAY_SECONDS * RATE) as usize;
+        assert_eq!(echoed.len(), 100 + delay * ECHO_REPEATS);
+        for repeat in 1..=ECHO_REPEATS {
+            let expected = ECHO_FEEDBACK.powi(repeat as i32);
+            assert!((echoed[delay * repeat] - expected).abs() < 1e-6);
+        }
+        assert_eq!(echoed[delay / 2], 0.0);
+    }
+
+    #[test]
+    fn reverb_rings_on_after_the_audio_ends() {
+        let dry = sine(440.0, 0.2, 0.5);
+        let wet = Effect::Reverb.process(dry.clone(), RATE);
+
+        assert_eq!(wet.len(), dry.len() + (REVERB_TAIL_SECONDS * RATE) as usize);
+        let tail = &wet[dry.len()..dry.len() + (0.1 * RATE) as usize];
+        assert!(rms(tail) > 0.01, "reverb tail is silent");
+        assert!(wet.iter().all(|s| s.is_finite()));
+    }
+
+    #[test]
+    fn pitch_shift_keeps_length_and_changes_frequency() {
+        let tone = sine(200.0, 0.5, 0.5);
+        let up = Effect::Pitch(12).process(tone.clone(), RATE);
+        let down = Effect::Pitch(-12).process(tone.clone(), RATE);
+
+        assert_eq!(up.len(), tone.len());
+        assert_eq!(down.len(), tone.len());
+        // skip the start, where the delay lines are still filling up.
+        let steady = (0.1 * RATE) as usize..tone.len();
+        assert!((frequency(&up[steady.clone()]) - 400.0).abs() < 20.0);
+        assert!((frequency(&down[steady]) - 100.0).abs() < 10.0);
+    }
+
+    #[test]
+    fn radio_cuts_the_lows() {
+        let low = Effect::Radio.process(sine(50.0, 0.5, 0.1), RATE);
+        let mid = Effect::Radio.process(sine(1000.0, 0.5, 0.1), RATE);
+        let steady = (0.1 * RATE) as usize..;
+        assert!(rms(&low[steady.clone()]) < rms(&mid[steady]) / 10.0);
+    }
+
+    #[test]
+    fn robot_and_reverse_keep_the_length() {
+        let tone = sine(440.0, 0.1, 0.5);
+        let robot = Effect::Robot.process(tone.clone(), RATE);
+        assert_eq!(robot.len(), tone.len());
+        assert!(robot.iter().zip(&tone).all(|(r, t)| r.abs() <= t.abs()));
+
+        let reversed = Effect::Reverse.process(vec![0.1, 0.2, 0.3], RATE);
+        assert_eq!(reversed, vec![0.3, 0.2, 0.1]);
+    }
+
+    #[test]
+    fn chains_do_not_clip() {
+        let samples = sine(440.0, 0.5, 1.0)
+            .into_iter()
+            .map(|s| (s * i16::MAX as f32) as i16)
+            .collect();
+        let wav = Pcm {
+            sample_rate: RATE as u32,
+            channels: 1,
+            samples,
+        }
+        .to_wav();
+
+        let processed = run_chain(&wav, &[Effect::Echo, Effect::Reverb]).unwrap();
+        let pcm = Pcm::from_wav(&processed).unwrap();
+        // echoes pile up well past full scale, so it was scaled back down rather than clipped.
+        let at_full_scale = pcm
+            .samples
+            .iter()
+            .filter(|s| s.unsigned_abs() >= i16::MAX as u16 - 1)
+            .count();
+        assert!(at_full_scale < 4, "{} samples clipped", at_full_scale);
+        assert!(pcm.samples.len() > (0.5 * RATE) as usize);
+    }
+}
@@ -0,0 +1,388 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Biquad, Pcm};
+
+/// Anything quieter than this, in dBFS, counts as silence when trimming.
+const SILENCE_THRESHOLD_DB: f32 = -50.0;
+/// How much silence to leave either side of the speech, so it doesn't start or stop abruptly.
+const SILENCE_PADDING_SECONDS: f32 = 0.05;
+/// Loudness is measured over blocks this long, each overlapping the last by 75%.
+const BLOCK_SECONDS: f32 = 0.4;
+/// Blocks quieter than this, in LUFS, are left out of the measurement entirely.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks more than this many LU below the ungated loudness are left out too.
+const RELATIVE_GATE_LU: f64 = 10.0;
+/// How far ahead of a peak the limiter starts easing the gain down.
+const LIMITER_ATTACK_SECONDS: f32 = 0.005;
+const LIMITER_RELEASE_SECONDS: f32 = 0.05;
+/// How many points to check between each pair of samples when looking for true peaks.
+const OVERSAMPLING: usize = 4;
+/// How many samples either side of a point the interpolator looks at.
+const INTERPOLATION_TAPS: isize = 8;
+
+/// How a guild wants synthesized audio evened out before it's played.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct LoudnessSettings {
+    /// Bring every clip to the same integrated loudness.
+    pub normalize: bool,
+    /// The integrated loudness to aim for, in LUFS.
+    pub target_lufs: f64,
+    /// The loudest any peak is allowed to get, between samples included, in dBTP.
+    pub true_peak_dbtp: f64,
+    /// Cut the silence off the start and end of clips.
+    pub trim_silence: bool,
+}
+
+impl Default for LoudnessSettings {
+    fn default() -> Self {
+        Self {
+            normalize: true,
+            target_lufs: -18.0,
+            true_peak_dbtp: -1.0,
+            trim_silence: true,
+        }
+    }
+}
+
+impl LoudnessSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.normalize || self.trim_silence
+    }
+}
+
+/// Trim and normalize audio, as configured in `settings`.
+pub fn level(pcm: &mut Pcm, settings: &LoudnessSettings) {
+    if pcm.samples.is_empty() {
+        return;
+    }
+    let rate = pcm.sample_rate as f32;
+    let mut channels = pcm.to_channels();
+
+    if settings.trim_silence {
+        trim_silence(&mut channels, rate);
+    }
+    if settings.normalize {
+        if let Some(loudness) = integrated_loudness(&channels, rate) {
+            let gain = db_to_gain((settings.target_lufs - loudness) as f32);
+            channels.iter_mut().flatten().for_each(|s| *s *= gain);
+        }
+        limit(
+            &mut channels,
+            rate,
+            db_to_gain(settings.true_peak_dbtp as f32),
+        );
+    }
+
+    pcm.set_channels(&channels);
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Cut off every frame at the start and end that's silent on all channels, other than a
+/// little padding.
+fn trim_silence(channels: &mut [Vec<f32>], rate: f32) {
+    let threshold = db_to_gain(SILENCE_THRESHOLD_DB);
+    let frames = channels.iter().map(Vec::len).min().unwrap_or(0);
+    let loud = |i: usize| channels.iter().any(|c| c[i].abs() > threshold);
+
+    let (first, last) = match (
+        (0..frames).find(|&i| loud(i)),
+        (0..frames).rfind(|&i| loud(i)),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        // it's all silence, so there's nothing to trim it down to.
+        _ => return,
+    };
+    let padding = (SILENCE_PADDING_SECONDS * rate) as usize;
+    let start = first.saturating_sub(padding);
+    let end = (last + 1 + padding).min(frames);
+
+    for channel in channels.iter_mut() {
+        channel.truncate(end);
+        channel.drain(..start);
+    }
+}
+
+/// The K-weighting filters from ITU-R BS.1770, which roughly match how loud we hear
+/// different frequencies. The coefficients are worked out for any sample rate the same
+/// way libebur128 does.
+fn k_weighting(rate: f32) -> [Biquad; 2] {
+    let shelf = {
+        let (f0, gain, q) = (1_681.974_5, 3.999_843_8, 0.707_175_25);
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f32.powf(gain / 20.0);
+        let vb = vh.powf(0.499_666_77);
+        Biquad::new(
+            vh + vb * k / q + k * k,
+            2.0 * (k * k - vh),
+            vh - vb * k / q + k * k,
+            1.0 + k / q + k * k,
+            2.0 * (k * k - 1.0),
+            1.0 - k / q + k * k,
+        )
+    };
+    let high_pass = {
+        let (f0, q) = (38.135_47, 0.500_327_04);
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Biquad::new(
+            a0,
+            -2.0 * a0,
+            a0,
+            a0,
+            2.0 * (k * k - 1.0),
+            1.0 - k / q + k * k,
+        )
+    };
+    [shelf, high_pass]
+}
+
+/// Measure gated integrated loudness in LUFS, following EBU R128. Returns `None` if
+/// there's nothing loud enough to measure.
+fn integrated_loudness(channels: &[Vec<f32>], rate: f32) -> Option<f64> {
+    let weighted = channels
+        .iter()
+        .map(|channel| {
+            let mut channel = channel.clone();
+            for filter in k_weighting(rate).iter() {
+                filter.run(&mut channel);
+            }
+            channel
+        })
+        .collect::<Vec<_>>();
+
+    let frames = weighted.iter().map(Vec::len).min().unwrap_or(0);
+    if frames == 0 {
+        return None;
+    }
+    // clips shorter than a block are measured as a single block.
+    let block = ((BLOCK_SECONDS * rate) as usize).min(frames).max(1);
+    let step = (block / 4).max(1);
+    let powers = (0..=frames.saturating_sub(block))
+        .step_by(step)
+        .map(|start| {
+            weighted
+                .iter()
+                .map(|c| {
+                    c[start..start + block]
+                        .iter()
+                        .map(|&s| (s * s) as f64)
+                        .sum::<f64>()
+                })
+                .sum::<f64>()
+                / block as f64
+        })
+        .filter(|&power| power > 0.0)
+        .collect::<Vec<_>>();
+
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let mean = |powers: &[f64]| {
+        (!powers.is_empty()).then(|| powers.iter().sum::<f64>() / powers.len() as f64)
+    };
+
+    let absolute = powers
+        .into_iter()
+        .filter(|&p| loudness(p) > ABSOLUTE_GATE_LUFS)
+        .collect::<Vec<_>>();
+    let relative_gate = loudness(mean(&absolute)?) - RELATIVE_GATE_LU;
+    let relative = absolute
+        .into_iter()
+        .filter(|&p| loudness(p) > relative_gate)
+        .collect::<Vec<_>>();
+
+    Some(loudness(mean(&relative)?))
+}
+
+/// Estimate the true peak around each sample, including between it and the next one, by
+/// interpolating with a windowed sinc.
+fn true_peaks(channel: &[f32]) -> Vec<f32> {
+    let kernel = (1..OVERSAMPLING)
+        .map(|phase| {
+            let offset = phase as f32 / OVERSAMPLING as f32;
+            (-INTERPOLATION_TAPS + 1..=INTERPOLATION_TAPS)
+                .map(|tap| {
+                    let x = tap as f32 - offset;
+                    let sinc = (PI * x).sin() / (PI * x);
+                    let window = 0.5 + 0.5 * (PI * x / INTERPOLATION_TAPS as f32).cos();
+                    (tap, sinc * window)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    (0..channel.len())
+        .map(|i| {
+            kernel
+                .iter()
+                .map(|taps| {
+                    taps.iter()
+                        .map(|&(tap, weight)| {
+                            let at = i as isize + tap;
+                            if at < 0 {
+                                0.0
+                            } else {
+                                channel.get(at as usize).copied().unwrap_or(0.0) * weight
+                            }
+                        })
+                        .sum::<f32>()
+                        .abs()
+                })
+                .fold(channel[i].abs(), f32::max)
+        })
+        .collect()
+}
+
+/// Keep true peaks under `ceiling`, by easing the gain down ahead of them and back up
+/// afterwards rather than clipping. The gain is the same across all channels.
+fn limit(channels: &mut [Vec<f32>], rate: f32, ceiling: f32) {
+    let frames = channels.iter().map(Vec::len).min().unwrap_or(0);
+    let mut peaks = vec![0f32; frames];
+    for channel in channels.iter() {
+        for (peak, channel_peak) in peaks.iter_mut().zip(true_peaks(channel)) {
+            *peak = peak.max(channel_peak);
+        }
+    }
+
+    let mut gains = peaks
+        .iter()
+        .map(|&peak| if peak > ceiling { ceiling / peak } else { 1.0 })
+        .collect::<Vec<_>>();
+    if gains.iter().all(|&g| g >= 1.0) {
+        return;
+    }
+
+    let attack = 1.0 / (LIMITER_ATTACK_SECONDS * rate).max(1.0);
+    for i in (0..frames.saturating_sub(1)).rev() {
+        gains[i] = gains[i].min(gains[i + 1] + attack);
+    }
+    let release = 1.0 / (LIMITER_RELEASE_SECONDS * rate).max(1.0);
+    for i in 1..frames {
+        gains[i] = gains[i].min(gains[i - 1] + release);
+    }
+
+    for channel in channels.iter_mut() {
+        for (s, gain) in channel.iter_mut().zip(&gains) {
+            *s *= gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn tone(frequency: f32, seconds: f32, amplitude: f32) -> Vec<f32> {
+        (0..(seconds * RATE as f32) as usize)
+            .map(|i| amplitude * (2.0 * PI * frequency * i as f32 / RATE as f32).sin())
+            .collect()
+    }
+
+    fn pcm(samples: &[f32]) -> Pcm {
+        let mut pcm = Pcm {
+            sample_rate: RATE,
+            channels: 1,
+            samples: vec![0; samples.len()],
+        };
+        pcm.set_channels(&[samples.to_vec()]);
+        pcm
+    }
+
+    fn settings(normalize: bool, target_lufs: f64, trim_silence: bool) -> LoudnessSettings {
+        LoudnessSettings {
+            normalize,
+            target_lufs,
+            true_peak_dbtp: -1.0,
+            trim_silence,
+        }
+    }
+
+    #[test]
+    fn measures_a_full_scale_tone() {
+        // a full scale 1kHz sine on one channel reads about -3 LUFS, per BS.1770.
+        let loudness = integrated_loudness(&[tone(1000.0, 2.0, 1.0)], RATE as f32).unwrap();
+        assert!((loudness + 3.0).abs() < 0.2, "measured {}", loudness);
+        assert_eq!(integrated_loudness(&[vec![0.0; 48_000]], RATE as f32), None);
+    }
+
+    #[test]
+    fn normalizes_to_the_target() {
+        for (amplitude, target) in [(0.01, -23.0), (0.5, -18.0), (0.9, -30.0)] {
+            let mut audio = pcm(&tone(1000.0, 2.0, amplitude));
+            level(&mut audio, &settings(true, target, false));
+
+            let measured = integrated_loudness(&audio.to_channels(), RATE as f32).unwrap();
+            assert!(
+                (measured - target).abs() < 0.5,
+                "aimed for {} but got {}",
+                target,
+                measured
+            );
+        }
+    }
+
+    #[test]
+    fn limits_peaks_to_the_ceiling() {
+        // -3 LUFS needs peaks well above -1 dBTP, so the limiter has to step in.
+        let mut audio = pcm(&tone(1000.0, 1.0, 0.1));
+        let loud = settings(true, -3.0, false);
+        level(&mut audio, &loud);
+
+        let ceiling = db_to_gain(loud.true_peak_dbtp as f32);
+        let channel = &audio.to_channels()[0];
+        let peak = true_peaks(channel).into_iter().fold(0f32, f32::max);
+        assert!(
+            peak <= ceiling + 0.01,
+            "peak {} over ceiling {}",
+            peak,
+            ceiling
+        );
+        // it's held down to the ceiling, not squashed far below it.
+        assert!(
+            peak > ceiling - 0.05,
+            "peak {} well under ceiling {}",
+            peak,
+            ceiling
+        );
+    }
+
+    #[test]
+    fn trims_silence_down_to_the_padding() {
+        let mut samples = vec![0.0; RATE as usize];
+        samples.extend(tone(440.0, 0.2, 0.5));
+        samples.extend(vec![0.0; RATE as usize]);
+        let mut audio = pcm(&samples);
+        level(&mut audio, &settings(false, -18.0, true));
+
+        let expected = 0.2 + 2.0 * SILENCE_PADDING_SECONDS;
+        let seconds = audio.samples.len() as f32 / RATE as f32;
+        assert!(
+            (seconds - expected).abs() < 0.01,
+            "{} seconds left",
+            seconds
+        );
+
+        // silence all the way through has nothing to trim down to.
+        let mut silent = pcm(&[0.0; 1000]);
+        level(&mut silent, &settings(false, -18.0, true));
+        assert_eq!(silent.samples.len(), 1000);
+    }
+
+    #[test]
+    fn empty_audio_is_left_alone() {
+        let mut pcm = Pcm {
+            sample_rate: 48_000,
+            channels: 1,
+            samples: Vec::new(),
+        };
+        level(&mut pcm, &LoudnessSettings::default());
+        assert!(pcm.samples.is_empty());
+        assert_eq!(integrated_loudness(&[Vec::new()], 48_000.0), None);
+    }
+}
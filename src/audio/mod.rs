@@ -0,0 +1,282 @@
+use std::{convert::TryInto, f32::consts::PI, time::Duration};
+
+use anyhow::{anyhow, Context as anyhowContext};
+
+pub mod earcon;
+pub mod effects;
+pub mod loudness;
+
+/// The sample rate we ask the TTS API for, so that clips can be joined together. It's also
+/// what songbird plays at, which saves resampling on the way out.
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Uncompressed 16 bit audio, interleaved if there's more than one channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pcm {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<i16>,
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> anyhow::Result<u16> {
+    let b = bytes.get(at..at + 2).context("WAV file is truncated")?;
+    Ok(u16::from_le_bytes(b.try_into()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> anyhow::Result<u32> {
+    let b = bytes.get(at..at + 4).context("WAV file is truncated")?;
+    Ok(u32::from_le_bytes(b.try_into()?))
+}
+
+impl Pcm {
+    /// Read a 16 bit PCM WAV file, which is what the TTS API gives us for `LINEAR16`.
+    pub fn from_wav(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.get(0..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") {
+            return Err(anyhow!("Not a WAV file"));
+        }
+
+        let mut format = None;
+        let mut at = 12;
+        while at + 8 <= bytes.len() {
+            let id = &bytes[at..at + 4];
+            let size = read_u32(bytes, at + 4)? as usize;
+            let body = at + 8;
+            // streamed WAV files don't always know their own length, so trust what's there.
+            let end = body.saturating_add(size).min(bytes.len());
+
+            match id {
+                b"fmt " => {
+                    let encoding = read_u16(bytes, body)?;
+                    let channels = read_u16(bytes, body + 2)?;
+                    let sample_rate = read_u32(bytes, body + 4)?;
+                    let bits = read_u16(bytes, body + 14)?;
+                    if encoding != 1 || bits != 16 || channels == 0 {
+                        return Err(anyhow!("Only 16 bit PCM WAV files are supported"));
+                    }
+                    if sample_rate == 0 {
+                        return Err(anyhow!("WAV file has no sample rate"));
+                    }
+                    format = Some((sample_rate, channels));
+                }
+                b"data" => {
+                    let (sample_rate, channels) =
+                        format.context("WAV data came before its format")?;
+                    let samples = bytes[body..end]
+                        .chunks_exact(2)
+                        .map(|s| i16::from_le_bytes([s[0], s[1]]))
+                        .collect::<Vec<_>>();
+                    if samples.is_empty() {
+                        return Err(anyhow!("WAV file has no samples"));
+                    }
+                    return Ok(Self {
+                        sample_rate,
+                        channels,
+                        samples,
+                    });
+                }
+                _ => {}
+            }
+
+            // chunks are padded out to an even length.
+            at = end + (size % 2);
+        }
+
+        Err(anyhow!("WAV file has no data"))
+    }
+
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_len = (self.samples.len() * 2) as u32;
+        let block_align = self.channels * 2;
+
+        let mut wav = Vec::with_capacity(44 + data_len as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&self.channels.to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for s in &self.samples {
+            wav.extend_from_slice(&s.to_le_bytes());
+        }
+        wav
+    }
+
+    /// Split the audio up into a run of samples for each channel, between -1 and 1.
+    fn to_channels(&self) -> Vec<Vec<f32>> {
+        let channels = self.channels.max(1) as usize;
+        (0..channels)
+            .map(|c| {
+                self.samples
+                    .iter()
+                    .skip(c)
+                    .step_by(channels)
+                    .map(|&s| s as f32 / i16::MAX as f32)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Replace the audio with runs of samples for each channel, like `to_channels` gives.
+    /// Anything past full scale is clipped, and any channel longer than the others is cut short.
+    fn set_channels(&mut self, channels: &[Vec<f32>]) {
+        let frames = channels.iter().map(Vec::len).min().unwrap_or(0);
+        self.samples = (0..frames)
+            .flat_map(|i| {
+                channels
+                    .iter()
+                    .map(move |channel| (channel[i].clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+            })
+            .collect();
+    }
+
+    /// Convert the audio to another sample rate and number of channels. It's mixed down and
+    /// resampled simply, which is fine for short sounds but not for music.
+    pub fn converted(&self, sample_rate: u32, channels: u16) -> Pcm {
+        if self.sample_rate == sample_rate && self.channels == channels {
+            return self.clone();
+        }
+
+        let source = self.to_channels();
+        let frames = source.iter().map(Vec::len).min().unwrap_or(0);
+        let mono = (0..frames)
+            .map(|i| source.iter().map(|c| c[i]).sum::<f32>() / source.len() as f32)
+            .collect::<Vec<_>>();
+
+        let ratio = self.sample_rate as f64 / sample_rate as f64;
+        let resampled = (0..(frames as f64 / ratio) as usize)
+            .map(|i| {
+                let position = i as f64 * ratio;
+                let index = position as usize;
+                let fraction = (position - index as f64) as f32;
+                let a = mono.get(index).copied().unwrap_or(0.0);
+                let b = mono.get(index + 1).copied().unwrap_or(a);
+                a + (b - a) * fraction
+            })
+            .collect::<Vec<_>>();
+
+        let mut converted = Pcm {
+            sample_rate,
+            channels,
+            samples: Vec::new(),
+        };
+        converted.set_channels(&vec![resampled; channels.max(1) as usize]);
+        converted
+    }
+
+    /// How long the audio plays for.
+    pub fn duration(&self) -> Duration {
+        let frames = self.samples.len() / self.channels.max(1) as usize;
+        Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64)
+    }
+
+    /// Add `other` onto the end of this audio.
+    pub fn append(&mut self, other: &Pcm) -> anyhow::Result<()> {
+        if self.sample_rate != other.sample_rate || self.channels != other.channels {
+            return Err(anyhow!(
+                "Can't join {}Hz/{}ch audio onto {}Hz/{}ch audio",
+                other.sample_rate,
+                other.channels,
+                self.sample_rate,
+                self.channels
+            ));
+        }
+        self.samples.extend_from_slice(&other.samples);
+        Ok(())
+    }
+}
+
+/// A second order filter, with coefficients from the Audio EQ Cookbook.
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    const Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    fn new(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    fn low_pass(frequency: f32, rate: f32) -> Self {
+        let w0 = 2.0 * PI * frequency / rate;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * Self::Q));
+        Self::new(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    fn high_pass(frequency: f32, rate: f32) -> Self {
+        let w0 = 2.0 * PI * frequency / rate;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * Self::Q));
+        Self::new(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    fn run(&self, samples: &mut [f32]) {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        for s in samples.iter_mut() {
+            let x = *s;
+            let y = self.b0 * x + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+            x2 = x1;
+            x1 = x;
+            y2 = y1;
+            y1 = y;
+            *s = y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(sample_rate: u32, samples: Vec<i16>) -> Vec<u8> {
+        Pcm {
+            sample_rate,
+            channels: 1,
+            samples,
+        }
+        .to_wav()
+    }
+
+    #[test]
+    fn round_trips_wav() {
+        let pcm = Pcm::from_wav(&wav(SAMPLE_RATE, vec![0, 1, -1, i16::MAX])).unwrap();
+        assert_eq!(pcm.sample_rate, SAMPLE_RATE);
+        assert_eq!(pcm.samples, vec![0, 1, -1, i16::MAX]);
+    }
+
+    #[test]
+    fn rejects_wav_that_cannot_be_played() {
+        assert!(Pcm::from_wav(&wav(SAMPLE_RATE, Vec::new())).is_err());
+        assert!(Pcm::from_wav(&wav(0, vec![0, 1, 2])).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context as anyhowContext;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    async_trait,
+    client::{Context, EventHandler},
+    model::{
+        gateway::Ready,
+        id::{ChannelId, GuildId, UserId},
+        Timestamp,
+    },
+    prelude::TypeMapKey,
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::settings::{get_settings_from_ctx, GuildSettings};
+
+/// How often entries past their guild's retention period are cleared out.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct AuditLogs;
+impl TypeMapKey for AuditLogs {
+    type Value = Arc<AuditLogStore>;
+}
+
+/// A record of something the bot said.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub timestamp: Timestamp,
+    /// The guild it was said in.
+    pub guild_id: GuildId,
+    /// Who asked for it to be said, if anyone in particular.
+    pub user: Option<UserId>,
+    /// The voice channel it was said in.
+    pub channel: Option<ChannelId>,
+    /// The text as it was given to us, before any processing.
+    pub text: String,
+    /// The name of the voice that said it.
+    pub voice: String,
+}
+
+/// Everything the bot has said in a guild, oldest first.
+#[derive(Default, Clone, Debug)]
+pub struct AuditLog {
+    pub entries: Vec<AuditEntry>,
+}
+
+/// The unix timestamp before which entries are past the guild's retention period.
+fn retention_cutoff(settings: &GuildSettings) -> i64 {
+    Timestamp::now().unix_timestamp() - settings.history_retention().as_secs() as i64
+}
+
+impl AuditLog {
+    /// Entries that are still within the guild's retention period, newest first.
+    pub fn recent(&self, settings: &GuildSettings) -> impl Iterator<Item = &AuditEntry> {
+        let cutoff = retention_cutoff(settings);
+        self.entries
+            .iter()
+            .rev()
+            .filter(move |e| e.timestamp.unix_timestamp() >= cutoff)
+    }
+}
+
+/// Keeps each guild's audit log as a file of JSON lines, one per entry. Something gets
+/// said far more often than the log is read, so recording an entry only ever appends to
+/// the file, and anything that removes entries rewrites it separately.
+pub struct AuditLogStore {
+    directory: PathBuf,
+    /// One lock per guild, so an append never lands halfway through a rewrite.
+    locks: std::sync::Mutex<HashMap<GuildId, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl AuditLogStore {
+    pub fn new(directory: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Could not create data directory {:?}", directory))?;
+
+        Ok(Self {
+            directory,
+            locks: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn path_for(&self, guild_id: GuildId) -> PathBuf {
+        self.directory.join(format!("{}.jsonl", guild_id.0))
+    }
+
+    fn lock_for(&self, guild_id: GuildId) -> Arc<tokio::sync::Mutex<()>> {
+        self.locks
+            .lock()
+            .expect("audit log lock poisoned")
+            .entry(guild_id)
+            .or_default()
+            .clone()
+    }
+
+    async fn read(&self, guild_id: GuildId) -> anyhow::Result<Vec<AuditEntry>> {
+        let path = self.path_for(guild_id);
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Could not read audit log at {:?}", path))
+            }
+        };
+
+        Ok(contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                // most likely a line cut short by a crash mid-append, which shouldn't cost us the rest.
+                Err(e) => {
+                    tracing::warn!(?e, ?path, "Skipping unreadable audit log entry");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    async fn write(&self, guild_id: GuildId, entries: &[AuditEntry]) -> anyhow::Result<()> {
+        let path = self.path_for(guild_id);
+        let tmp_path = path.with_extension("jsonl.tmp");
+        let mut bytes = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut bytes, entry).context("Could not serialize audit entry")?;
+            bytes.push(b'\n');
+        }
+
+        tokio::fs::write(&tmp_path, bytes)
+            .await
+            .with_context(|| format!("Could not write audit log to {:?}", tmp_path))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .with_context(|| format!("Could not move audit log into place at {:?}", path))
+    }
+
+    /// Everything in a guild's log, including anything past its retention period that
+    /// hasn't been pruned yet.
+    pub async fn get(&self, guild_id: GuildId) -> anyhow::Result<AuditLog> {
+        let lock = self.lock_for(guild_id);
+        let _guard = lock.lock().await;
+        Ok(AuditLog {
+            entries: self.read(guild_id).await?,
+        })
+    }
+
+    pub async fn append(&self, guild_id: GuildId, entry: &AuditEntry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(entry).context("Could not serialize audit entry")?;
+        line.push(b'\n');
+
+        let lock = self.lock_for(guild_id);
+        let _guard = lock.lock().await;
+        let path = self.path_for(guild_id);
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Could not open audit log at {:?}", path))?;
+        file.write_all(&line)
+            .await
+            .with_context(|| format!("Could not append to audit log at {:?}", path))
+    }
+
+    /// Keep only the entries `keep` returns true for, returning how many were removed.
+    pub async fn retain<F>(&self, guild_id: GuildId, mut keep: F) -> anyhow::Result<usize>
+    where
+        F: FnMut(&AuditEntry) -> bool,
+    {
+        let lock = self.lock_for(guild_id);
+        let _guard = lock.lock().await;
+        let mut entries = self.read(guild_id).await?;
+        let before = entries.len();
+        entries.retain(|e| keep(e));
+
+        let removed = before - entries.len();
+        if removed > 0 {
+            self.write(guild_id, &entries).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Drop every entry older than the guild's retention period.
+    pub async fn prune(
+        &self,
+        guild_id: GuildId,
+        settings: &GuildSettings,
+    ) -> anyhow::Result<usize> {
+        let cutoff = retention_cutoff(settings);
+        self.retain(guild_id, |e| e.timestamp.unix_timestamp() >= cutoff)
+            .await
+    }
+}
+
+pub async fn get_audit_logs_from_ctx(ctx: &Context) -> Arc<AuditLogStore> {
+    ctx.data
+        .read()
+        .await
+        .get::<AuditLogs>()
+        .expect("Audit log store should be present")
+        .clone()
+}
+
+/// Add an entry to a guild's audit log, unless the guild has turned history off.
+pub async fn record(
+    ctx: &Context,
+    guild_id: GuildId,
+    settings: &GuildSettings,
+    entry: AuditEntry,
+) -> anyhow::Result<()> {
+    if settings.history_retention().as_secs() == 0 {
+        return Ok(());
+    }
+
+    get_audit_logs_from_ctx(ctx)
+        .await
+        .append(guild_id, &entry)
+        .await
+}
+
+/// Forget everything a user has had the bot say in a guild, returning how many entries were removed.
+pub async fn forget_user(ctx: &Context, guild_id: GuildId, user: UserId) -> anyhow::Result<usize> {
+    get_audit_logs_from_ctx(ctx)
+        .await
+        .retain(guild_id, |e| e.user != Some(user))
+        .await
+}
+
+/// Clears out audit log entries once they're past their guild's retention period.
+#[derive(Default)]
+pub struct AuditPruner {
+    started: AtomicBool,
+}
+
+#[async_trait]
+impl EventHandler for AuditPruner {
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        // ready fires again whenever we reconnect, but one loop is plenty.
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let settings = get_settings_from_ctx(&ctx).await;
+                let logs = get_audit_logs_from_ctx(&ctx).await;
+                for guild_id in ctx.cache.guilds() {
+                    let result = match settings.get(guild_id).await {
+                        Ok(s) => logs.prune(guild_id, &s).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        tracing::error!(?e, ?guild_id, "Could not prune audit log");
+                    }
+                }
+            }
+        });
+    }
+}
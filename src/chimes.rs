@@ -0,0 +1,64 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context as anyhowContext;
+use serenity::{client::Context, model::id::GuildId, prelude::TypeMapKey};
+
+use crate::audio::Pcm;
+
+pub struct Chimes;
+impl TypeMapKey for Chimes {
+    type Value = Arc<ChimeStore>;
+}
+
+/// The chimes guilds have uploaded to play before each utterance, as a WAV file each.
+pub struct ChimeStore {
+    directory: PathBuf,
+}
+
+impl ChimeStore {
+    pub fn new(directory: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Could not create chime directory {:?}", directory))?;
+        Ok(Self { directory })
+    }
+
+    fn path(&self, guild_id: GuildId) -> PathBuf {
+        self.directory
+            .join(guild_id.0.to_string())
+            .with_extension("wav")
+    }
+
+    /// The guild's chime, if it's uploaded one.
+    pub async fn get(&self, guild_id: GuildId) -> anyhow::Result<Option<Pcm>> {
+        let path = self.path(guild_id);
+        match tokio::fs::read(&path).await {
+            Ok(wav) => Pcm::from_wav(&wav).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Could not read chime at {:?}", path)),
+        }
+    }
+
+    pub async fn save(&self, guild_id: GuildId, chime: &Pcm) -> anyhow::Result<()> {
+        let path = self.path(guild_id);
+        let tmp_path = path.with_extension("wav.tmp");
+        tokio::fs::write(&tmp_path, chime.to_wav())
+            .await
+            .with_context(|| format!("Could not write chime to {:?}", tmp_path))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .with_context(|| format!("Could not move chime into place at {:?}", path))
+    }
+}
+
+pub async fn get_chimes_from_ctx(ctx: &Context) -> Arc<ChimeStore> {
+    ctx.data
+        .read()
+        .await
+        .get::<Chimes>()
+        .expect("Chime store should be present")
+        .clone()
+}
@@ -0,0 +1,115 @@
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        channel::ChannelType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{CommandResponse, Precondition, TugboatCommand};
+use crate::settings::get_settings_from_ctx;
+
+pub struct AutoreadCommand;
+
+#[async_trait]
+impl TugboatCommand for AutoreadCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No autoread subcommand given"))?;
+        let settings = get_settings_from_ctx(ctx).await;
+
+        match subcommand.name.as_str() {
+            "bind" => {
+                let channel = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "channel")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Channel(ref c)) => Some(c.id),
+                        _ => None,
+                    })
+                    .ok_or_else(|| anyhow!("channel option is required"))?;
+
+                settings
+                    .update(guild.id, |s| s.autoread_channel = Some(channel))
+                    .await?;
+
+                Ok(format!(
+                    "While I'm in a voice channel, I'll read out everything posted in <#{}>.",
+                    channel.0
+                )
+                .into())
+            }
+            "unbind" => {
+                let previous = settings
+                    .update(guild.id, |s| s.autoread_channel.take())
+                    .await?;
+
+                Ok(match previous {
+                    Some(c) => format!("I'll stop reading out <#{}>.", c.0).into(),
+                    None => "I wasn't reading out any channel.".into(),
+                })
+            }
+            other => Err(anyhow!("Unknown autoread subcommand {}", other)),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("autoread")
+            .description("Read out every message posted in a text channel")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("bind")
+                    .description("Start reading out a channel while the bot is in voice")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("channel")
+                            .description(
+                                "The channel to read out, can be a voice channel's text chat",
+                            )
+                            .kind(CommandOptionType::Channel)
+                            .channel_types(&[
+                                ChannelType::Text,
+                                ChannelType::Voice,
+                                ChannelType::Stage,
+                            ])
+                            .required(true)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("unbind")
+                    .description("Stop reading out messages")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("autoread")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::Permission(Permissions::MANAGE_CHANNELS),
+        ]
+    }
+}
@@ -0,0 +1,580 @@
+use std::convert::TryFrom;
+
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{get_string_option, CommandResponse, Precondition, TugboatCommand};
+use crate::{
+    audio::{earcon::Chime, Pcm},
+    audit::get_audit_logs_from_ctx,
+    chimes::get_chimes_from_ctx,
+    settings::get_settings_from_ctx,
+    text::normalize::NormalizationSettings,
+};
+
+/// Anything bigger than this is too long to be a chime anyway.
+const MAX_CHIME_BYTES: u64 = 1024 * 1024;
+const MAX_CHIME_SECONDS: f64 = 3.0;
+const MAX_GAP_MILLISECONDS: i64 = 5000;
+
+/// Every normalization step that can be toggled, with the name it's given in the command.
+const NORMALIZATION_STEPS: [(&str, &str); 5] = [
+    ("mentions", "Say names instead of mentions"),
+    ("emoji", "Describe emoji instead of reading them out"),
+    ("links", "Shorten links to the site they point to"),
+    ("markdown", "Strip formatting and skip code blocks"),
+    ("skip-spoilers", "Skip spoilers entirely"),
+];
+
+fn normalization_step<'a>(
+    settings: &'a mut NormalizationSettings,
+    step: &str,
+) -> Option<&'a mut bool> {
+    match step {
+        "mentions" => Some(&mut settings.mentions),
+        "emoji" => Some(&mut settings.emoji),
+        "links" => Some(&mut settings.links),
+        "markdown" => Some(&mut settings.markdown),
+        "skip-spoilers" => Some(&mut settings.skip_spoilers),
+        _ => None,
+    }
+}
+
+/// Parse a UTC offset like `+2`, `-05:30` or `UTC+10` into minutes.
+fn parse_utc_offset(input: &str) -> Option<i32> {
+    let input = input.trim();
+    let input = input
+        .strip_prefix("UTC")
+        .or_else(|| input.strip_prefix("utc"))
+        .unwrap_or(input)
+        .trim();
+    let (sign, rest) = match input.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, input.strip_prefix('+').unwrap_or(input)),
+    };
+    // the sign goes up front, so neither part gets one of its own.
+    let number = |s: &str| {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse::<i32>().ok()
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (number(h)?, number(m)?),
+        None => (number(rest)?, 0),
+    };
+
+    let offset = sign * (hours * 60 + minutes);
+    if minutes >= 60 || !(-12 * 60..=14 * 60).contains(&offset) {
+        return None;
+    }
+    Some(offset)
+}
+
+fn format_utc_offset(minutes: i32) -> String {
+    format!(
+        "UTC{}{:02}:{:02}",
+        if minutes < 0 { '-' } else { '+' },
+        minutes.abs() / 60,
+        minutes.abs() % 60
+    )
+}
+
+pub struct ConfigCommand;
+
+#[async_trait]
+impl TugboatCommand for ConfigCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No config subcommand given"))?;
+        let settings = get_settings_from_ctx(ctx).await;
+
+        match subcommand.name.as_str() {
+            "show" => {
+                let mut s = settings.get(guild.id).await?;
+                let mut lines = vec![
+                    format!(
+                        "Leave after being alone for: {} seconds",
+                        s.alone_timeout().as_secs()
+                    ),
+                    format!(
+                        "Keep the history of what I said for: {} days",
+                        s.history_retention().as_secs() / (24 * 60 * 60)
+                    ),
+                    format!("Time zone: {}", format_utc_offset(s.utc_offset_minutes)),
+                    if s.loudness.normalize {
+                        format!(
+                            "Loudness: normalized to {} LUFS, peaking at {} dBTP",
+                            s.loudness.target_lufs, s.loudness.true_peak_dbtp
+                        )
+                    } else {
+                        "Loudness: left as synthesized".to_owned()
+                    },
+                    format!(
+                        "Trim silence: {}",
+                        if s.loudness.trim_silence { "on" } else { "off" }
+                    ),
+                    format!("Chime: {}", s.earcon.chime.name()),
+                    format!(
+                        "Gap between utterances: {} milliseconds",
+                        s.earcon.gap_milliseconds
+                    ),
+                ];
+                for (step, description) in NORMALIZATION_STEPS.iter() {
+                    let enabled = normalization_step(&mut s.normalization, step) == Some(&mut true);
+                    lines.push(format!(
+                        "{} (`{}`): {}",
+                        description,
+                        step,
+                        if enabled { "on" } else { "off" }
+                    ));
+                }
+                Ok(lines.join("\n").into())
+            }
+            "history-retention" => {
+                let days = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "days")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Integer(i)) => u64::try_from(i).ok(),
+                        _ => None,
+                    })
+                    .ok_or_else(|| anyhow!("days option is required"))?;
+
+                settings
+                    .update(guild.id, |s| s.history_retention_days = Some(days))
+                    .await?;
+
+                if days == 0 {
+                    get_audit_logs_from_ctx(ctx)
+                        .await
+                        .retain(guild.id, |_| false)
+                        .await?;
+                }
+
+                Ok(if days == 0 {
+                    "I'll stop keeping a history of what I say, and forgot everything I'd kept so far.".to_owned()
+                } else {
+                    format!("I'll keep the history of what I say for {} days.", days)
+                }
+                .into())
+            }
+            "normalize" => {
+                let mut step = None;
+                let mut enabled = None;
+                for option in &subcommand.options {
+                    match (option.name.as_str(), &option.resolved) {
+                        ("step", Some(CommandDataOptionValue::String(s))) => step = Some(s.clone()),
+                        ("enabled", Some(CommandDataOptionValue::Boolean(b))) => enabled = Some(*b),
+                        _ => continue,
+                    }
+                }
+                let step = step.ok_or_else(|| anyhow!("step option is required"))?;
+                let enabled = enabled.ok_or_else(|| anyhow!("enabled option is required"))?;
+
+                let known = settings
+                    .update(guild.id, |s| {
+                        normalization_step(&mut s.normalization, &step)
+                            .map(|value| *value = enabled)
+                            .is_some()
+                    })
+                    .await?;
+                if !known {
+                    return Err(anyhow!("Unknown normalization step {}", step));
+                }
+
+                Ok(format!("Turned `{}` {}.", step, if enabled { "on" } else { "off" }).into())
+            }
+            "alone-timeout" => {
+                let seconds = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "seconds")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Integer(i)) => u64::try_from(i).ok(),
+                        _ => None,
+                    })
+                    .ok_or_else(|| anyhow!("seconds option is required"))?;
+
+                settings
+                    .update(guild.id, |s| s.alone_timeout_seconds = Some(seconds))
+                    .await?;
+
+                Ok(format!(
+                    "I'll now leave after being alone in a voice channel for {} seconds.",
+                    seconds
+                )
+                .into())
+            }
+            "loudness" => {
+                let mut target = None;
+                let mut true_peak = None;
+                let mut normalize = None;
+                let mut trim_silence = None;
+                for option in &subcommand.options {
+                    match (option.name.as_str(), &option.resolved) {
+                        ("target", Some(CommandDataOptionValue::Number(n))) => target = Some(*n),
+                        ("true-peak", Some(CommandDataOptionValue::Number(n))) => {
+                            true_peak = Some(*n)
+                        }
+                        ("normalize", Some(CommandDataOptionValue::Boolean(b))) => {
+                            normalize = Some(*b)
+                        }
+                        ("trim-silence", Some(CommandDataOptionValue::Boolean(b))) => {
+                            trim_silence = Some(*b)
+                        }
+                        _ => continue,
+                    }
+                }
+
+                let loudness = settings
+                    .update(guild.id, |s| {
+                        let loudness = &mut s.loudness;
+                        loudness.target_lufs = target.unwrap_or(loudness.target_lufs);
+                        loudness.true_peak_dbtp = true_peak.unwrap_or(loudness.true_peak_dbtp);
+                        loudness.normalize = normalize.unwrap_or(loudness.normalize);
+                        loudness.trim_silence = trim_silence.unwrap_or(loudness.trim_silence);
+                        loudness.clone()
+                    })
+                    .await?;
+
+                Ok(format!(
+                    "{}, and {} silence at the start and end.",
+                    if loudness.normalize {
+                        format!(
+                            "I'll normalize everything I say to {} LUFS, peaking at {} dBTP",
+                            loudness.target_lufs, loudness.true_peak_dbtp
+                        )
+                    } else {
+                        "I'll leave the loudness of what I say as it was synthesized".to_owned()
+                    },
+                    if loudness.trim_silence {
+                        "trim"
+                    } else {
+                        "keep"
+                    }
+                )
+                .into())
+            }
+            "chime" => {
+                let chimes = get_chimes_from_ctx(ctx).await;
+                let attachment = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "file")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Attachment(ref a)) => Some(a),
+                        _ => None,
+                    });
+                let mut chime =
+                    get_string_option(&subcommand.options, "sound").and_then(Chime::from_name);
+
+                if let Some(attachment) = attachment {
+                    if attachment.size > MAX_CHIME_BYTES {
+                        return Ok("That file is too big to be a chime.".into());
+                    }
+                    let contents = attachment
+                        .download()
+                        .await
+                        .context("Could not download chime")?;
+                    let pcm = match Pcm::from_wav(&contents) {
+                        Ok(pcm) => pcm,
+                        Err(_) => {
+                            return Ok(
+                                "Chimes need to be 16 bit WAV files with some sound in them."
+                                    .into(),
+                            )
+                        }
+                    };
+                    if pcm.duration().as_secs_f64() > MAX_CHIME_SECONDS {
+                        return Ok(format!(
+                            "Chimes can be at most {} seconds long.",
+                            MAX_CHIME_SECONDS
+                        )
+                        .into());
+                    }
+
+                    chimes.save(guild.id, &pcm).await?;
+                    chime = chime.or(Some(Chime::Custom));
+                } else if chime == Some(Chime::Custom) && chimes.get(guild.id).await?.is_none() {
+                    return Ok("Upload a WAV file with `file` to use a custom chime.".into());
+                }
+
+                let chime = match chime {
+                    Some(c) => c,
+                    None => {
+                        return Ok("Pick a sound, or upload a WAV file to use as the chime.".into())
+                    }
+                };
+                settings
+                    .update(guild.id, |s| s.earcon.chime = chime)
+                    .await?;
+
+                Ok(match chime {
+                    Chime::Off => "I won't play a chime before speaking.",
+                    Chime::Builtin => "I'll play my own chime before saying anything.",
+                    Chime::Custom => "I'll play this server's chime before saying anything.",
+                }
+                .into())
+            }
+            "gap" => {
+                let milliseconds = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "milliseconds")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Integer(i)) => u64::try_from(i).ok(),
+                        _ => None,
+                    })
+                    .ok_or_else(|| anyhow!("milliseconds option is required"))?;
+
+                settings
+                    .update(guild.id, |s| s.earcon.gap_milliseconds = milliseconds)
+                    .await?;
+
+                Ok(if milliseconds == 0 {
+                    "I'll say things back to back.".to_owned()
+                } else {
+                    format!(
+                        "I'll leave {} milliseconds between each thing I say.",
+                        milliseconds
+                    )
+                }
+                .into())
+            }
+            "utc-offset" => {
+                let offset = get_string_option(&subcommand.options, "offset")
+                    .ok_or_else(|| anyhow!("offset option is required"))?;
+                let minutes = match parse_utc_offset(offset) {
+                    Some(m) => m,
+                    None => return Ok(
+                        "That's not an offset I understand. Try something like `+2` or `-05:30`."
+                            .into(),
+                    ),
+                };
+
+                settings
+                    .update(guild.id, |s| s.utc_offset_minutes = minutes)
+                    .await?;
+
+                Ok(format!(
+                    "Times of day will now be in {}.",
+                    format_utc_offset(minutes)
+                )
+                .into())
+            }
+            other => Err(anyhow!("Unknown config subcommand {}", other)),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("config")
+            .description("Change how the bot behaves on this server")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("show")
+                    .description("Show the current configuration")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_sub_option(|s| {
+                s.name("alone-timeout")
+                    .description("How long to wait before leaving once everyone else has left the voice channel")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("seconds")
+                            .description("Grace period in seconds")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(0)
+                            .max_int_value(3600)
+                            .required(true)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("history-retention")
+                    .description("How long to keep the history of what the bot said")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("days")
+                            .description("Days to keep it for, 0 to keep no history")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(0)
+                            .max_int_value(365)
+                            .required(true)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("normalize")
+                    .description("Choose how messages are cleaned up before they're read out")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("step")
+                            .description("The step to turn on or off")
+                            .kind(CommandOptionType::String)
+                            .required(true);
+                        for (step, description) in NORMALIZATION_STEPS.iter() {
+                            o.add_string_choice(description, step);
+                        }
+                        o
+                    })
+                    .create_sub_option(|o| {
+                        o.name("enabled")
+                            .description("Whether the step should be applied")
+                            .kind(CommandOptionType::Boolean)
+                            .required(true)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("loudness")
+                    .description("Even out how loud everything the bot says is. Anything left out stays as it is")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("target")
+                            .description("The loudness to aim for in LUFS (default -18)")
+                            .kind(CommandOptionType::Number)
+                            .min_number_value(-40.0)
+                            .max_number_value(-6.0)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("true-peak")
+                            .description("The loudest any peak can get in dBTP (default -1)")
+                            .kind(CommandOptionType::Number)
+                            .min_number_value(-12.0)
+                            .max_number_value(0.0)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("normalize")
+                            .description("Whether to normalize loudness at all")
+                            .kind(CommandOptionType::Boolean)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("trim-silence")
+                            .description("Whether to cut silence off the start and end of what's said")
+                            .kind(CommandOptionType::Boolean)
+                            .required(false)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("chime")
+                    .description("Play a short chime before everything the bot says")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("sound")
+                            .description("Which chime to play")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                            .add_string_choice("None", Chime::Off.name())
+                            .add_string_choice("Built in", Chime::Builtin.name())
+                            .add_string_choice("Uploaded", Chime::Custom.name())
+                    })
+                    .create_sub_option(|o| {
+                        o.name("file")
+                            .description("A short 16 bit WAV file to use as this server's chime")
+                            .kind(CommandOptionType::Attachment)
+                            .required(false)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("gap")
+                    .description("Leave some silence between each thing the bot says")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("milliseconds")
+                            .description("How long the gap should be, 0 for none")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(0)
+                            .max_int_value(MAX_GAP_MILLISECONDS)
+                            .required(true)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("utc-offset")
+                    .description("Set the server's time zone, for scheduling things at a time of day")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("offset")
+                            .description("The offset from UTC, e.g. `+2` or `-05:30`")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("config")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::Permission(Permissions::MANAGE_GUILD),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_utc_offsets() {
+        let cases = [
+            ("+2", Some(120)),
+            ("2", Some(120)),
+            ("-05:30", Some(-330)),
+            ("UTC+10", Some(600)),
+            ("utc-3:45", Some(-225)),
+            (" UTC 0 ", Some(0)),
+            ("+14", Some(14 * 60)),
+            ("-12:00", Some(-12 * 60)),
+            ("+14:30", None),
+            ("-13", None),
+            ("5:-30", None),
+            ("5:+30", None),
+            ("--5", None),
+            ("+-5", None),
+            ("5:60", None),
+            ("5:", None),
+            ("", None),
+            ("UTC", None),
+            ("five", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_utc_offset(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn formats_utc_offsets_back() {
+        for minutes in [0, 120, -330, 14 * 60, -45] {
+            assert_eq!(parse_utc_offset(&format_utc_offset(minutes)), Some(minutes));
+        }
+        assert_eq!(format_utc_offset(-330), "UTC-05:30");
+    }
+}
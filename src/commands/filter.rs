@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        channel::ChannelType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{get_string_option, CommandResponse, Precondition, TugboatCommand};
+use crate::{
+    settings::get_settings_from_ctx,
+    text::filter::{FilterAction, FilterRule},
+};
+
+fn describe(rule: &FilterRule) -> String {
+    let kind = if rule.is_regex { "regex" } else { "word" };
+    match (rule.action, rule.replacement.as_deref()) {
+        (FilterAction::Replace, Some(replacement)) => format!(
+            "{} `{}`: replace with \"{}\"",
+            kind, rule.pattern, replacement
+        ),
+        (action, _) => format!("{} `{}`: {}", kind, rule.pattern, action.name()),
+    }
+}
+
+pub struct FilterCommand;
+
+#[async_trait]
+impl TugboatCommand for FilterCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No filter subcommand given"))?;
+        let settings = get_settings_from_ctx(ctx).await;
+
+        match subcommand.name.as_str() {
+            "add" => {
+                let pattern = get_string_option(&subcommand.options, "pattern")
+                    .ok_or_else(|| anyhow!("pattern option is required"))?
+                    .trim();
+                let action = get_string_option(&subcommand.options, "action")
+                    .and_then(FilterAction::from_name)
+                    .ok_or_else(|| anyhow!("action option is required"))?;
+                let is_regex = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "regex")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Boolean(b)) => Some(b),
+                        _ => None,
+                    })
+                    .unwrap_or(false);
+                let replacement = get_string_option(&subcommand.options, "replacement")
+                    .map(|r| r.trim().to_owned());
+
+                if pattern.is_empty() {
+                    return Ok("The pattern can't be empty.".into());
+                }
+                if action == FilterAction::Replace && replacement.is_none() {
+                    return Ok("Tell me what to say instead with the `replacement` option.".into());
+                }
+
+                let rule = FilterRule {
+                    pattern: pattern.to_owned(),
+                    is_regex,
+                    action,
+                    replacement: replacement.filter(|_| action == FilterAction::Replace),
+                };
+                if let Err(e) = rule.compile() {
+                    return Ok(format!("That isn't a pattern I can use: {}", e).into());
+                }
+
+                let description = describe(&rule);
+                settings
+                    .update(guild.id, |s| {
+                        // adding the same pattern again changes what happens to it.
+                        s.filter.rules.retain(|r| r.pattern != rule.pattern);
+                        s.filter.rules.push(rule);
+                    })
+                    .await?;
+
+                Ok(CommandResponse::Ephemeral(format!(
+                    "Added to the filter: {}",
+                    description
+                )))
+            }
+            "remove" => {
+                let pattern = get_string_option(&subcommand.options, "pattern")
+                    .ok_or_else(|| anyhow!("pattern option is required"))?
+                    .trim()
+                    .to_owned();
+                let removed = settings
+                    .update(guild.id, |s| {
+                        let before = s.filter.rules.len();
+                        s.filter.rules.retain(|r| r.pattern != pattern);
+                        before != s.filter.rules.len()
+                    })
+                    .await?;
+
+                Ok(CommandResponse::Ephemeral(if removed {
+                    format!("Removed `{}` from the filter.", pattern)
+                } else {
+                    format!("`{}` isn't in the filter.", pattern)
+                }))
+            }
+            "list" => {
+                let s = settings.get(guild.id).await?;
+                let mut lines = s.filter.rules.iter().map(describe).collect::<Vec<_>>();
+                if lines.is_empty() {
+                    lines.push("Nothing is being filtered.".into());
+                }
+                lines.push(match s.filter.moderation_channel {
+                    Some(c) => format!("Blocked attempts are reported in <#{}>.", c.0),
+                    None => "Blocked attempts aren't reported anywhere.".into(),
+                });
+
+                // the list is everything people on the server aren't supposed to hear.
+                Ok(CommandResponse::Ephemeral(lines.join("\n")))
+            }
+            "log-channel" => {
+                let channel = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "channel")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Channel(ref c)) => Some(c.id),
+                        _ => None,
+                    });
+
+                settings
+                    .update(guild.id, |s| s.filter.moderation_channel = channel)
+                    .await?;
+
+                Ok(match channel {
+                    Some(c) => format!("I'll report blocked attempts in <#{}>.", c.0),
+                    None => "I'll stop reporting blocked attempts.".into(),
+                }
+                .into())
+            }
+            other => Err(anyhow!("Unknown filter subcommand {}", other)),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("filter")
+            .description("Keep the bot from saying certain things on this server")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("add")
+                    .description("Add a word or pattern to the filter")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("pattern")
+                            .description("The word to filter, matched regardless of case")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("action")
+                            .description("What to do when it comes up")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                            .add_string_choice("Beep it out", "beep")
+                            .add_string_choice("Don't say the message at all", "drop")
+                            .add_string_choice("Say something else instead", "replace")
+                    })
+                    .create_sub_option(|o| {
+                        o.name("regex")
+                            .description("Treat the pattern as a regular expression (default off)")
+                            .kind(CommandOptionType::Boolean)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("replacement")
+                            .description("What to say instead, when replacing")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("remove")
+                    .description("Remove a pattern from the filter")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("pattern")
+                            .description("The pattern exactly as it was added")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("list")
+                    .description("Show everything that's being filtered")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_sub_option(|s| {
+                s.name("log-channel")
+                    .description("Where to report blocked attempts, leave empty to stop reporting")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("channel")
+                            .description("The channel to report in")
+                            .kind(CommandOptionType::Channel)
+                            .channel_types(&[ChannelType::Text])
+                            .required(false)
+                    })
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("filter")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::Permission(Permissions::MANAGE_MESSAGES),
+        ]
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{CommandResponse, Precondition, TugboatCommand};
+use crate::session::get_sessions_from_ctx;
+
+pub struct FollowCommand;
+
+#[async_trait]
+impl TugboatCommand for FollowCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let enabled = options
+            .iter()
+            .find(|o| o.name == "enabled")
+            .and_then(|o| match o.resolved {
+                Some(CommandDataOptionValue::Boolean(b)) => Some(b),
+                _ => None,
+            })
+            .unwrap_or(true);
+
+        let session = match get_sessions_from_ctx(ctx).await.get(guild.id).await {
+            Some(s) => s,
+            None => return Ok("I'm not in a voice channel right now.".into()),
+        };
+        session.set_follow_summoner(enabled);
+
+        let summoner = session.summoner();
+        let name = match guild.members.get(&summoner) {
+            Some(m) => m.display_name().into_owned(),
+            None => "whoever brought me here".into(),
+        };
+
+        if enabled {
+            Ok(format!("I'll follow {} when they switch voice channels.", name).into())
+        } else {
+            Ok(format!("I'll stay put when {} switches voice channels.", name).into())
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("follow")
+            .description("Follow whoever brought the bot in when they switch voice channels")
+            .kind(CommandOptionType::SubCommand)
+            .create_sub_option(|o| {
+                o.name("enabled")
+                    .description("Turn follow mode on or off (default on)")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("follow")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::UserInVoice,
+            Precondition::BotInSameChannel,
+        ]
+    }
+}
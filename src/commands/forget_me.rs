@@ -0,0 +1,52 @@
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType, guild::Guild,
+        prelude::interaction::application_command::CommandDataOption, user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{CommandResponse, Precondition, TugboatCommand};
+use crate::audit::forget_user;
+
+pub struct ForgetMeCommand;
+
+#[async_trait]
+impl TugboatCommand for ForgetMeCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        _options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let removed = forget_user(ctx, guild.id, user.id).await?;
+
+        Ok(CommandResponse::Ephemeral(match removed {
+            0 => "I don't have any record of you on this server.".to_owned(),
+            n => format!("Deleted {} records of things you had me say.", n),
+        }))
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("forget-me")
+            .description("Delete the record of everything you've had the bot say on this server")
+            .kind(CommandOptionType::SubCommand)
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("forget-me")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+}
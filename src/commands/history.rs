@@ -0,0 +1,253 @@
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        id::UserId,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{get_string_option, CommandResponse, Precondition, TugboatCommand};
+use crate::{
+    audit::{get_audit_logs_from_ctx, AuditEntry},
+    settings::get_settings_from_ctx,
+    text::normalize::user_name,
+};
+
+const DEFAULT_SEARCH_RESULTS: usize = 10;
+/// Leave some room under Discord's 2000 character limit.
+const MAX_SEARCH_LENGTH: usize = 1800;
+/// How much of each entry's text to show in search results.
+const MAX_ENTRY_PREVIEW: usize = 150;
+
+/// Which entries a moderator is interested in.
+struct Query {
+    user: Option<UserId>,
+    /// Lowercased text to look for.
+    contains: Option<String>,
+}
+
+impl Query {
+    fn from_options(options: &[CommandDataOption]) -> Self {
+        let user = options
+            .iter()
+            .find(|o| o.name == "user")
+            .and_then(|o| match o.resolved {
+                Some(CommandDataOptionValue::User(ref u, _)) => Some(u.id),
+                _ => None,
+            });
+        let contains = get_string_option(options, "contains").map(|c| c.to_lowercase());
+
+        Self { user, contains }
+    }
+
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        if self.user.is_some() && entry.user != self.user {
+            return false;
+        }
+        match self.contains {
+            Some(ref c) => entry.text.to_lowercase().contains(c),
+            None => true,
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+fn to_csv(entries: &[&AuditEntry]) -> String {
+    let mut csv = String::from("timestamp,guild_id,user_id,channel_id,voice,text\n");
+    for e in entries {
+        let fields = [
+            e.timestamp.to_string(),
+            e.guild_id.0.to_string(),
+            e.user.map(|u| u.0.to_string()).unwrap_or_default(),
+            e.channel.map(|c| c.0.to_string()).unwrap_or_default(),
+            e.voice.clone(),
+            e.text.clone(),
+        ];
+        let line = fields
+            .iter()
+            .map(|f| csv_field(f))
+            .collect::<Vec<_>>()
+            .join(",");
+        csv.push_str(&line);
+        csv.push('\n');
+    }
+    csv
+}
+
+pub struct HistoryCommand;
+
+#[async_trait]
+impl TugboatCommand for HistoryCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No history subcommand given"))?;
+
+        let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+        let log = get_audit_logs_from_ctx(ctx).await.get(guild.id).await?;
+        let query = Query::from_options(&subcommand.options);
+        let entries = log
+            .recent(&settings)
+            .filter(|e| query.matches(e))
+            .collect::<Vec<_>>();
+
+        match subcommand.name.as_str() {
+            "search" => {
+                if entries.is_empty() {
+                    return Ok(CommandResponse::Ephemeral(
+                        "I haven't said anything like that.".into(),
+                    ));
+                }
+
+                let limit = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "limit")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Integer(i)) => Some(i as usize),
+                        _ => None,
+                    })
+                    .unwrap_or(DEFAULT_SEARCH_RESULTS);
+
+                let mut response = String::new();
+                for e in entries.iter().take(limit) {
+                    let who = e
+                        .user
+                        .map(|u| user_name(ctx, Some(&guild), u))
+                        .unwrap_or_else(|| "Nobody in particular".into());
+                    let mut preview = e.text.chars().take(MAX_ENTRY_PREVIEW).collect::<String>();
+                    if preview.len() < e.text.len() {
+                        preview.push('…');
+                    }
+                    let line = format!(
+                        "<t:{}:f> **{}**{}: {}\n",
+                        e.timestamp.unix_timestamp(),
+                        who,
+                        e.channel
+                            .map(|c| format!(" in <#{}>", c.0))
+                            .unwrap_or_default(),
+                        preview
+                    );
+
+                    if response.len() + line.len() > MAX_SEARCH_LENGTH {
+                        break;
+                    }
+                    response.push_str(&line);
+                }
+
+                Ok(CommandResponse::Ephemeral(response))
+            }
+            "export" => {
+                let format = get_string_option(&subcommand.options, "format").unwrap_or("csv");
+                let (filename, data) = match format {
+                    "json" => (
+                        "history.json",
+                        serde_json::to_vec_pretty(&entries)
+                            .context("Could not serialize history")?,
+                    ),
+                    _ => ("history.csv", to_csv(&entries).into_bytes()),
+                };
+
+                Ok(CommandResponse::File {
+                    content: format!("Here are {} things I've said.", entries.len()),
+                    filename: filename.into(),
+                    data,
+                    ephemeral: true,
+                })
+            }
+            other => Err(anyhow!("Unknown history subcommand {}", other)),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("history")
+            .description("Look up who made the bot say what")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("search")
+                    .description("Show the most recent things the bot said")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("user")
+                            .description("Only show what this user had the bot say")
+                            .kind(CommandOptionType::User)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("contains")
+                            .description("Only show messages containing this text")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("limit")
+                            .description("How many results to show (default 10)")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .max_int_value(25)
+                            .required(false)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("export")
+                    .description("Download the history as a file")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("format")
+                            .description("The file format (default CSV)")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                            .add_string_choice("CSV", "csv")
+                            .add_string_choice("JSON", "json")
+                    })
+                    .create_sub_option(|o| {
+                        o.name("user")
+                            .description("Only export what this user had the bot say")
+                            .kind(CommandOptionType::User)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("contains")
+                            .description("Only export messages containing this text")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("history")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::Permission(Permissions::MANAGE_MESSAGES),
+        ]
+    }
+}
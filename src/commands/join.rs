@@ -1,20 +1,41 @@
-use std::{sync::atomic::Ordering, time::Duration};
+use std::sync::Arc;
 
-use anyhow::anyhow;
+use anyhow::Context as anyhowContext;
 use serenity::async_trait;
 
 use serenity::{
     builder::CreateApplicationCommandOption,
     client::Context,
     model::{
-        application::command::CommandOptionType, guild::Guild,
-        prelude::interaction::application_command::CommandDataOption,
+        application::command::CommandOptionType,
+        channel::{Channel, ChannelType},
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
     },
 };
-use songbird::{id::ChannelId, Event};
+use songbird::id::ChannelId;
 
-use super::TugboatCommand;
-use crate::commands::{get_songbird_from_ctx, IdleDurationTracker, IdleDurations};
+use super::{CommandResponse, Precondition, TugboatCommand};
+use crate::session::{get_sessions_from_ctx, GuildSession};
+
+/// Get the session for this guild, joining `channel_id` on behalf of `user` first if we
+/// aren't connected yet. Returns `None` if we couldn't join.
+pub(crate) async fn join_if_needed(
+    ctx: &Context,
+    guild: &Guild,
+    channel_id: ChannelId,
+    user: &User,
+) -> anyhow::Result<Option<Arc<GuildSession>>> {
+    let sessions = get_sessions_from_ctx(ctx).await;
+    if sessions.get(guild.id).await.is_none() {
+        JoinCommand
+            .execute(ctx, &[], Some(guild.clone()), Some(channel_id), user)
+            .await?;
+    }
+
+    Ok(sessions.get(guild.id).await)
+}
 
 pub struct JoinCommand;
 
@@ -23,61 +44,102 @@ impl TugboatCommand for JoinCommand {
     async fn execute(
         &self,
         ctx: &Context,
-        _options: &[CommandDataOption],
-        guild: Guild,
-        channel_id: ChannelId,
-    ) -> anyhow::Result<String> {
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let channel_id = channel_id.context("Voice channel precondition not met")?;
         tracing::debug!(guild=?guild.id, ?channel_id, "Attempting to join voice channel");
 
-        let manager = get_songbird_from_ctx(ctx).await;
-        let (handle_lock, success) = manager.join(guild.id, channel_id).await;
-
-        if success.is_ok() {
-            tracing::trace!("Join complete");
-            // create the duration tracking atomic usize
-            let duration_tracking = {
-                let mut data = ctx.data.write().await;
-                let durations = data
-                    .get_mut::<IdleDurations>()
-                    .expect("join durations hashmap should be here");
-                let d = durations
-                    .entry(songbird::id::GuildId::from(guild.id))
-                    .or_default();
-                d.store(0, Ordering::SeqCst);
-                tracing::trace!("Created durationg tracking atomic usize");
-                d.clone()
-            };
+        let sessions = get_sessions_from_ctx(ctx).await;
+        if let Some(session) = sessions.get(guild.id).await {
+            match session.current_channel().await {
+                Some(c) if c == channel_id => return Ok("I'm already here!".into()),
+                Some(c) => {
+                    return Ok(
+                        format!("I'm already in <#{}>. Use `move` to bring me over.", c.0).into(),
+                    )
+                }
+                None => {}
+            }
+        }
 
+        // the option is restricted to voice-ish channels on Discord's side, but double check.
+        let channel = match guild.channels.get(&channel_id.0.into()) {
+            Some(Channel::Guild(c))
+                if matches!(c.kind, ChannelType::Voice | ChannelType::Stage) =>
             {
-                let mut handle = handle_lock.lock().await;
-
-                handle.add_global_event(
-                    Event::Periodic(Duration::from_secs(60), None),
-                    IdleDurationTracker::new(
-                        duration_tracking,
-                        manager.clone(),
-                        songbird::id::GuildId::from(guild.id),
-                    ),
-                );
-                tracing::trace!("Created timer to count down 10 minutes");
+                c.clone()
             }
+            _ => return Ok("That's not a voice channel I can join.".into()),
+        };
 
-            tracing::trace!("Returning success");
-            Ok("Joined voice channel".into())
-        } else {
-            Err(anyhow!("Could not join voice channel."))
+        // anyone can bring us to where they are, but not to somewhere they couldn't go themselves.
+        if self.requested_channel(options).is_some() {
+            let member = guild.member(ctx, user.id).await?;
+            let can_connect = guild
+                .user_permissions_in(&channel, &member)
+                .is_ok_and(|p| p.connect());
+            if !can_connect {
+                return Ok(CommandResponse::Ephemeral(format!(
+                    "You can't join <#{}> yourself, so I won't go there for you.",
+                    channel_id.0
+                )));
+            }
         }
+
+        sessions.join(guild.id, channel_id, user.id).await?;
+
+        // on a stage we start out in the audience, so try to get ourselves up on stage.
+        if channel.kind == ChannelType::Stage {
+            if let Err(e) = channel
+                .edit_own_voice_state(&ctx.http, |s| s.suppress(false))
+                .await
+            {
+                tracing::warn!(?e, ?channel_id, "Could not become a stage speaker");
+                return Ok(
+                    "Joined the stage, but I couldn't make myself a speaker. A stage moderator will need to invite me up."
+                        .into(),
+                );
+            }
+        }
+
+        tracing::trace!("Returning success");
+        Ok("Joined voice channel".into())
+    }
+
+    fn requested_channel(&self, options: &[CommandDataOption]) -> Option<ChannelId> {
+        options
+            .iter()
+            .find(|o| o.name == "channel")
+            .and_then(|o| match o.resolved {
+                Some(CommandDataOptionValue::Channel(ref c)) => Some(ChannelId::from(c.id)),
+                _ => None,
+            })
     }
 
     fn create_command(&self) -> CreateApplicationCommandOption {
         CreateApplicationCommandOption::default()
             .name("join")
-            .description("Join your current voice channel")
+            .description("Join your current voice channel, or another one")
             .kind(CommandOptionType::SubCommand)
+            .create_sub_option(|o| {
+                o.name("channel")
+                    .description("The channel to join, if not the one you're in")
+                    .kind(CommandOptionType::Channel)
+                    .channel_types(&[ChannelType::Voice, ChannelType::Stage])
+                    .required(false)
+            })
             .clone()
     }
 
     fn get_name(&self) -> String {
         String::from("join")
     }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild, Precondition::UserInVoice]
+    }
 }
@@ -4,12 +4,12 @@ use serenity::{
     client::Context,
     model::{
         application::command::CommandOptionType, guild::Guild,
-        prelude::interaction::application_command::CommandDataOption,
+        prelude::interaction::application_command::CommandDataOption, user::User,
     },
 };
 use songbird::id::ChannelId;
 
-use super::say::Voices;
+use super::{say::Voices, CommandResponse, Precondition};
 
 pub struct LanguagesCommand;
 
@@ -19,9 +19,10 @@ impl super::TugboatCommand for LanguagesCommand {
         &self,
         ctx: &Context,
         _options: &[CommandDataOption],
-        _guild: Guild,
-        _channel_id: ChannelId,
-    ) -> anyhow::Result<String> {
+        _guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
         let data = ctx.data.read().await;
 
         let voices = data
@@ -34,7 +35,7 @@ impl super::TugboatCommand for LanguagesCommand {
         let mut res = format!("{} languages available:\n", voices.len());
         res.push_str(&voices.join(", "));
 
-        Ok(res)
+        Ok(res.into())
     }
 
     fn create_command(&self) -> CreateApplicationCommandOption {
@@ -48,4 +49,8 @@ impl super::TugboatCommand for LanguagesCommand {
     fn get_name(&self) -> String {
         String::from("languages")
     }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![]
+    }
 }
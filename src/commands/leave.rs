@@ -1,25 +1,18 @@
-use std::sync::Arc;
-
+use anyhow::Context as anyhowContext;
 use serenity::{
     async_trait,
     builder::CreateApplicationCommandOption,
     client::Context,
     model::{
         application::command::CommandOptionType, guild::Guild,
-        prelude::interaction::application_command::CommandDataOption,
+        prelude::interaction::application_command::CommandDataOption, user::User,
     },
 };
-use songbird::{
-    error::JoinResult,
-    id::{ChannelId, GuildId},
-    Songbird,
-};
+use songbird::id::ChannelId;
 
-use crate::commands::{get_songbird_from_ctx, NOT_IN_SAME_VOICE_CHANNEL_MESSAGE};
+use super::{CommandResponse, Precondition};
 
-pub(super) async fn do_leave(manager: Arc<Songbird>, guild_id: GuildId) -> JoinResult<()> {
-    manager.remove(guild_id).await
-}
+use crate::session::get_sessions_from_ctx;
 
 pub struct LeaveCommand;
 
@@ -29,30 +22,18 @@ impl super::TugboatCommand for LeaveCommand {
         &self,
         ctx: &Context,
         _options: &[CommandDataOption],
-        guild: Guild,
-        channel_id: ChannelId,
-    ) -> anyhow::Result<String> {
-        let manager = get_songbird_from_ctx(ctx).await;
-        match manager.get(guild.id) {
-            Some(call) => {
-                // we are in a call right now, check to make sure the user is in the same channel.
-                let call_channel = {
-                    let r = call.lock().await;
-                    r.current_channel().expect("There should be a channel here")
-                };
-
-                if channel_id != call_channel {
-                    return Ok(NOT_IN_SAME_VOICE_CHANNEL_MESSAGE.into());
-                }
-            }
-            None => {
-                return Ok("I'm not in a voice channel.".into());
-            }
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let sessions = get_sessions_from_ctx(ctx).await;
+
+        // our preconditions have already made sure the user is in the same channel as us.
+        if !sessions.leave(guild.id).await? {
+            return Ok("I'm not in a voice channel.".into());
         }
 
-        // if we've made it past all the checks, we are clear to remove ourselves from the channel.
-        do_leave(manager, songbird::id::GuildId::from(guild.id)).await?;
-
         Ok("Left voice channel".into())
     }
 
@@ -67,4 +48,12 @@ impl super::TugboatCommand for LeaveCommand {
     fn get_name(&self) -> String {
         String::from("leave")
     }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::UserInVoice,
+            Precondition::BotInSameChannel,
+        ]
+    }
 }
@@ -1,103 +1,231 @@
 use anyhow::anyhow;
+use serde_json::Value;
 use serenity::{
     async_trait,
-    builder::CreateApplicationCommandOption,
+    builder::{
+        CreateApplicationCommandOption, CreateComponents, CreateInteractionResponse,
+        EditInteractionResponse,
+    },
     client::{Context, EventHandler},
     http::Http,
     model::{
         application::{
-            command::Command,
+            command::{Command, CommandType},
+            component::ActionRowComponent,
             interaction::{
-                application_command::{ApplicationCommandInteraction, CommandDataOption},
+                application_command::{
+                    ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue,
+                    ResolvedTarget,
+                },
+                autocomplete::AutocompleteInteraction,
+                message_component::MessageComponentInteraction,
+                modal::ModalSubmitInteraction,
                 Interaction,
             },
         },
-        guild::Guild,
+        channel::{AttachmentType, Message},
+        guild::{Guild, Member},
         id::GuildId as SerenityGuildId,
         prelude::{interaction::InteractionResponseType, Ready, User},
+        Permissions,
     },
     prelude::TypeMapKey,
 };
 use songbird::{
     events::EventHandler as VoiceEventHandler,
-    id::{ChannelId, GuildId},
-    Event, EventContext, Songbird,
-};
-use std::{
-    collections::HashMap,
-    str::FromStr,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
-    vec,
+    id::ChannelId,
+    tracks::{PlayMode, TrackHandle},
+    Event, EventContext, TrackEvent,
 };
+use std::{borrow::Cow, collections::HashMap, str::FromStr, sync::Arc, vec};
 
-use crate::commands::leave::do_leave;
+use crate::{
+    commands::permissions::{check_command_permissions, is_guild_admin, PermissionCheck},
+    session::get_sessions_from_ctx,
+};
 
+pub(crate) mod autoread;
+pub(crate) mod config;
+pub(crate) mod filter;
+pub(crate) mod follow;
+pub(crate) mod forget_me;
+pub(crate) mod history;
 pub mod join;
 pub(crate) mod languages;
 pub(crate) mod leave;
+pub(crate) mod move_channel;
+pub(crate) mod permissions;
+pub(crate) mod persona;
+pub(crate) mod phrase;
+pub(crate) mod pronounce;
+pub(crate) mod read_aloud;
+pub(crate) mod recent;
+pub(crate) mod render;
+pub(crate) mod replay;
 pub mod say;
+pub(crate) mod say_long;
+pub(crate) mod schedule;
 pub(crate) mod skip;
+pub(crate) mod speak_for_me;
+pub(crate) mod timer;
+pub(crate) mod voice;
 
+/// Discord won't show any more suggestions than this.
+const MAX_AUTOCOMPLETE_CHOICES: usize = 25;
+const NOT_IN_GUILD_MESSAGE: &str = "Can't call this from a non-guild context";
 const NOT_IN_VOICE_CHANNEL_MESSAGE: &str =
     "Can't tell me what to do if you're not in a voice channel!";
 const NOT_IN_SAME_VOICE_CHANNEL_MESSAGE: &str =
     "Can't tell me what to do if you're not in the same voice channel!";
 
-pub struct IdleDurations;
-impl TypeMapKey for IdleDurations {
-    type Value = HashMap<GuildId, Arc<AtomicUsize>>;
+fn get_voice_channel_by_user(guild: &Guild, user: &User) -> Option<ChannelId> {
+    guild
+        .voice_states
+        .get(&user.id)
+        .and_then(|vs| vs.channel_id)
+        .map(ChannelId::from)
+}
+
+/// The value of a string option, if it was given.
+fn get_string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match o.resolved {
+            Some(CommandDataOptionValue::String(ref s)) => Some(s.as_str()),
+            _ => None,
+        })
+}
+
+/// The option the user is typing in during autocomplete, along with what they've typed so far.
+/// Options aren't resolved during autocomplete, so this goes by the raw value.
+fn get_focused_option(options: &[CommandDataOption]) -> Option<(&str, &str)> {
+    options.iter().find(|o| o.focused).map(|o| match o.value {
+        Some(Value::String(ref s)) => (o.name.as_str(), s.as_str()),
+        _ => (o.name.as_str(), ""),
+    })
 }
 
-struct IdleDurationTracker {
-    idle_count_minutes: Arc<AtomicUsize>,
-    manager: Arc<Songbird>,
-    guild_id: GuildId,
+/// Things that have to be true before a command is allowed to run. These are checked
+/// centrally, in the order they're declared, before `execute` is ever called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precondition {
+    /// The command has to be run from within a guild.
+    Guild,
+    /// The invoking user has to be in a voice channel, or have explicitly named one.
+    UserInVoice,
+    /// If the bot is in a voice channel, the invoking user has to be in that same channel.
+    BotInSameChannel,
+    /// The invoking member needs these Discord permissions.
+    Permission(Permissions),
 }
 
-impl IdleDurationTracker {
-    pub fn new(u: Arc<AtomicUsize>, manager: Arc<Songbird>, guild_id: GuildId) -> Self {
-        Self {
-            idle_count_minutes: u,
-            manager,
-            guild_id,
+/// Evaluate a command's preconditions, along with the guild's configured command permissions.
+/// Returns the reason the command can't be run, if it can't.
+async fn check_preconditions(
+    ctx: &Context,
+    name: &str,
+    preconditions: Vec<Precondition>,
+    guild: Option<&Guild>,
+    channel_id: Option<ChannelId>,
+    member: Option<&Member>,
+) -> anyhow::Result<Option<String>> {
+    // everything other than the bare minimum needs a guild to make sense.
+    let guild = match guild {
+        Some(g) => g,
+        None if preconditions.is_empty() => return Ok(None),
+        None => return Ok(Some(NOT_IN_GUILD_MESSAGE.into())),
+    };
+
+    // interactions from within a guild always come with a member attached.
+    let member = member.ok_or_else(|| anyhow!("Guild interaction without a member"))?;
+    if let PermissionCheck::Denied(reason) =
+        check_command_permissions(ctx, guild.id, name, member).await?
+    {
+        return Ok(Some(reason));
+    }
+
+    for precondition in preconditions {
+        match precondition {
+            Precondition::Guild => {}
+            Precondition::UserInVoice => {
+                if channel_id.is_none() {
+                    return Ok(Some(NOT_IN_VOICE_CHANNEL_MESSAGE.into()));
+                }
+            }
+            Precondition::BotInSameChannel => {
+                if let Some(session) = get_sessions_from_ctx(ctx).await.get(guild.id).await {
+                    if session.current_channel().await != channel_id {
+                        return Ok(Some(NOT_IN_SAME_VOICE_CHANNEL_MESSAGE.into()));
+                    }
+                }
+            }
+            Precondition::Permission(required) => {
+                let has = member.permissions.unwrap_or_else(Permissions::empty);
+                if !is_guild_admin(member) && !has.contains(required) {
+                    return Ok(Some(format!(
+                        "You need the {} permission to use `{}`.",
+                        required.get_permission_names().join(", "),
+                        name
+                    )));
+                }
+            }
         }
     }
+
+    Ok(None)
 }
 
-#[async_trait]
-impl VoiceEventHandler for IdleDurationTracker {
-    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
-        let idle_minutes = self.idle_count_minutes.fetch_add(1, Ordering::Relaxed) + 1;
-        tracing::debug!("Idle in voice channel for {} minutes!", idle_minutes);
-
-        // if we've been idle in the channel for 10 minutes, leave.
-        if idle_minutes >= 10 {
-            tracing::info!(
-                "Idle for 10+ minutes in guild {:?}, leaving!",
-                self.guild_id
-            );
-            let _ = do_leave(self.manager.clone(), self.guild_id).await;
-        }
+/// What a command sends back to whoever ran it.
+#[derive(Debug)]
+pub enum CommandResponse {
+    /// A plain message.
+    Message(String),
+    /// A message only the user who ran the command can see.
+    Ephemeral(String),
+    /// A message with buttons attached. Clicks are handed to [`TugboatCommand::component`].
+    Interactive {
+        content: String,
+        components: CreateComponents,
+        /// A button to grey out once this track has finished playing, by its custom ID.
+        disable_after: Option<(TrackHandle, String)>,
+    },
+    /// A message with a file attached.
+    File {
+        content: String,
+        filename: String,
+        data: Vec<u8>,
+        /// Whether only the user who ran the command should see it.
+        ephemeral: bool,
+    },
+    /// A modal for the user to fill in. Its submission is handed to [`TugboatCommand::submit`].
+    Modal {
+        custom_id: String,
+        title: String,
+        components: CreateComponents,
+    },
+    /// Grey out the button that was clicked, leaving the rest of its message as it was.
+    /// Only makes sense coming from [`TugboatCommand::component`].
+    DisableButton,
+}
 
-        None
+impl From<String> for CommandResponse {
+    fn from(s: String) -> Self {
+        Self::Message(s)
     }
 }
 
-async fn get_songbird_from_ctx(ctx: &Context) -> Arc<Songbird> {
-    songbird::get(ctx)
-        .await
-        .expect("Songbird context should be present")
+impl From<&str> for CommandResponse {
+    fn from(s: &str) -> Self {
+        Self::Message(s.to_owned())
+    }
 }
 
-fn get_voice_channel_by_user(guild: &Guild, user: &User) -> Option<ChannelId> {
-    guild
-        .voice_states
-        .get(&user.id)
-        .and_then(|vs| vs.channel_id)
-        .map(ChannelId::from)
+pub struct MessageCommandsMap;
+/// Message context-menu commands, keyed by the label shown in Discord's menu.
+pub type MessageCommands = HashMap<String, Arc<dyn TugboatMessageCommand + Send + Sync + 'static>>;
+impl TypeMapKey for MessageCommandsMap {
+    type Value = MessageCommands;
 }
 
 pub struct CommandsMap;
@@ -134,6 +262,25 @@ pub fn register_commands() -> Commands {
         Arc::new(leave::LeaveCommand),
         Arc::new(skip::SkipCommand),
         Arc::new(languages::LanguagesCommand),
+        Arc::new(permissions::PermissionsCommand),
+        Arc::new(config::ConfigCommand),
+        Arc::new(move_channel::MoveCommand),
+        Arc::new(follow::FollowCommand),
+        Arc::new(voice::VoiceCommand),
+        Arc::new(autoread::AutoreadCommand),
+        Arc::new(speak_for_me::SpeakForMeCommand),
+        Arc::new(pronounce::PronounceCommand),
+        Arc::new(filter::FilterCommand),
+        Arc::new(history::HistoryCommand),
+        Arc::new(forget_me::ForgetMeCommand),
+        Arc::new(replay::ReplayCommand),
+        Arc::new(recent::RecentCommand),
+        Arc::new(render::RenderCommand),
+        Arc::new(say_long::SayLongCommand),
+        Arc::new(phrase::PhraseCommand),
+        Arc::new(schedule::ScheduleCommand),
+        Arc::new(timer::TimerCommand),
+        Arc::new(persona::PersonaCommand),
     ];
 
     v.into_iter()
@@ -141,17 +288,98 @@ pub fn register_commands() -> Commands {
         .collect::<Commands>()
 }
 
+/// Static registration of all message context-menu commands.
+pub fn register_message_commands() -> MessageCommands {
+    let v: Vec<Arc<dyn TugboatMessageCommand + Send + Sync>> =
+        vec![Arc::new(read_aloud::ReadAloudCommand)];
+
+    v.into_iter()
+        .map(|c| (c.get_label(), c))
+        .collect::<MessageCommands>()
+}
+
 #[async_trait]
 pub trait TugboatCommand {
     async fn execute(
         &self,
         ctx: &Context,
         options: &[CommandDataOption],
-        guild: Guild,
-        channel_id: ChannelId,
-    ) -> anyhow::Result<String>;
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse>;
     fn create_command(&self) -> CreateApplicationCommandOption;
     fn get_name(&self) -> String;
+    /// What needs to be true before this command can run. See [`Precondition`].
+    fn preconditions(&self) -> Vec<Precondition>;
+    /// The preconditions for running the command with these particular options, for
+    /// commands whose subcommands need different things.
+    fn preconditions_for(&self, _options: &[CommandDataOption]) -> Vec<Precondition> {
+        self.preconditions()
+    }
+    /// Suggest values for the option the user is typing in, which is marked as focused.
+    /// Each suggestion is the name to show along with the value to fill in.
+    async fn autocomplete(
+        &self,
+        _ctx: &Context,
+        _options: &[CommandDataOption],
+        _guild: Option<Guild>,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(Vec::new())
+    }
+    /// A channel the command was explicitly asked to act on, which takes the place of
+    /// the invoking user's voice channel.
+    fn requested_channel(&self, _options: &[CommandDataOption]) -> Option<ChannelId> {
+        None
+    }
+    /// Handle the submission of a modal this command opened. `fields` maps each text
+    /// input's custom ID to what was typed into it.
+    async fn submit(
+        &self,
+        _ctx: &Context,
+        _custom_id: &str,
+        _fields: &HashMap<String, String>,
+        _guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        Err(anyhow!("{} doesn't take modal input", self.get_name()))
+    }
+    /// The name to check the guild's command permissions against for a modal or component
+    /// with this custom ID, for commands whose buttons do another command's job.
+    fn permission_name_for(&self, _custom_id: &str) -> String {
+        self.get_name()
+    }
+    /// Handle a click on a component this command attached to its response.
+    async fn component(
+        &self,
+        _ctx: &Context,
+        _custom_id: &str,
+        _guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        Err(anyhow!("{} doesn't have any components", self.get_name()))
+    }
+}
+
+/// A command that shows up when right-clicking a message, rather than under our slash command.
+#[async_trait]
+pub trait TugboatMessageCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        message: &Message,
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse>;
+    /// What the command is called in Discord's context menu.
+    fn get_label(&self) -> String;
+    /// What the command is called when setting up permissions.
+    fn get_name(&self) -> String;
+    /// What needs to be true before this command can run. See [`Precondition`].
+    fn preconditions(&self) -> Vec<Precondition>;
 }
 
 pub struct ApplicationCommandHandler {
@@ -160,16 +388,26 @@ pub struct ApplicationCommandHandler {
 }
 
 impl ApplicationCommandHandler {
+    fn message_command_labels(&self, ctx_data: &serenity::prelude::TypeMap) -> Vec<String> {
+        ctx_data
+            .get::<MessageCommandsMap>()
+            .expect("Should have been message commands here")
+            .keys()
+            .cloned()
+            .collect()
+    }
+
     async fn set_commands_global(&self, ctx: &Context) {
-        let options = ctx
-            .data
-            .read()
-            .await
-            .get::<CommandsMap>()
-            .expect("Should have been commands here")
-            .values()
-            .map(|comm| comm.create_command())
-            .collect::<Vec<_>>();
+        let (options, labels) = {
+            let data = ctx.data.read().await;
+            let options = data
+                .get::<CommandsMap>()
+                .expect("Should have been commands here")
+                .values()
+                .map(|comm| comm.create_command())
+                .collect::<Vec<_>>();
+            (options, self.message_command_labels(&data))
+        };
 
         if let Err(err) = Command::create_global_application_command(&ctx.http, |c| {
             c.name(&self.prefix)
@@ -182,18 +420,29 @@ impl ApplicationCommandHandler {
         } else {
             tracing::info!("Registered global application commands");
         }
+
+        for label in labels {
+            if let Err(err) = Command::create_global_application_command(&ctx.http, |c| {
+                c.name(&label).kind(CommandType::Message)
+            })
+            .await
+            {
+                tracing::error!(?err, label, "Could not set global message command")
+            }
+        }
     }
 
     async fn set_commands_guild(&self, guild_id: SerenityGuildId, ctx: &Context) {
-        let options = ctx
-            .data
-            .read()
-            .await
-            .get::<CommandsMap>()
-            .expect("Should have been commands here")
-            .values()
-            .map(|comm| comm.create_command())
-            .collect::<Vec<_>>();
+        let (options, labels) = {
+            let data = ctx.data.read().await;
+            let options = data
+                .get::<CommandsMap>()
+                .expect("Should have been commands here")
+                .values()
+                .map(|comm| comm.create_command())
+                .collect::<Vec<_>>();
+            (options, self.message_command_labels(&data))
+        };
 
         if let Err(e) = guild_id
             .set_application_commands(&ctx.http, |c| {
@@ -201,7 +450,11 @@ impl ApplicationCommandHandler {
                     a.name(&self.prefix) // TODO: replace this with something configurable
                         .description("Commands")
                         .set_options(options)
-                })
+                });
+                for label in labels {
+                    c.create_application_command(|a| a.name(label).kind(CommandType::Message));
+                }
+                c
             })
             .await
         {
@@ -214,106 +467,647 @@ impl ApplicationCommandHandler {
     async fn send_interaction_response(
         &self,
         http: &impl AsRef<Http>,
-        command: &ApplicationCommandInteraction,
-        content: &str,
+        interaction: Invocation<'_>,
+        response: CommandResponse,
     ) {
-        tracing::debug!(content, "Sending interaction response");
-        if let Err(e) = command
-            .create_interaction_response(http, |r| {
-                r.kind(InteractionResponseType::ChannelMessageWithSource)
-                    .interaction_response_data(|message| message.content(content.to_owned()))
+        tracing::debug!(?response, "Sending interaction response");
+        let result = match interaction {
+            Invocation::Command(command) => {
+                command
+                    .create_interaction_response(http, |r| build_response(r, response))
+                    .await
+            }
+            Invocation::ModalSubmit(submit) => {
+                submit
+                    .create_interaction_response(http, |r| build_response(r, response))
+                    .await
+            }
+            Invocation::Component(component) => {
+                component
+                    .create_interaction_response(http, |r| match response {
+                        CommandResponse::DisableButton => r
+                            .kind(InteractionResponseType::UpdateMessage)
+                            .interaction_response_data(|message| {
+                                message.set_components(disable_button(
+                                    &component.message,
+                                    &component.data.custom_id,
+                                ))
+                            }),
+                        response => build_response(r, response),
+                    })
+                    .await
+            }
+        };
+
+        if let Err(e) = result {
+            tracing::error!(?e, "Could not respond to interaction");
+        } else {
+            tracing::debug!("Interaction response sent successfully!");
+        }
+    }
+
+    /// Send back the result of running a command, or a generic error if it failed.
+    async fn send_command_result(
+        &self,
+        ctx: &Context,
+        interaction: Invocation<'_>,
+        result: anyhow::Result<CommandResponse>,
+    ) {
+        match result {
+            Ok(mut s) => {
+                tracing::trace!("We received a successful response, sending back result");
+                let disable_after = match s {
+                    CommandResponse::Interactive {
+                        ref mut disable_after,
+                        ..
+                    } => disable_after.take(),
+                    _ => None,
+                };
+                self.send_interaction_response(&ctx.http, interaction, s)
+                    .await;
+                if let Some((track, custom_id)) = disable_after {
+                    disable_when_finished(ctx, interaction.token(), &track, custom_id).await;
+                }
+            }
+            Err(e) => {
+                tracing::error!(?e, guild_id=?interaction.guild_id(), "Error completing interaction");
+                self.send_interaction_response(
+                    &ctx.http,
+                    interaction,
+                    "Error completing interaction.".into(),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Check a command's preconditions, letting the user know if they aren't met.
+    /// Returns whether the command may go ahead.
+    async fn preconditions_met(
+        &self,
+        ctx: &Context,
+        interaction: Invocation<'_>,
+        name: &str,
+        preconditions: Vec<Precondition>,
+        guild: Option<&Guild>,
+        channel_id: Option<ChannelId>,
+    ) -> bool {
+        match check_preconditions(
+            ctx,
+            name,
+            preconditions,
+            guild,
+            channel_id,
+            interaction.member(),
+        )
+        .await
+        {
+            Ok(None) => true,
+            Ok(Some(reason)) => {
+                tracing::info!(user=?interaction.user().id, command=name, reason, "Command preconditions not met");
+                self.send_interaction_response(&ctx.http, interaction, reason.into())
+                    .await;
+                false
+            }
+            Err(e) => {
+                tracing::error!(?e, guild_id=?interaction.guild_id(), "Could not check command preconditions");
+                self.send_interaction_response(
+                    &ctx.http,
+                    interaction,
+                    "Error completing interaction.".into(),
+                )
+                .await;
+                false
+            }
+        }
+    }
+
+    async fn handle_chat_command(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let interaction = Invocation::Command(command);
+        // since our top level command is always tugboat, we are interested in the first child of the options.
+        let incoming = &command.data.options[0];
+
+        let dispatched_command = {
+            let data = ctx.data.read().await;
+            let commands = data
+                .get::<CommandsMap>()
+                .expect("Should have been commands here");
+            commands.get(&incoming.name).cloned()
+        };
+
+        let guild = match interaction_guild(ctx, command.guild_id) {
+            Ok(g) => g,
+            Err(()) => return,
+        };
+
+        let requested_channel = dispatched_command
+            .as_ref()
+            .and_then(|c| c.requested_channel(&incoming.options));
+        let channel_id = requested_channel.or_else(|| {
+            guild
+                .as_ref()
+                .and_then(|g| get_voice_channel_by_user(g, &command.user))
+        });
+
+        if let Some(c) = dispatched_command.as_ref() {
+            if !self
+                .preconditions_met(
+                    ctx,
+                    interaction,
+                    &c.get_name(),
+                    c.preconditions_for(&incoming.options),
+                    guild.as_ref(),
+                    channel_id,
+                )
+                .await
+            {
+                return;
+            }
+        }
+
+        let response = {
+            match dispatched_command {
+                Some(c) => {
+                    tracing::debug!(
+                        requested_comm = incoming.name.as_str(),
+                        "Dispatching command"
+                    );
+                    let r = c
+                        .execute(ctx, &incoming.options, guild, channel_id, &command.user)
+                        .await;
+                    tracing::debug!(result=?r, "We have received a result from our command!");
+                    r
+                }
+                None => Err(anyhow!("Unknown command {}", &incoming.name)),
+            }
+        };
+
+        self.send_command_result(ctx, interaction, response).await
+    }
+
+    async fn handle_autocomplete(&self, ctx: &Context, autocomplete: &AutocompleteInteraction) {
+        let incoming = &autocomplete.data.options[0];
+        let dispatched_command = {
+            let data = ctx.data.read().await;
+            data.get::<CommandsMap>()
+                .expect("Should have been commands here")
+                .get(&incoming.name)
+                .cloned()
+        };
+        let dispatched_command = match dispatched_command {
+            Some(c) => c,
+            None => {
+                tracing::error!(
+                    name = incoming.name.as_str(),
+                    "Autocomplete for unknown command"
+                );
+                return;
+            }
+        };
+        let guild = match interaction_guild(ctx, autocomplete.guild_id) {
+            Ok(g) => g,
+            Err(()) => return,
+        };
+
+        let choices = match dispatched_command
+            .autocomplete(ctx, &incoming.options, guild)
+            .await
+        {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::error!(?e, guild_id=?autocomplete.guild_id, "Could not autocomplete");
+                return;
+            }
+        };
+
+        if let Err(e) = autocomplete
+            .create_autocomplete_response(&ctx.http, |r| {
+                for (name, value) in choices.into_iter().take(MAX_AUTOCOMPLETE_CHOICES) {
+                    r.add_string_choice(name, value);
+                }
+                r
             })
             .await
         {
-            tracing::error!(?e, "Could not respond to slash command");
-        } else {
-            tracing::debug!("Application command response sent successfully!");
+            tracing::error!(?e, "Could not respond to autocomplete");
+        }
+    }
+
+    async fn handle_message_command(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let interaction = Invocation::Command(command);
+        let dispatched_command = {
+            let data = ctx.data.read().await;
+            data.get::<MessageCommandsMap>()
+                .expect("Should have been message commands here")
+                .get(&command.data.name)
+                .cloned()
+        };
+        let dispatched_command = match dispatched_command {
+            Some(c) => c,
+            None => {
+                let e = anyhow!("Unknown message command {}", &command.data.name);
+                return self.send_command_result(ctx, interaction, Err(e)).await;
+            }
+        };
+
+        let message = match command.data.target() {
+            Some(ResolvedTarget::Message(m)) => m,
+            _ => {
+                let e = anyhow!("Message command without a target message");
+                return self.send_command_result(ctx, interaction, Err(e)).await;
+            }
+        };
+
+        let guild = match interaction_guild(ctx, command.guild_id) {
+            Ok(g) => g,
+            Err(()) => return,
+        };
+        let channel_id = guild
+            .as_ref()
+            .and_then(|g| get_voice_channel_by_user(g, &command.user));
+
+        if !self
+            .preconditions_met(
+                ctx,
+                interaction,
+                &dispatched_command.get_name(),
+                dispatched_command.preconditions(),
+                guild.as_ref(),
+                channel_id,
+            )
+            .await
+        {
+            return;
+        }
+
+        tracing::debug!(
+            requested_comm = command.data.name.as_str(),
+            "Dispatching message command"
+        );
+        let response = dispatched_command
+            .execute(ctx, &message, guild, channel_id, &command.user)
+            .await;
+        self.send_command_result(ctx, interaction, response).await
+    }
+
+    /// Modals and components are created by one of our subcommands, with a custom ID of the
+    /// form `name` or `name:anything`, so whatever the user did goes back to the command named.
+    /// Checks the command's preconditions again, since things might have changed in the
+    /// meantime, and returns the command along with its guild and voice channel if they're met.
+    async fn command_for_custom_id(
+        &self,
+        ctx: &Context,
+        interaction: Invocation<'_>,
+        custom_id: &str,
+    ) -> Option<(
+        Arc<dyn TugboatCommand + Send + Sync>,
+        Option<Guild>,
+        Option<ChannelId>,
+    )> {
+        let name = custom_id.split(':').next().unwrap_or_default();
+        let dispatched_command = {
+            let data = ctx.data.read().await;
+            data.get::<CommandsMap>()
+                .expect("Should have been commands here")
+                .get(name)
+                .cloned()
+        };
+        let dispatched_command = match dispatched_command {
+            Some(c) => c,
+            None => {
+                let e = anyhow!("Interaction for unknown command {}", name);
+                self.send_command_result(ctx, interaction, Err(e)).await;
+                return None;
+            }
+        };
+
+        let guild = interaction_guild(ctx, interaction.guild_id()).ok()?;
+        let channel_id = guild
+            .as_ref()
+            .and_then(|g| get_voice_channel_by_user(g, interaction.user()));
+
+        if !self
+            .preconditions_met(
+                ctx,
+                interaction,
+                &dispatched_command.permission_name_for(custom_id),
+                dispatched_command.preconditions(),
+                guild.as_ref(),
+                channel_id,
+            )
+            .await
+        {
+            return None;
+        }
+
+        tracing::debug!(requested_comm = name, "Dispatching {:?}", custom_id);
+        Some((dispatched_command, guild, channel_id))
+    }
+
+    async fn handle_modal_submit(&self, ctx: &Context, submit: &ModalSubmitInteraction) {
+        let interaction = Invocation::ModalSubmit(submit);
+        let (dispatched_command, guild, channel_id) = match self
+            .command_for_custom_id(ctx, interaction, &submit.data.custom_id)
+            .await
+        {
+            Some(c) => c,
+            None => return,
+        };
+
+        let fields = submit
+            .data
+            .components
+            .iter()
+            .flat_map(|row| row.components.iter())
+            .filter_map(|c| match c {
+                ActionRowComponent::InputText(t) => Some((t.custom_id.clone(), t.value.clone())),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+
+        let response = dispatched_command
+            .submit(
+                ctx,
+                &submit.data.custom_id,
+                &fields,
+                guild,
+                channel_id,
+                &submit.user,
+            )
+            .await;
+        self.send_command_result(ctx, interaction, response).await
+    }
+
+    async fn handle_component(&self, ctx: &Context, component: &MessageComponentInteraction) {
+        let interaction = Invocation::Component(component);
+        let (dispatched_command, guild, channel_id) = match self
+            .command_for_custom_id(ctx, interaction, &component.data.custom_id)
+            .await
+        {
+            Some(c) => c,
+            None => return,
+        };
+
+        let response = dispatched_command
+            .component(
+                ctx,
+                &component.data.custom_id,
+                guild,
+                channel_id,
+                &component.user,
+            )
+            .await;
+        self.send_command_result(ctx, interaction, response).await
+    }
+}
+
+/// The interactions a command can be run from, so they can share precondition checks
+/// and responses.
+#[derive(Clone, Copy)]
+enum Invocation<'a> {
+    Command(&'a ApplicationCommandInteraction),
+    ModalSubmit(&'a ModalSubmitInteraction),
+    Component(&'a MessageComponentInteraction),
+}
+
+impl<'a> Invocation<'a> {
+    fn guild_id(&self) -> Option<SerenityGuildId> {
+        match self {
+            Self::Command(c) => c.guild_id,
+            Self::ModalSubmit(m) => m.guild_id,
+            Self::Component(c) => c.guild_id,
+        }
+    }
+
+    fn user(&self) -> &'a User {
+        match self {
+            Self::Command(c) => &c.user,
+            Self::ModalSubmit(m) => &m.user,
+            Self::Component(c) => &c.user,
+        }
+    }
+
+    fn token(&self) -> &'a str {
+        match self {
+            Self::Command(c) => &c.token,
+            Self::ModalSubmit(m) => &m.token,
+            Self::Component(c) => &c.token,
+        }
+    }
+
+    fn member(&self) -> Option<&'a Member> {
+        match self {
+            Self::Command(c) => c.member.as_ref(),
+            Self::ModalSubmit(m) => m.member.as_ref(),
+            Self::Component(c) => c.member.as_ref(),
+        }
+    }
+}
+
+/// The buttons on `message`, with the one with `custom_id` greyed out. Anything other than
+/// buttons is left off, since we don't send anything else.
+fn disable_button(message: &Message, custom_id: &str) -> CreateComponents {
+    let mut components = CreateComponents::default();
+    for row in &message.components {
+        components.create_action_row(|r| {
+            for component in &row.components {
+                if let ActionRowComponent::Button(button) = component {
+                    r.create_button(|b| {
+                        b.style(button.style).disabled(
+                            button.disabled || button.custom_id.as_deref() == Some(custom_id),
+                        );
+                        if let Some(ref label) = button.label {
+                            b.label(label);
+                        }
+                        if let Some(ref emoji) = button.emoji {
+                            b.emoji(emoji.clone());
+                        }
+                        if let Some(ref id) = button.custom_id {
+                            b.custom_id(id);
+                        }
+                        if let Some(ref url) = button.url {
+                            b.url(url);
+                        }
+                        b
+                    });
+                }
+            }
+            r
+        });
+    }
+    components
+}
+
+/// Greys out a button on an interaction's reply once the track it controls is done.
+struct DisableOnEnd {
+    http: Arc<Http>,
+    token: String,
+    custom_id: String,
+}
+
+impl DisableOnEnd {
+    async fn disable(&self) {
+        let result = async {
+            let message = self
+                .http
+                .get_original_interaction_response(&self.token)
+                .await?;
+            let mut edit = EditInteractionResponse::default();
+            edit.components(|c| {
+                *c = disable_button(&message, &self.custom_id);
+                c
+            });
+            let map = serenity::json::hashmap_to_json_map(edit.0);
+            self.http
+                .edit_original_interaction_response(&self.token, &Value::from(map))
+                .await
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::warn!(
+                ?e,
+                custom_id = self.custom_id.as_str(),
+                "Could not disable button"
+            );
         }
     }
 }
 
+#[async_trait]
+impl VoiceEventHandler for DisableOnEnd {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        self.disable().await;
+        None
+    }
+}
+
+/// Grey out the button with `custom_id` on the reply to an interaction once `track` has
+/// finished playing, or right away if it already has.
+async fn disable_when_finished(ctx: &Context, token: &str, track: &TrackHandle, custom_id: String) {
+    let handler = DisableOnEnd {
+        http: ctx.http.clone(),
+        token: token.to_owned(),
+        custom_id,
+    };
+    // the track may have finished while we were replying, in which case it's gone already.
+    let finished = match track.get_info().await {
+        Ok(state) => matches!(state.playing, PlayMode::End | PlayMode::Stop),
+        Err(_) => true,
+    };
+    if finished {
+        handler.disable().await;
+    } else if let Err(e) = track.add_event(Event::Track(TrackEvent::End), handler) {
+        tracing::warn!(?e, "Could not watch track for when it finishes");
+    }
+}
+
+fn build_response<'a, 'b>(
+    r: &'b mut CreateInteractionResponse<'a>,
+    response: CommandResponse,
+) -> &'b mut CreateInteractionResponse<'a> {
+    match response {
+        CommandResponse::Message(content) => r
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|message| message.content(content)),
+        CommandResponse::Ephemeral(content) => r
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|message| message.content(content).ephemeral(true)),
+        CommandResponse::Interactive {
+            content,
+            components,
+            ..
+        } => r
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|message| {
+                message.content(content).set_components(components)
+            }),
+        CommandResponse::File {
+            content,
+            filename,
+            data,
+            ephemeral,
+        } => r
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|message| {
+                message
+                    .content(content)
+                    .add_file(AttachmentType::Bytes {
+                        data: Cow::Owned(data),
+                        filename,
+                    })
+                    .ephemeral(ephemeral)
+            }),
+        CommandResponse::Modal {
+            custom_id,
+            title,
+            components,
+        } => r
+            .kind(InteractionResponseType::Modal)
+            .interaction_response_data(|modal| {
+                modal
+                    .custom_id(custom_id)
+                    .title(title)
+                    .set_components(components)
+            }),
+        // there's no button to disable when a command was run some other way.
+        CommandResponse::DisableButton => {
+            tracing::error!("Asked to disable a button without one having been clicked");
+            build_response(
+                r,
+                CommandResponse::Ephemeral("Error completing interaction.".into()),
+            )
+        }
+    }
+}
+
+/// Look up the guild an interaction came from in the cache. Errors if the interaction
+/// came from a guild we don't have cached, which has already been logged.
+fn interaction_guild(
+    ctx: &Context,
+    guild_id: Option<SerenityGuildId>,
+) -> Result<Option<Guild>, ()> {
+    match guild_id {
+        Some(g) => match g.to_guild_cached(&ctx.cache) {
+            Some(gu) => Ok(Some(gu)),
+            None => {
+                tracing::error!(guild_id=?g, "Could not find guild in cache!");
+                Err(())
+            }
+        },
+        None => Ok(None),
+    }
+}
+
 #[async_trait]
 impl EventHandler for ApplicationCommandHandler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::ApplicationCommand(command) = interaction {
-            tracing::info!(data=?command.data, "got command interaction!");
-
-            // since our top level command is always tugboat, we are interested in the first child of the options.
-            let incoming = &command.data.options[0];
-
-            // gather up guild and channel info
-            let guild = match command.guild_id {
-                Some(g) => match g.to_guild_cached(&ctx.cache) {
-                    Some(gu) => gu,
-                    None => {
-                        tracing::error!(guild_id=?g, "Could not find guild in cache!");
-                        return;
-                    }
-                },
-                None => {
-                    return self
-                        .send_interaction_response(
-                            &ctx.http,
-                            &command,
-                            "Can't call this from a non-guild context",
-                        )
-                        .await
-                }
-            };
-
-            let channel_id = match get_voice_channel_by_user(&guild, &command.user) {
-                Some(c) => c,
-                None => {
-                    return self
-                        .send_interaction_response(
-                            &ctx.http,
-                            &command,
-                            NOT_IN_VOICE_CHANNEL_MESSAGE,
-                        )
-                        .await
-                }
-            };
-
-            let dispatched_command = {
-                let data = ctx.data.read().await;
-                let commands = data
-                    .get::<CommandsMap>()
-                    .expect("Should have been commands here");
-                commands.get(&incoming.name).cloned()
-            };
-
-            let response = {
-                match dispatched_command {
-                    Some(c) => {
-                        tracing::debug!(
-                            requested_comm = incoming.name.as_str(),
-                            "Dispatching command"
-                        );
-                        let r = c.execute(&ctx, &incoming.options, guild, channel_id).await;
-                        tracing::debug!(result=?r, "We have received a result from our command!");
-                        r
-                    }
-                    None => Err(anyhow!("Unknown command {}", &incoming.name)),
-                }
-            };
-
-            // dispatch to the relevant command in our command struct.
-            match response {
-                Ok(s) => {
-                    tracing::trace!("We received a successful response, sending back result");
-                    self.send_interaction_response(&ctx.http, &command, &s)
-                        .await
-                }
-                Err(e) => {
-                    tracing::error!(?e, guild_id=?command.guild_id, "Error completing interaction");
-                    self.send_interaction_response(
-                        &ctx.http,
-                        &command,
-                        "Error completing interaction.",
-                    )
-                    .await
+        match interaction {
+            Interaction::ApplicationCommand(command) => {
+                tracing::info!(data=?command.data, "got command interaction!");
+
+                match command.data.kind {
+                    CommandType::Message => self.handle_message_command(&ctx, &command).await,
+                    _ => self.handle_chat_command(&ctx, &command).await,
                 }
             }
+            Interaction::Autocomplete(autocomplete) => {
+                self.handle_autocomplete(&ctx, &autocomplete).await
+            }
+            Interaction::ModalSubmit(submit) => {
+                tracing::info!(
+                    custom_id = submit.data.custom_id.as_str(),
+                    "got modal submission!"
+                );
+                self.handle_modal_submit(&ctx, &submit).await
+            }
+            Interaction::MessageComponent(component) => {
+                tracing::info!(
+                    custom_id = component.data.custom_id.as_str(),
+                    "got component interaction!"
+                );
+                self.handle_component(&ctx, &component).await
+            }
+            _ => {}
         }
     }
 
@@ -0,0 +1,70 @@
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType, guild::Guild,
+        prelude::interaction::application_command::CommandDataOption, user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{CommandResponse, Precondition, TugboatCommand};
+use crate::{session::get_sessions_from_ctx, voice_state::is_alone};
+
+pub struct MoveCommand;
+
+#[async_trait]
+impl TugboatCommand for MoveCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        _options: &[CommandDataOption],
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let channel_id = channel_id.context("Voice channel precondition not met")?;
+        let sessions = get_sessions_from_ctx(ctx).await;
+        let session = match sessions.get(guild.id).await {
+            Some(s) => s,
+            None => return Ok("I'm not in a voice channel right now. Use `join` instead.".into()),
+        };
+
+        match session.current_channel().await {
+            Some(c) if c == channel_id => return Ok("I'm already here!".into()),
+            // don't strand anyone who is still listening in the other channel.
+            Some(c) if !is_alone(ctx, guild.id, c) => {
+                return Ok(format!(
+                    "Someone is still listening to me in <#{}>, so I'm staying put.",
+                    c.0
+                )
+                .into())
+            }
+            _ => {}
+        }
+
+        tracing::debug!(guild=?guild.id, ?channel_id, "Moving to caller's voice channel");
+        sessions.join(guild.id, channel_id, user.id).await?;
+
+        Ok("Moved to your voice channel".into())
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("move")
+            .description("Bring the bot over to your current voice channel")
+            .kind(CommandOptionType::SubCommand)
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("move")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild, Precondition::UserInVoice]
+    }
+}
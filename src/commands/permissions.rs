@@ -0,0 +1,471 @@
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Context as anyhowContext};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::{Guild, Member},
+        id::{GuildId, RoleId, UserId},
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{CommandResponse, CommandsMap, MessageCommandsMap, Precondition, TugboatCommand};
+use crate::settings::get_settings_from_ctx;
+
+/// Allow and deny lists for a single subcommand. An empty allow list means anyone
+/// who isn't on a deny list may use the command. Rules for a user win over rules for
+/// their roles, and at the same level being denied wins over being allowed.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct CommandPermissions {
+    pub allowed_roles: BTreeSet<RoleId>,
+    pub allowed_users: BTreeSet<UserId>,
+    pub denied_roles: BTreeSet<RoleId>,
+    pub denied_users: BTreeSet<UserId>,
+}
+
+impl CommandPermissions {
+    fn is_empty(&self) -> bool {
+        self.allowed_roles.is_empty()
+            && self.allowed_users.is_empty()
+            && self.denied_roles.is_empty()
+            && self.denied_users.is_empty()
+    }
+
+    fn check(&self, command: &str, member: &Member) -> PermissionCheck {
+        if self.denied_users.contains(&member.user.id) {
+            return PermissionCheck::Denied(format!(
+                "You've been blocked from using `{}` on this server.",
+                command
+            ));
+        }
+
+        if self.allowed_users.contains(&member.user.id) {
+            return PermissionCheck::Allowed;
+        }
+
+        if member.roles.iter().any(|r| self.denied_roles.contains(r)) {
+            return PermissionCheck::Denied(format!(
+                "One of your roles has been blocked from using `{}` on this server.",
+                command
+            ));
+        }
+
+        if self.allowed_roles.is_empty() && self.allowed_users.is_empty() {
+            return PermissionCheck::Allowed;
+        }
+
+        if member.roles.iter().any(|r| self.allowed_roles.contains(r)) {
+            PermissionCheck::Allowed
+        } else {
+            PermissionCheck::Denied(format!(
+                "`{}` is restricted to certain roles and users on this server, and you aren't one of them.",
+                command
+            ))
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PermissionCheck {
+    Allowed,
+    Denied(String),
+}
+
+/// Guild admins can always use every command, so that nobody can lock themselves
+/// out of fixing a bad permissions setup.
+pub fn is_guild_admin(member: &Member) -> bool {
+    member
+        .permissions
+        .map(|p| p.administrator() || p.manage_guild())
+        .unwrap_or(false)
+}
+
+/// Check whether `member` may run the subcommand `command` according to this guild's
+/// configured allow and deny lists.
+pub async fn check_command_permissions(
+    ctx: &Context,
+    guild_id: GuildId,
+    name: &str,
+    member: &Member,
+) -> anyhow::Result<PermissionCheck> {
+    if is_guild_admin(member) {
+        return Ok(PermissionCheck::Allowed);
+    }
+
+    let settings = get_settings_from_ctx(ctx).await.get(guild_id).await?;
+    Ok(resolve(settings.permissions.get(name), name, member))
+}
+
+/// Whether `member` may run `command` under its rules, if it has any.
+fn resolve(rules: Option<&CommandPermissions>, command: &str, member: &Member) -> PermissionCheck {
+    if is_guild_admin(member) {
+        return PermissionCheck::Allowed;
+    }
+    match rules {
+        Some(p) => p.check(command, member),
+        None => PermissionCheck::Allowed,
+    }
+}
+
+enum Target {
+    Role(RoleId),
+    User(UserId),
+}
+
+fn parse_options(options: &[CommandDataOption]) -> (Option<String>, Vec<Target>) {
+    let mut command = None;
+    let mut targets = vec![];
+
+    for option in options {
+        match (option.name.as_str(), option.resolved.as_ref()) {
+            ("command", Some(CommandDataOptionValue::String(s))) => command = Some(s.to_owned()),
+            ("role", Some(CommandDataOptionValue::Role(r))) => targets.push(Target::Role(r.id)),
+            ("user", Some(CommandDataOptionValue::User(u, _))) => targets.push(Target::User(u.id)),
+            _ => continue,
+        }
+    }
+
+    (command, targets)
+}
+
+fn describe(guild: &Guild, permissions: &CommandPermissions) -> String {
+    let role_names = |roles: &BTreeSet<RoleId>| {
+        roles
+            .iter()
+            .map(|r| match guild.roles.get(r) {
+                Some(role) => format!("@{}", role.name),
+                None => format!("(deleted role {})", r),
+            })
+            .collect::<Vec<_>>()
+    };
+    let user_names = |users: &BTreeSet<UserId>| {
+        users
+            .iter()
+            .map(|u| match guild.members.get(u) {
+                Some(m) => m.display_name().into_owned(),
+                None => format!("(user {})", u),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut allowed = role_names(&permissions.allowed_roles);
+    allowed.extend(user_names(&permissions.allowed_users));
+    let mut denied = role_names(&permissions.denied_roles);
+    denied.extend(user_names(&permissions.denied_users));
+
+    format!(
+        "allowed: {}; denied: {}",
+        if allowed.is_empty() {
+            "everyone".into()
+        } else {
+            allowed.join(", ")
+        },
+        if denied.is_empty() {
+            "nobody".into()
+        } else {
+            denied.join(", ")
+        }
+    )
+}
+
+pub struct PermissionsCommand;
+
+#[async_trait]
+impl TugboatCommand for PermissionsCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No permissions subcommand given"))?;
+        let (command, targets) = parse_options(&subcommand.options);
+
+        let known_commands = {
+            let data = ctx.data.read().await;
+            let mut names = data
+                .get::<CommandsMap>()
+                .expect("Should have been commands here")
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+            names.extend(
+                data.get::<MessageCommandsMap>()
+                    .expect("Should have been message commands here")
+                    .values()
+                    .map(|c| c.get_name()),
+            );
+            names.sort();
+            names
+        };
+
+        if let Some(ref c) = command {
+            if !known_commands.contains(c) {
+                return Ok(format!(
+                    "I don't have a command called `{}`. Try one of: {}",
+                    c,
+                    known_commands.join(", ")
+                )
+                .into());
+            }
+        }
+
+        let settings = get_settings_from_ctx(ctx).await;
+
+        if subcommand.name == "show" {
+            let stored = settings.get(guild.id).await?;
+            let mut lines = vec![];
+            for name in known_commands
+                .iter()
+                .filter(|n| command.is_none() || command.as_ref() == Some(n))
+            {
+                if let Some(p) = stored.permissions.get(name) {
+                    lines.push(format!("`{}`: {}", name, describe(&guild, p)));
+                }
+            }
+
+            if lines.is_empty() {
+                return Ok(
+                    "No command restrictions are set up, everyone can use everything.".into(),
+                );
+            }
+            return Ok(lines.join("\n").into());
+        }
+
+        let command = command.ok_or_else(|| anyhow!("Command option is required"))?;
+        if subcommand.name != "clear" && targets.is_empty() {
+            return Ok("You need to give me a role or a user.".into());
+        }
+
+        let response = settings
+            .update(guild.id, |s| {
+                let entry = s.permissions.entry(command.clone()).or_default();
+                for target in &targets {
+                    match (subcommand.name.as_str(), target) {
+                        ("allow", Target::Role(r)) => {
+                            entry.denied_roles.remove(r);
+                            entry.allowed_roles.insert(*r);
+                        }
+                        ("allow", Target::User(u)) => {
+                            entry.denied_users.remove(u);
+                            entry.allowed_users.insert(*u);
+                        }
+                        ("deny", Target::Role(r)) => {
+                            entry.allowed_roles.remove(r);
+                            entry.denied_roles.insert(*r);
+                        }
+                        ("deny", Target::User(u)) => {
+                            entry.allowed_users.remove(u);
+                            entry.denied_users.insert(*u);
+                        }
+                        (_, Target::Role(r)) => {
+                            entry.allowed_roles.remove(r);
+                            entry.denied_roles.remove(r);
+                        }
+                        (_, Target::User(u)) => {
+                            entry.allowed_users.remove(u);
+                            entry.denied_users.remove(u);
+                        }
+                    }
+                }
+
+                // clearing without naming anyone resets the command entirely.
+                if subcommand.name == "clear" && targets.is_empty() {
+                    *entry = Default::default();
+                }
+
+                let response = format!("`{}`: {}", command, describe(&guild, entry));
+                if entry.is_empty() {
+                    s.permissions.remove(&command);
+                }
+                response
+            })
+            .await?;
+
+        Ok(response.into())
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        let mut group = CreateApplicationCommandOption::default();
+        group
+            .name("permissions")
+            .description("Control who can use which commands on this server")
+            .kind(CommandOptionType::SubCommandGroup);
+
+        for (name, description) in [
+            ("allow", "Only let the given roles and users use a command"),
+            (
+                "deny",
+                "Block the given roles and users from using a command",
+            ),
+            (
+                "clear",
+                "Remove roles and users from a command's lists, or reset it entirely",
+            ),
+        ] {
+            group.create_sub_option(|s| {
+                s.name(name)
+                    .description(description)
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("command")
+                            .description("The command to change, e.g. say or skip")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("role")
+                            .description("A role")
+                            .kind(CommandOptionType::Role)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("user")
+                            .description("A user")
+                            .kind(CommandOptionType::User)
+                            .required(false)
+                    })
+            });
+        }
+
+        group
+            .create_sub_option(|s| {
+                s.name("show")
+                    .description("Show the current command restrictions")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("command")
+                            .description("Only show this command")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("permissions")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::Permission(Permissions::MANAGE_GUILD),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: UserId = UserId(1);
+    const MODS: RoleId = RoleId(10);
+    const MUTED: RoleId = RoleId(11);
+
+    fn member(user: UserId, roles: &[RoleId], permissions: Permissions) -> Member {
+        let mut member: Member = serde_json::from_value(serde_json::json!({
+            "deaf": false,
+            "guild_id": "100",
+            "joined_at": null,
+            "mute": false,
+            "nick": null,
+            "roles": roles.iter().map(|r| r.0.to_string()).collect::<Vec<_>>(),
+            "user": {
+                "id": user.0.to_string(),
+                "username": "someone",
+                "discriminator": "0001",
+                "avatar": null,
+            },
+        }))
+        .unwrap();
+        member.permissions = Some(permissions);
+        member
+    }
+
+    fn rules(
+        allowed_roles: &[RoleId],
+        allowed_users: &[UserId],
+        denied_roles: &[RoleId],
+        denied_users: &[UserId],
+    ) -> CommandPermissions {
+        CommandPermissions {
+            allowed_roles: allowed_roles.iter().copied().collect(),
+            allowed_users: allowed_users.iter().copied().collect(),
+            denied_roles: denied_roles.iter().copied().collect(),
+            denied_users: denied_users.iter().copied().collect(),
+        }
+    }
+
+    fn allowed(rules: &CommandPermissions, member: &Member) -> bool {
+        resolve(Some(rules), "say", member) == PermissionCheck::Allowed
+    }
+
+    #[test]
+    fn anyone_may_use_commands_without_rules() {
+        let nobody = member(UserId(2), &[], Permissions::empty());
+        assert_eq!(resolve(None, "say", &nobody), PermissionCheck::Allowed);
+        assert!(allowed(&rules(&[], &[], &[MUTED], &[]), &nobody));
+    }
+
+    #[test]
+    fn deny_beats_allow() {
+        let alice = member(ALICE, &[MODS, MUTED], Permissions::empty());
+        assert!(!allowed(&rules(&[], &[ALICE], &[], &[ALICE]), &alice));
+        assert!(!allowed(&rules(&[MODS], &[], &[MUTED], &[]), &alice));
+        assert!(!allowed(&rules(&[MODS], &[], &[], &[ALICE]), &alice));
+    }
+
+    #[test]
+    fn user_rules_beat_role_rules() {
+        let alice = member(ALICE, &[MODS, MUTED], Permissions::empty());
+        assert!(allowed(&rules(&[], &[ALICE], &[MUTED], &[]), &alice));
+        assert!(!allowed(&rules(&[MODS], &[], &[], &[ALICE]), &alice));
+    }
+
+    #[test]
+    fn allow_lists_shut_out_everyone_else() {
+        let restricted = rules(&[MODS], &[ALICE], &[], &[]);
+        assert!(allowed(
+            &restricted,
+            &member(ALICE, &[], Permissions::empty())
+        ));
+        assert!(allowed(
+            &restricted,
+            &member(UserId(2), &[MODS], Permissions::empty())
+        ));
+        assert!(!allowed(
+            &restricted,
+            &member(UserId(3), &[MUTED], Permissions::empty())
+        ));
+    }
+
+    #[test]
+    fn admins_bypass_the_lists() {
+        let lockout = rules(&[MODS], &[], &[MUTED], &[ALICE]);
+        for permissions in [Permissions::ADMINISTRATOR, Permissions::MANAGE_GUILD] {
+            let alice = member(ALICE, &[MUTED], permissions);
+            assert!(is_guild_admin(&alice));
+            assert!(allowed(&lockout, &alice));
+        }
+        assert!(!is_guild_admin(&member(
+            ALICE,
+            &[],
+            Permissions::MANAGE_MESSAGES
+        )));
+    }
+}
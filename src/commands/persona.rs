@@ -0,0 +1,417 @@
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        id::GuildId,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{
+    get_focused_option, get_string_option, say::Voices, voice::describe, CommandResponse,
+    Precondition, TugboatCommand,
+};
+use crate::{
+    persona::{persona_key, Persona},
+    settings::get_settings_from_ctx,
+    speech::{Prosody, VoiceOptions},
+};
+
+const MAX_PERSONAS: usize = 25;
+const MAX_SUBSTITUTIONS: usize = 100;
+const MAX_NAME_LENGTH: u16 = 32;
+/// Leave some room under Discord's 2000 character limit.
+const MAX_LIST_LENGTH: usize = 1800;
+
+/// The device profiles the TTS API can tune audio for, as (label, profile ID).
+const EFFECTS_PROFILES: &[(&str, &str)] = &[
+    ("Smart watch", "wearable-class-device"),
+    ("Phone", "handset-class-device"),
+    ("Headphones", "headphone-class-device"),
+    ("Small speaker", "small-bluetooth-speaker-class-device"),
+    ("Medium speaker", "medium-bluetooth-speaker-class-device"),
+    ("Home theater", "large-home-entertainment-class-device"),
+    ("Car", "large-automotive-class-device"),
+    ("Phone call", "telephony-class-application"),
+];
+
+fn get_number_option(options: &[CommandDataOption], name: &str) -> Option<f64> {
+    options
+        .iter()
+        .find(|o| o.name == name)
+        .and_then(|o| match o.resolved {
+            Some(CommandDataOptionValue::Number(n)) => Some(n),
+            _ => None,
+        })
+}
+
+fn describe_persona(persona: &Persona) -> String {
+    let mut description = format!("`{}` speaks in {}", persona.name, describe(&persona.voice));
+    if let Some(rate) = persona.prosody.speaking_rate {
+        description.push_str(&format!(", at {}x speed", rate));
+    }
+    if let Some(pitch) = persona.prosody.pitch {
+        description.push_str(&format!(", pitched {:+} semitones", pitch));
+    }
+    if let Some(ref prefix) = persona.prefix {
+        description.push_str(&format!(", starting with \"{}\"", prefix));
+    }
+    if let Some(ref suffix) = persona.suffix {
+        description.push_str(&format!(", ending with \"{}\"", suffix));
+    }
+    if !persona.substitutions.is_empty() {
+        description.push_str(&format!(
+            ", and says {} words differently",
+            persona.substitutions.len()
+        ));
+    }
+    description
+}
+
+/// Autocomplete choices for a guild's personas whose names contain what's been typed so far.
+pub(super) async fn persona_choices(
+    ctx: &Context,
+    guild_id: GuildId,
+    typed: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let typed = persona_key(typed);
+    let settings = get_settings_from_ctx(ctx).await.get(guild_id).await?;
+    Ok(settings
+        .personas
+        .iter()
+        .filter(|(key, _)| key.contains(&typed))
+        .map(|(_, p)| (p.name.clone(), p.name.clone()))
+        .collect())
+}
+
+/// Add the `name` option every subcommand other than `list` takes.
+fn add_name_option(
+    command: &mut CreateApplicationCommandOption,
+    autocomplete: bool,
+) -> &mut CreateApplicationCommandOption {
+    command.create_sub_option(|o| {
+        o.name("name")
+            .description("The name of the persona")
+            .kind(CommandOptionType::String)
+            .max_length(MAX_NAME_LENGTH)
+            .set_autocomplete(autocomplete)
+            .required(true)
+    })
+}
+
+pub struct PersonaCommand;
+
+#[async_trait]
+impl TugboatCommand for PersonaCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No persona subcommand given"))?;
+        let settings = get_settings_from_ctx(ctx).await;
+        let name = get_string_option(&subcommand.options, "name")
+            .map(str::trim)
+            .unwrap_or_default();
+        let key = persona_key(name);
+
+        match subcommand.name.as_str() {
+            "set" => {
+                if key.is_empty() {
+                    return Ok("Personas need a name.".into());
+                }
+                let text = |option: &str| {
+                    get_string_option(&subcommand.options, option)
+                        .map(str::trim)
+                        .filter(|v| !v.is_empty())
+                        .map(str::to_owned)
+                };
+                let voice = VoiceOptions {
+                    language: text("language"),
+                    gender: text("gender"),
+                    name: text("voice"),
+                };
+
+                // make sure we can actually find a voice that fits before saving it.
+                if !voice.is_empty() {
+                    let valid = {
+                        let data = ctx.data.read().await;
+                        let voices = data.get::<Voices>().expect("Should have been voices here");
+                        voice.resolve(voices, None)
+                    };
+                    if let Err(e) = valid {
+                        return Ok(format!("I can't use that voice: {}", e).into());
+                    }
+                }
+
+                let existing = settings.get(guild.id).await?.personas;
+                if !existing.contains_key(&key) && existing.len() >= MAX_PERSONAS {
+                    return Ok(format!(
+                        "This server already has {} personas, delete some first.",
+                        MAX_PERSONAS
+                    )
+                    .into());
+                }
+
+                let persona = Persona {
+                    name: name.to_owned(),
+                    voice,
+                    prosody: Prosody {
+                        speaking_rate: get_number_option(&subcommand.options, "rate"),
+                        pitch: get_number_option(&subcommand.options, "pitch"),
+                        effects_profile: text("effects"),
+                    },
+                    prefix: text("prefix"),
+                    suffix: text("suffix"),
+                    // substitutions are managed on their own, so changing anything else keeps them.
+                    substitutions: existing
+                        .get(&key)
+                        .map(|p| p.substitutions.clone())
+                        .unwrap_or_default(),
+                };
+                let description = describe_persona(&persona);
+                settings
+                    .update(guild.id, |s| s.personas.insert(key, persona))
+                    .await?;
+
+                Ok(format!("Saved: {}.", description).into())
+            }
+            "substitute" => {
+                let word = get_string_option(&subcommand.options, "word")
+                    .map(persona_key)
+                    .filter(|w| !w.is_empty())
+                    .ok_or_else(|| anyhow!("word option is required"))?;
+                let replacement = get_string_option(&subcommand.options, "replacement")
+                    .map(str::trim)
+                    .filter(|r| !r.is_empty())
+                    .map(str::to_owned);
+
+                let response = settings
+                    .update(guild.id, |s| {
+                        let persona = match s.personas.get_mut(&key) {
+                            Some(p) => p,
+                            None => return format!("There's no persona called `{}`.", name),
+                        };
+                        match replacement {
+                            None => match persona.substitutions.remove(&word) {
+                                Some(_) => {
+                                    format!(
+                                        "`{}` will say `{}` normally again.",
+                                        persona.name, word
+                                    )
+                                }
+                                None => {
+                                    format!(
+                                        "`{}` doesn't say `{}` differently.",
+                                        persona.name, word
+                                    )
+                                }
+                            },
+                            Some(_)
+                                if !persona.substitutions.contains_key(&word)
+                                    && persona.substitutions.len() >= MAX_SUBSTITUTIONS =>
+                            {
+                                format!(
+                                    "`{}` already says {} words differently, remove some first.",
+                                    persona.name, MAX_SUBSTITUTIONS
+                                )
+                            }
+                            Some(replacement) => {
+                                let response = format!(
+                                    "`{}` will say \"{}\" instead of `{}`.",
+                                    persona.name, replacement, word
+                                );
+                                persona.substitutions.insert(word, replacement);
+                                response
+                            }
+                        }
+                    })
+                    .await?;
+
+                Ok(response.into())
+            }
+            "delete" => {
+                let removed = settings
+                    .update(guild.id, |s| s.personas.remove(&key))
+                    .await?;
+                Ok(match removed {
+                    Some(p) => format!("Deleted `{}`.", p.name),
+                    None => format!("There's no persona called `{}`.", name),
+                }
+                .into())
+            }
+            "list" => {
+                let s = settings.get(guild.id).await?;
+                if s.personas.is_empty() {
+                    return Ok("This server doesn't have any personas yet.".into());
+                }
+
+                let mut response = String::new();
+                for persona in s.personas.values() {
+                    let line = format!("{}\n", describe_persona(persona));
+                    if response.len() + line.len() > MAX_LIST_LENGTH {
+                        response.push_str("…and more.");
+                        break;
+                    }
+                    response.push_str(&line);
+                }
+
+                Ok(response.into())
+            }
+            other => Err(anyhow!("Unknown persona subcommand {}", other)),
+        }
+    }
+
+    async fn autocomplete(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        match (
+            guild,
+            options.first().and_then(|s| get_focused_option(&s.options)),
+        ) {
+            (Some(guild), Some(("name", typed))) => persona_choices(ctx, guild.id, typed).await,
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("persona")
+            .description("Define characters the bot can speak as with /say")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("set")
+                    .description(
+                        "Create a persona, or change one while keeping its word substitutions",
+                    )
+                    .kind(CommandOptionType::SubCommand);
+                add_name_option(s, true)
+                    .create_sub_option(|o| {
+                        o.name("language")
+                            .description("A language to use (default en-US)")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("gender")
+                            .description("The gender of the voice")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                            .add_string_choice("Male", "MALE")
+                            .add_string_choice("Female", "FEMALE")
+                    })
+                    .create_sub_option(|o| {
+                        o.name("voice")
+                            .description("A specific voice, e.g. en-GB-Wavenet-A")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("rate")
+                            .description("How fast to speak, 1 being normal speed")
+                            .kind(CommandOptionType::Number)
+                            .min_number_value(0.25)
+                            .max_number_value(4.0)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("pitch")
+                            .description("How many semitones to raise or lower the voice by")
+                            .kind(CommandOptionType::Number)
+                            .min_number_value(-20.0)
+                            .max_number_value(20.0)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("effects")
+                            .description(
+                                "What kind of speaker to make it sound like it's played on",
+                            )
+                            .kind(CommandOptionType::String)
+                            .required(false);
+                        for (label, profile) in EFFECTS_PROFILES {
+                            o.add_string_choice(label, profile);
+                        }
+                        o
+                    })
+                    .create_sub_option(|o| {
+                        o.name("prefix")
+                            .description("Something to say before everything else")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("suffix")
+                            .description("Something to say after everything else")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("substitute")
+                    .description("Have a persona say a word differently, or normally again")
+                    .kind(CommandOptionType::SubCommand);
+                add_name_option(s, true)
+                    .create_sub_option(|o| {
+                        o.name("word")
+                            .description("The word to replace, matched regardless of case")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("replacement")
+                            .description(
+                                "What to say instead. Leave out to say the word normally again",
+                            )
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("delete")
+                    .description("Delete a persona")
+                    .kind(CommandOptionType::SubCommand);
+                add_name_option(s, true)
+            })
+            .create_sub_option(|s| {
+                s.name("list")
+                    .description("List this server's personas")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("persona")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+
+    fn preconditions_for(&self, options: &[CommandDataOption]) -> Vec<Precondition> {
+        match options.first().map(|s| s.name.as_str()) {
+            Some("list") => self.preconditions(),
+            _ => vec![
+                Precondition::Guild,
+                Precondition::Permission(Permissions::MANAGE_GUILD),
+            ],
+        }
+    }
+}
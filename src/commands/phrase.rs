@@ -0,0 +1,329 @@
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{
+    get_focused_option, get_string_option,
+    join::join_if_needed,
+    say::{add_effects_option, add_say_options, effect_choices, SayOptions},
+    CommandResponse, Precondition, TugboatCommand,
+};
+use crate::{
+    audio::effects::parse_chain,
+    phrases::{get_phrases_from_ctx, phrase_key, Phrase, PhraseChange},
+    settings::get_settings_from_ctx,
+    speech::{play, prepare, synthesize, AudioFormat, Speech},
+    text::filter::{self, redact, FilterOutcome},
+};
+
+/// Each phrase keeps its audio on disk, so don't let a guild save an unbounded number.
+const MAX_PHRASES: usize = 100;
+const MAX_NAME_LENGTH: u16 = 32;
+/// Leave some room under Discord's 2000 character limit.
+const MAX_LIST_LENGTH: usize = 1800;
+/// How much of each phrase's text to show when listing them.
+const MAX_PHRASE_PREVIEW: usize = 80;
+
+/// Add the `name` option every subcommand other than `list` takes.
+fn add_name_option(
+    command: &mut CreateApplicationCommandOption,
+    autocomplete: bool,
+) -> &mut CreateApplicationCommandOption {
+    command.create_sub_option(|o| {
+        o.name("name")
+            .description("The name of the phrase")
+            .kind(CommandOptionType::String)
+            .max_length(MAX_NAME_LENGTH)
+            .set_autocomplete(autocomplete)
+            .required(true)
+    })
+}
+
+pub struct PhraseCommand;
+
+#[async_trait]
+impl TugboatCommand for PhraseCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No phrase subcommand given"))?;
+        let store = get_phrases_from_ctx(ctx).await;
+        let name = get_string_option(&subcommand.options, "name")
+            .map(str::trim)
+            .unwrap_or_default();
+        let key = phrase_key(name);
+
+        match subcommand.name.as_str() {
+            "save" => {
+                if name.is_empty() {
+                    return Ok("Phrases need a name.".into());
+                }
+                let say = match SayOptions::from_options(&subcommand.options) {
+                    Some(s) => s,
+                    None => return Ok("Must supply a string with at least one character".into()),
+                };
+
+                let book = store.get(guild.id).await?;
+                match book.phrases.get(&key) {
+                    Some(p) if p.locked => {
+                        return Ok(format!("`{}` is locked, so it can't be changed.", p.name).into())
+                    }
+                    None if book.phrases.len() >= MAX_PHRASES => {
+                        return Ok(format!(
+                            "This server already has {} phrases, delete some first.",
+                            MAX_PHRASES
+                        )
+                        .into())
+                    }
+                    _ => {}
+                }
+
+                // synthesize it once now, so that playing it never has to.
+                let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+                let utterance = say.into_utterance(Some(&settings), user);
+                let prepared = match prepare(ctx, guild.id, &settings, &utterance).await {
+                    Some(p) if !p.text.trim().is_empty() => p,
+                    _ => return Ok("I'm not allowed to say that on this server.".into()),
+                };
+                let speech = synthesize(ctx, &prepared, AudioFormat::Wav).await?;
+
+                let phrase = Phrase {
+                    name: name.to_owned(),
+                    text: utterance.text,
+                    voice: speech.voice,
+                    created_by: user.id,
+                    locked: false,
+                };
+                Ok(match store.save(guild.id, phrase, &speech.audio).await? {
+                    PhraseChange::Locked => {
+                        format!("`{}` is locked, so it can't be changed.", name)
+                    }
+                    _ => format!("Saved `{}`.", name),
+                }
+                .into())
+            }
+            "play" => {
+                let channel_id = channel_id.context("Voice channel precondition not met")?;
+                let chain = match get_string_option(&subcommand.options, "effects") {
+                    Some(chain) => match parse_chain(chain) {
+                        Ok(chain) => chain,
+                        Err(e) => return Ok(CommandResponse::Ephemeral(e.to_string())),
+                    },
+                    None => Vec::new(),
+                };
+                let phrase = match store.get(guild.id).await?.phrases.remove(&key) {
+                    Some(p) => p,
+                    None => return Ok(format!("There's no phrase called `{}`.", name).into()),
+                };
+                // the audio was made when it was saved, so it doesn't know about newer filter rules.
+                let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+                let text = filter::strip_substitutions(&phrase.text);
+                if !matches!(
+                    filter::apply(&settings.filter, &text, true),
+                    FilterOutcome::Unchanged
+                ) {
+                    return Ok(CommandResponse::Ephemeral(format!(
+                        "`{}` has something this server's filter blocks now, so I won't play it. Save it again to update it.",
+                        phrase.name
+                    )));
+                }
+
+                let session = match join_if_needed(ctx, &guild, channel_id, user).await? {
+                    Some(s) => s,
+                    None => return Ok("Not in a voice channel right now.".into()),
+                };
+
+                let speech = Speech {
+                    audio: store.audio(guild.id, &key).await?,
+                    voice: phrase.voice,
+                };
+                play(
+                    ctx,
+                    &session,
+                    &settings,
+                    Some(user.id),
+                    &phrase.text,
+                    speech,
+                    &chain,
+                )
+                .await?;
+
+                Ok(format!(
+                    "**{}**: {}",
+                    phrase.name,
+                    redact(&settings.filter, &phrase.text)
+                )
+                .into())
+            }
+            "list" => {
+                let book = store.get(guild.id).await?;
+                if book.phrases.is_empty() {
+                    return Ok("This server doesn't have any phrases yet.".into());
+                }
+
+                let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+                let mut response = String::new();
+                for phrase in book.phrases.values() {
+                    let text = redact(&settings.filter, &phrase.text);
+                    let mut preview = text.chars().take(MAX_PHRASE_PREVIEW).collect::<String>();
+                    if preview.len() < text.len() {
+                        preview.push('…');
+                    }
+                    let line = format!(
+                        "`{}`{}: {}\n",
+                        phrase.name,
+                        if phrase.locked { " (locked)" } else { "" },
+                        preview
+                    );
+
+                    if response.len() + line.len() > MAX_LIST_LENGTH {
+                        response.push_str("…and more.");
+                        break;
+                    }
+                    response.push_str(&line);
+                }
+
+                Ok(response.into())
+            }
+            "delete" => Ok(match store.delete(guild.id, &key).await? {
+                PhraseChange::Done => format!("Deleted `{}`.", name),
+                PhraseChange::Missing => format!("There's no phrase called `{}`.", name),
+                PhraseChange::Locked => format!("`{}` is locked, so it can't be deleted.", name),
+            }
+            .into()),
+            "lock" => {
+                let locked = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "locked")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Boolean(b)) => Some(b),
+                        _ => None,
+                    })
+                    .unwrap_or(true);
+
+                if !store.set_locked(guild.id, &key, locked).await? {
+                    return Ok(format!("There's no phrase called `{}`.", name).into());
+                }
+                Ok(if locked {
+                    format!("Locked `{}`.", name)
+                } else {
+                    format!("Unlocked `{}`.", name)
+                }
+                .into())
+            }
+            other => Err(anyhow!("Unknown phrase subcommand {}", other)),
+        }
+    }
+
+    async fn autocomplete(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        let guild = match guild {
+            Some(g) => g,
+            None => return Ok(Vec::new()),
+        };
+        let typed = match options.first().and_then(|s| get_focused_option(&s.options)) {
+            Some(("name", typed)) => phrase_key(typed),
+            Some(("effects", typed)) => return Ok(effect_choices(typed)),
+            _ => return Ok(Vec::new()),
+        };
+
+        let book = get_phrases_from_ctx(ctx).await.get(guild.id).await?;
+        Ok(book
+            .phrases
+            .iter()
+            .filter(|(key, _)| key.contains(&typed))
+            .map(|(_, p)| (p.name.clone(), p.name.clone()))
+            .collect())
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("phrase")
+            .description("Save things to say often, and play them back instantly")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("save")
+                    .description("Save a phrase, replacing any other with the same name")
+                    .kind(CommandOptionType::SubCommand);
+                add_say_options(add_name_option(s, false))
+            })
+            .create_sub_option(|s| {
+                s.name("play")
+                    .description(
+                        "Say a saved phrase, unless it has something the filter now blocks",
+                    )
+                    .kind(CommandOptionType::SubCommand);
+                add_name_option(s, true).create_sub_option(add_effects_option)
+            })
+            .create_sub_option(|s| {
+                s.name("list")
+                    .description("List this server's phrases")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_sub_option(|s| {
+                s.name("delete")
+                    .description("Delete a phrase")
+                    .kind(CommandOptionType::SubCommand);
+                add_name_option(s, true)
+            })
+            .create_sub_option(|s| {
+                s.name("lock")
+                    .description("Stop a phrase from being changed or deleted, or allow it again")
+                    .kind(CommandOptionType::SubCommand);
+                add_name_option(s, true).create_sub_option(|o| {
+                    o.name("locked")
+                        .description("Whether the phrase should be locked (default true)")
+                        .kind(CommandOptionType::Boolean)
+                        .required(false)
+                })
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("phrase")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+
+    fn preconditions_for(&self, options: &[CommandDataOption]) -> Vec<Precondition> {
+        match options.first().map(|s| s.name.as_str()) {
+            Some("play") => vec![
+                Precondition::Guild,
+                Precondition::UserInVoice,
+                Precondition::BotInSameChannel,
+            ],
+            Some("lock") => vec![
+                Precondition::Guild,
+                Precondition::Permission(Permissions::MANAGE_GUILD),
+            ],
+            _ => self.preconditions(),
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{get_string_option, CommandResponse, Precondition, TugboatCommand};
+use crate::{
+    settings::get_settings_from_ctx,
+    text::pronounce::{dictionary_key, Dictionary, Pronunciation, PronunciationKind},
+};
+
+/// Anything bigger than this can't be a dictionary anyone typed up by hand.
+const MAX_IMPORT_BYTES: u64 = 1024 * 1024;
+/// Leave some room under Discord's 2000 character limit for the rest of the message.
+const MAX_LIST_LENGTH: usize = 1800;
+
+fn describe(word: &str, pronunciation: &Pronunciation) -> String {
+    match pronunciation.kind {
+        PronunciationKind::Text => {
+            format!("`{}` is said as \"{}\"", word, pronunciation.replacement)
+        }
+        kind => format!(
+            "`{}` is pronounced /{}/ ({})",
+            word,
+            pronunciation.replacement,
+            kind.name()
+        ),
+    }
+}
+
+pub struct PronounceCommand;
+
+#[async_trait]
+impl TugboatCommand for PronounceCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No pronounce subcommand given"))?;
+        let settings = get_settings_from_ctx(ctx).await;
+
+        match subcommand.name.as_str() {
+            "add" => {
+                let word = get_string_option(&subcommand.options, "word")
+                    .ok_or_else(|| anyhow!("word option is required"))?;
+                let replacement = get_string_option(&subcommand.options, "replacement")
+                    .ok_or_else(|| anyhow!("replacement option is required"))?;
+                let kind = get_string_option(&subcommand.options, "alphabet")
+                    .and_then(PronunciationKind::from_name)
+                    .unwrap_or_default();
+
+                let key = dictionary_key(word);
+                if key.is_empty() || replacement.trim().is_empty() {
+                    return Ok("Both the word and how to say it need to be filled in.".into());
+                }
+
+                let pronunciation = Pronunciation {
+                    replacement: replacement.trim().to_owned(),
+                    kind,
+                };
+                let description = describe(&key, &pronunciation);
+                settings
+                    .update(guild.id, |s| s.pronunciations.insert(key, pronunciation))
+                    .await?;
+
+                Ok(format!("Got it, {}.", description).into())
+            }
+            "remove" => {
+                let word = get_string_option(&subcommand.options, "word")
+                    .ok_or_else(|| anyhow!("word option is required"))?;
+                let key = dictionary_key(word);
+                let removed = settings
+                    .update(guild.id, |s| s.pronunciations.remove(&key))
+                    .await?;
+
+                Ok(match removed {
+                    Some(_) => format!("I'll go back to saying `{}` my own way.", key),
+                    None => format!("I don't have a pronunciation for `{}`.", key),
+                }
+                .into())
+            }
+            "list" => {
+                let s = settings.get(guild.id).await?;
+                if s.pronunciations.is_empty() {
+                    return Ok("There aren't any pronunciations on this server yet.".into());
+                }
+
+                let lines = s
+                    .pronunciations
+                    .iter()
+                    .map(|(word, p)| describe(word, p))
+                    .collect::<Vec<_>>();
+                let list = lines.join("\n");
+                if list.len() > MAX_LIST_LENGTH {
+                    return Ok(format!(
+                        "There are {} pronunciations on this server, too many to list here. Use `export` to get all of them.",
+                        lines.len()
+                    )
+                    .into());
+                }
+
+                Ok(list.into())
+            }
+            "export" => {
+                let s = settings.get(guild.id).await?;
+                let data = serde_json::to_vec_pretty(&s.pronunciations)
+                    .context("Could not serialize pronunciations")?;
+
+                Ok(CommandResponse::File {
+                    content: format!(
+                        "Here are all {} pronunciations on this server.",
+                        s.pronunciations.len()
+                    ),
+                    filename: "pronunciations.json".into(),
+                    data,
+                    ephemeral: false,
+                })
+            }
+            "import" => {
+                let attachment = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "file")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Attachment(ref a)) => Some(a),
+                        _ => None,
+                    })
+                    .ok_or_else(|| anyhow!("file option is required"))?;
+
+                if attachment.size > MAX_IMPORT_BYTES {
+                    return Ok("That file is too big to be a pronunciation dictionary.".into());
+                }
+
+                let contents = attachment
+                    .download()
+                    .await
+                    .context("Could not download pronunciation dictionary")?;
+                let imported = match serde_json::from_slice::<Dictionary>(&contents) {
+                    Ok(d) => d,
+                    Err(e) => {
+                        return Ok(format!(
+                            "That doesn't look like an exported pronunciation dictionary: {}",
+                            e
+                        )
+                        .into())
+                    }
+                };
+
+                let imported = imported
+                    .into_iter()
+                    .map(|(word, p)| (dictionary_key(&word), p))
+                    .filter(|(word, p)| !word.is_empty() && !p.replacement.trim().is_empty())
+                    .collect::<Dictionary>();
+                let count = imported.len();
+                settings
+                    .update(guild.id, |s| s.pronunciations.extend(imported))
+                    .await?;
+
+                Ok(format!("Imported {} pronunciations.", count).into())
+            }
+            other => Err(anyhow!("Unknown pronounce subcommand {}", other)),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("pronounce")
+            .description("Teach the bot how to say words it gets wrong")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("add")
+                    .description("Add or change how a word is pronounced")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("word")
+                            .description(
+                                "The word to pronounce differently, matched regardless of case",
+                            )
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("replacement")
+                            .description("What to say instead, or the phoneme to use")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("alphabet")
+                            .description("How the replacement is written (default plain text)")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                            .add_string_choice("Plain text", "text")
+                            .add_string_choice("IPA", "ipa")
+                            .add_string_choice("X-SAMPA", "x-sampa")
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("remove")
+                    .description("Stop pronouncing a word differently")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("word")
+                            .description("The word to remove")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("list")
+                    .description("List every pronunciation on this server")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_sub_option(|s| {
+                s.name("export")
+                    .description("Download this server's pronunciations as a file")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_sub_option(|s| {
+                s.name("import")
+                    .description("Add pronunciations from a previously exported file")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("file")
+                            .description("A file made with export")
+                            .kind(CommandOptionType::Attachment)
+                            .required(true)
+                    })
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("pronounce")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+
+    fn preconditions_for(&self, options: &[CommandDataOption]) -> Vec<Precondition> {
+        match options.first().map(|s| s.name.as_str()) {
+            // the dictionary changes how the bot speaks for everyone on the server.
+            Some("add" | "remove" | "import") => vec![
+                Precondition::Guild,
+                Precondition::Permission(Permissions::MANAGE_GUILD),
+            ],
+            _ => self.preconditions(),
+        }
+    }
+}
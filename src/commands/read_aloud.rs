@@ -0,0 +1,82 @@
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    client::Context,
+    model::{channel::Message, guild::Guild, user::User},
+};
+use songbird::id::ChannelId;
+
+use super::{join::join_if_needed, CommandResponse, Precondition, TugboatMessageCommand};
+use crate::{
+    speech::{speak, Utterance},
+    text::normalize::user_name,
+};
+
+pub struct ReadAloudCommand;
+
+#[async_trait]
+impl TugboatMessageCommand for ReadAloudCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        message: &Message,
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let channel_id = channel_id.context("Voice channel precondition not met")?;
+
+        if message.content.trim().is_empty() {
+            return Ok("That message doesn't have any text to read.".into());
+        }
+
+        let session = match join_if_needed(ctx, &guild, channel_id, user).await? {
+            Some(s) => s,
+            None => return Ok("Not in a voice channel right now.".into()),
+        };
+        let settings = session.settings().await?;
+
+        // read it the way the author would have sounded, not the person asking.
+        let utterance = Utterance {
+            text: message.content.clone(),
+            is_ssml: false,
+            voice: settings
+                .voice_profiles
+                .get(&message.author.id)
+                .cloned()
+                .unwrap_or_default(),
+            voice_seed: Some(message.author.id.0),
+            requester: Some(user.id),
+            prosody: Default::default(),
+            effects: Vec::new(),
+        };
+
+        if speak(ctx, &session, &utterance).await?.is_none() {
+            return Ok("I'm not allowed to say that on this server.".into());
+        }
+
+        Ok(format!(
+            "Reading {}'s message: {}",
+            user_name(ctx, Some(&guild), message.author.id),
+            message.link()
+        )
+        .into())
+    }
+
+    fn get_label(&self) -> String {
+        String::from("Read aloud")
+    }
+
+    fn get_name(&self) -> String {
+        String::from("read-aloud")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::UserInVoice,
+            Precondition::BotInSameChannel,
+        ]
+    }
+}
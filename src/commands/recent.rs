@@ -0,0 +1,90 @@
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType, guild::Guild,
+        prelude::interaction::application_command::CommandDataOption, user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{CommandResponse, Precondition, TugboatCommand};
+use crate::{
+    replay::get_replays_from_ctx,
+    settings::get_settings_from_ctx,
+    text::{filter::redact, normalize::user_name},
+};
+
+/// How much of each utterance's text to show.
+const MAX_PREVIEW: usize = 150;
+/// Leave some room under Discord's 2000 character limit.
+const MAX_LIST_LENGTH: usize = 1800;
+
+pub struct RecentCommand;
+
+#[async_trait]
+impl TugboatCommand for RecentCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        _options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let recent = get_replays_from_ctx(ctx).await.list(guild.id);
+        if recent.is_empty() {
+            return Ok("I haven't said anything lately.".into());
+        }
+
+        let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+
+        let mut response = String::new();
+        for (i, r) in recent.iter().enumerate() {
+            let who = r
+                .user
+                .map(|u| user_name(ctx, Some(&guild), u))
+                .unwrap_or_else(|| "Nobody in particular".into());
+            let text = redact(&settings.filter, &r.text);
+            let mut preview = text.chars().take(MAX_PREVIEW).collect::<String>();
+            if preview.len() < text.len() {
+                preview.push('…');
+            }
+            let line = format!(
+                "{}. <t:{}:R> **{}** ({}): {}\n",
+                i + 1,
+                r.timestamp.unix_timestamp(),
+                who,
+                r.voice,
+                preview
+            );
+
+            if response.len() + line.len() > MAX_LIST_LENGTH {
+                response.push_str("…and more.");
+                break;
+            }
+            response.push_str(&line);
+        }
+
+        Ok(response.into())
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("recent")
+            .description("List the last few things the bot said, to pick one to replay")
+            .kind(CommandOptionType::SubCommand)
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("recent")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType, guild::Guild,
+        prelude::interaction::application_command::CommandDataOption, user::User, Timestamp,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{
+    get_focused_option, get_string_option,
+    persona::persona_choices,
+    say::{add_effects_option, add_persona_option, add_say_options, effect_choices, SayOptions},
+    CommandResponse, Precondition, TugboatCommand,
+};
+use crate::{
+    audio::{effects, loudness, Pcm},
+    audit::{self, AuditEntry},
+    settings::get_settings_from_ctx,
+    speech::{prepare, synthesize, synthesize_script, AudioFormat},
+    text::filter::redact,
+};
+
+/// How much of the text to show alongside the file.
+const MAX_PREVIEW: usize = 150;
+
+pub struct RenderCommand;
+
+#[async_trait]
+impl TugboatCommand for RenderCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let say = match SayOptions::from_options(options) {
+            Some(s) => s,
+            None => return Ok("Must supply a string with at least one character".into()),
+        };
+        let format = match get_string_option(options, "format") {
+            Some("mp3") => AudioFormat::Mp3,
+            Some("ogg") => AudioFormat::OggOpus,
+            _ => AudioFormat::Wav,
+        };
+
+        // outside of a guild there's no settings to apply, so the text is used as is.
+        let settings = match guild {
+            Some(ref g) => Some(get_settings_from_ctx(ctx).await.get(g.id).await?),
+            None => None,
+        };
+        let script = match say.check(settings.as_ref()) {
+            Ok(script) => script,
+            Err(e) => return Ok(CommandResponse::Ephemeral(e)),
+        };
+        // what was asked for, rather than what the persona made of it.
+        let say_text = say.message.clone();
+        let utterance = say.into_utterance(settings.as_ref(), user);
+        // joining clips and running effects on them is only something we can do to WAV.
+        if format != AudioFormat::Wav && (script.is_some() || !utterance.effects.is_empty()) {
+            return Ok(CommandResponse::Ephemeral(
+                "Scripts and effects can only be rendered as WAV.".into(),
+            ));
+        }
+
+        let guild_settings = guild.as_ref().map(|g| g.id).zip(settings.as_ref());
+        let speech = match script {
+            Some(ref segments) => {
+                synthesize_script(ctx, guild_settings, &utterance, segments).await?
+            }
+            None => {
+                let prepared = match guild_settings {
+                    Some((guild_id, s)) => prepare(ctx, guild_id, s, &utterance)
+                        .await
+                        .filter(|p| !p.text.trim().is_empty()),
+                    None => Some(utterance.clone()),
+                };
+                match prepared {
+                    Some(p) => Some(synthesize(ctx, &p, format).await?),
+                    None => None,
+                }
+            }
+        };
+        let mut speech = match speech {
+            Some(s) => s,
+            None => return Ok("I'm not allowed to say that on this server.".into()),
+        };
+
+        // match what playing it would sound like, other than the chime and gap.
+        if format == AudioFormat::Wav {
+            let audio = effects::apply(&speech.audio.into(), &utterance.effects).await?;
+            let mut pcm = Pcm::from_wav(&audio).context("Could not read synthesized audio")?;
+            if let Some(s) = settings.as_ref() {
+                let loudness = s.loudness.clone();
+                pcm = tokio::task::spawn_blocking(move || {
+                    loudness::level(&mut pcm, &loudness);
+                    pcm
+                })
+                .await?;
+            }
+            speech.audio = pcm.to_wav();
+        }
+
+        if let Some((guild_id, s)) = guild_settings {
+            let entry = AuditEntry {
+                timestamp: Timestamp::now(),
+                guild_id,
+                user: Some(user.id),
+                channel: None,
+                text: say_text.clone(),
+                voice: speech.voice,
+            };
+            if let Err(e) = audit::record(ctx, guild_id, s, entry).await {
+                tracing::error!(
+                    ?e,
+                    ?guild_id,
+                    "Could not record rendered utterance in the audit log"
+                );
+            }
+        }
+
+        let text = match settings {
+            Some(ref s) => redact(&s.filter, &say_text),
+            None => say_text,
+        };
+        let mut preview = text.chars().take(MAX_PREVIEW).collect::<String>();
+        if preview.len() < text.len() {
+            preview.push('…');
+        }
+        Ok(CommandResponse::File {
+            content: preview,
+            filename: format!("speech.{}", format.extension()),
+            data: speech.audio,
+            ephemeral: false,
+        })
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        let mut command = CreateApplicationCommandOption::default();
+        command
+            .name("render")
+            .description("Get what the bot would say as an audio file, without playing it")
+            .kind(CommandOptionType::SubCommand);
+        add_say_options(&mut command)
+            .create_sub_option(add_persona_option)
+            .create_sub_option(add_effects_option)
+            .create_sub_option(|o| {
+                o.name("format")
+                    .description("The audio format (default WAV)")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+                    .add_string_choice("WAV", "wav")
+                    .add_string_choice("MP3", "mp3")
+                    .add_string_choice("OGG/Opus", "ogg")
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("render")
+    }
+
+    async fn autocomplete(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        match (guild, get_focused_option(options)) {
+            (Some(guild), Some(("persona", typed))) => persona_choices(ctx, guild.id, typed).await,
+            (_, Some(("effects", typed))) => Ok(effect_choices(typed)),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![]
+    }
+}
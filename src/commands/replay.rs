@@ -0,0 +1,133 @@
+use std::convert::TryFrom;
+
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{
+    get_focused_option, get_string_option,
+    say::{add_effects_option, effect_choices},
+    CommandResponse, Precondition, TugboatCommand,
+};
+use crate::{
+    audio::effects::{self, describe_chain, parse_chain},
+    replay::{get_replays_from_ctx, RECENT_UTTERANCES},
+    session::get_sessions_from_ctx,
+    settings::get_settings_from_ctx,
+    speech::enqueue_audio,
+    text::filter::redact,
+};
+
+/// How much of the replayed text to show.
+const MAX_PREVIEW: usize = 150;
+
+pub struct ReplayCommand;
+
+#[async_trait]
+impl TugboatCommand for ReplayCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let n = options
+            .iter()
+            .find(|o| o.name == "n")
+            .and_then(|o| match o.resolved {
+                Some(CommandDataOptionValue::Integer(i)) => usize::try_from(i).ok(),
+                _ => None,
+            })
+            .unwrap_or(1);
+        let chain = match get_string_option(options, "effects").map(parse_chain) {
+            Some(Ok(chain)) => Some(chain),
+            Some(Err(e)) => return Ok(CommandResponse::Ephemeral(e.to_string())),
+            None => None,
+        };
+
+        let session = match get_sessions_from_ctx(ctx).await.get(guild.id).await {
+            Some(s) => s,
+            None => return Ok("Not in a voice channel right now.".into()),
+        };
+        let recent = match get_replays_from_ctx(ctx).await.get(guild.id, n) {
+            Some(r) => r,
+            None => return Ok("I don't remember saying that much.".into()),
+        };
+
+        // the audio is already synthesized, so this doesn't go through the TTS API again.
+        let chain = chain.unwrap_or(recent.effects);
+        enqueue_audio(ctx, &session, &effects::apply(&recent.audio, &chain).await?).await?;
+
+        let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+        let text = redact(&settings.filter, &recent.text);
+        let mut preview = text.chars().take(MAX_PREVIEW).collect::<String>();
+        if preview.len() < text.len() {
+            preview.push('…');
+        }
+
+        Ok(if chain.is_empty() {
+            format!("Replaying: {}", preview)
+        } else {
+            format!("Replaying with {}: {}", describe_chain(&chain), preview)
+        }
+        .into())
+    }
+
+    async fn autocomplete(
+        &self,
+        _ctx: &Context,
+        options: &[CommandDataOption],
+        _guild: Option<Guild>,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(match get_focused_option(options) {
+            Some(("effects", typed)) => effect_choices(typed),
+            _ => Vec::new(),
+        })
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("replay")
+            .description("Say something again")
+            .kind(CommandOptionType::SubCommand)
+            .create_sub_option(|o| {
+                o.name("n")
+                    .description("How far back to go, 1 being the last thing said (default 1)")
+                    .kind(CommandOptionType::Integer)
+                    .min_int_value(1)
+                    .max_int_value(RECENT_UTTERANCES as u64)
+                    .required(false)
+            })
+            .create_sub_option(|o| {
+                add_effects_option(o).description(
+                    "Different effects to replay it with. Leave out to use the same ones",
+                )
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("replay")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::UserInVoice,
+            Precondition::BotInSameChannel,
+        ]
+    }
+}
@@ -1,37 +1,38 @@
-use std::{
-    collections::HashMap,
-    io::Write,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
-    },
-};
+use std::collections::HashMap;
 
 use anyhow::{anyhow, Context as anyhowContext};
-use base64::{engine::general_purpose, Engine as _};
-use google_texttospeech1::{
-    api::{AudioConfig, SynthesisInput, SynthesizeSpeechRequest, Voice, VoiceSelectionParams},
-    hyper_rustls::HttpsConnector,
-    Texttospeech,
-};
+use google_texttospeech1::{api::Voice, hyper_rustls::HttpsConnector, Texttospeech};
 use hyper::client::HttpConnector;
 use serde_json::Value;
 use serenity::{
     async_trait,
-    builder::CreateApplicationCommandOption,
+    builder::{CreateApplicationCommandOption, CreateComponents},
     client::Context,
     model::{
-        application::command::CommandOptionType, guild::Guild,
+        application::{command::CommandOptionType, component::ButtonStyle},
+        guild::Guild,
+        id::GuildId,
         prelude::interaction::application_command::CommandDataOption,
+        user::User,
     },
     prelude::TypeMapKey,
 };
-use songbird::{create_player, id::ChannelId, Event, EventContext, TrackEvent};
-use songbird::{events::EventHandler as VoiceEventHandler, id::GuildId};
+use songbird::id::ChannelId;
 
-use crate::commands::{get_songbird_from_ctx, IdleDurations, NOT_IN_SAME_VOICE_CHANNEL_MESSAGE};
+use crate::{
+    audio::effects::{self, complete_chain, parse_chain, Effect, EffectError},
+    persona::{persona_key, Persona},
+    replay::get_replays_from_ctx,
+    session::get_sessions_from_ctx,
+    settings::{get_settings_from_ctx, GuildSettings},
+    speech::{enqueue_audio, speak, speak_script, Utterance, VoiceOptions},
+    text::script::{self, Segment},
+};
 
-use super::{CommandsMap, TugboatCommand};
+use super::{
+    get_focused_option, join::join_if_needed, persona::persona_choices, say_long::say_modal,
+    CommandResponse, Precondition, TugboatCommand,
+};
 
 pub struct TtsService;
 impl TypeMapKey for TtsService {
@@ -44,240 +45,141 @@ impl TypeMapKey for Voices {
     type Value = VoiceValues;
 }
 
-struct TrackCleanup {
-    idle_tracking: Arc<AtomicUsize>,
-    /// Unused, we want to tie the lifetimes together so that the temp file is cleaned up.
-    _tmpfile: tempfile::NamedTempFile,
+/// What someone asked to have said, and how. Shared by every command that takes the
+/// same options as `say`.
+pub(crate) struct SayOptions {
+    pub message: String,
+    pub voice: VoiceOptions,
+    /// The name of one of the guild's personas to speak as.
+    pub persona: Option<String>,
+    /// A chain of effects to run on the audio, as typed.
+    pub effects: Option<String>,
 }
 
-#[async_trait]
-impl VoiceEventHandler for TrackCleanup {
-    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
-        // reset the idle tracker for this guild.
-        self.idle_tracking.store(0, Ordering::SeqCst);
-        None
+impl SayOptions {
+    /// Returns `None` if there was no message to say.
+    pub fn from_options(options: &[CommandDataOption]) -> Option<Self> {
+        let mut message = None;
+        let mut voice = VoiceOptions::default();
+        let mut persona = None;
+        let mut effects = None;
+        for option in options {
+            let value = option.value.as_ref().and_then(|v| match v {
+                Value::String(s) => Some(s.to_owned()),
+                _ => None,
+            });
+            match option.name.as_str() {
+                "message" => message = value,
+                "language" => voice.language = value,
+                "gender" => voice.gender = value,
+                "persona" => persona = value.filter(|p| !p.trim().is_empty()),
+                "effects" => effects = value,
+                _ => continue,
+            }
+        }
+
+        message.filter(|m| !m.is_empty()).map(|message| Self {
+            message,
+            voice,
+            persona,
+            effects,
+        })
     }
-}
 
-pub struct SayCommand;
+    /// Read the options out of a submitted modal, keyed by each field's custom ID. Blank
+    /// fields are treated as not given. Returns `None` if there was no message to say.
+    pub fn from_fields(fields: &HashMap<String, String>) -> Option<Self> {
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty())
+                .map(str::to_owned)
+        };
+        let voice = VoiceOptions {
+            language: field("language"),
+            gender: field("gender")
+                .map(|g| g.to_uppercase())
+                .filter(|g| g == "MALE" || g == "FEMALE"),
+            name: field("voice"),
+        };
 
-#[async_trait]
-impl TugboatCommand for SayCommand {
-    fn get_name(&self) -> String {
-        String::from("say")
+        field("message").map(|message| Self {
+            message,
+            voice,
+            persona: field("persona"),
+            effects: None,
+        })
     }
 
-    async fn execute(
-        &self,
-        ctx: &Context,
-        options: &[CommandDataOption],
-        guild: Guild,
-        channel_id: ChannelId,
-    ) -> anyhow::Result<String> {
-        let manager = get_songbird_from_ctx(ctx).await;
-        // if we're not in a voice channel for this guild, join the channel.
-        // if we're in another voice channel in the same guild, deny the say with a message.
-        match manager.get(guild.id) {
-            Some(s) => {
-                let r = s.lock().await;
-                let c = r.current_channel().expect("there should be a channel here");
-                if c != channel_id {
-                    return Ok(NOT_IN_SAME_VOICE_CHANNEL_MESSAGE.into());
-                }
-            }
-            None => {
-                // we are not in a voice channel for this guild, join one.
-                let join_command = {
-                    let data = ctx.data.read().await;
-                    data.get::<CommandsMap>()
-                        .expect("Should have been commands here")
-                        .get("join")
-                        .expect("There should always be a join command")
-                        .clone()
-                };
-
-                join_command
-                    .execute(ctx, options, guild.clone(), channel_id)
-                    .await?;
-            }
-        }
+    /// The guild persona these options ask to speak as, if it exists.
+    pub fn persona<'a>(&self, settings: Option<&'a GuildSettings>) -> Option<&'a Persona> {
+        let name = self.persona.as_deref()?;
+        settings?.personas.get(&persona_key(name))
+    }
 
-        let (message, language, gender) = {
-            let mut m = None;
-            let mut l = None;
-            let mut g = None;
-            for option in options {
-                match option.name.as_str() {
-                    "message" => {
-                        m = option.value.as_ref().and_then(|v| match v {
-                            Value::String(s) => Some(s.to_owned()),
-                            _ => None,
-                        });
-                    }
-                    "language" => {
-                        l = option.value.as_ref().and_then(|v| match v {
-                            Value::String(s) => Some(s.to_owned()),
-                            _ => None,
-                        });
-                    }
-                    "gender" => {
-                        g = option.value.as_ref().and_then(|v| match v {
-                            Value::String(s) => Some(s.to_owned()),
-                            _ => None,
-                        });
-                    }
-                    _ => continue,
-                }
-            }
+    /// The effects these options ask for, or why they can't be used.
+    pub fn effects(&self) -> Result<Vec<Effect>, EffectError> {
+        match self.effects {
+            Some(ref chain) => parse_chain(chain),
+            None => Ok(Vec::new()),
+        }
+    }
 
-            (m, l, g)
-        };
+    /// Check that the persona and effects asked for can be used, and parse the message as a
+    /// dialogue script if it is one, spoken by the persona. Otherwise returns why not, to
+    /// show to whoever asked.
+    pub fn check(&self, settings: Option<&GuildSettings>) -> Result<Option<Vec<Segment>>, String> {
+        let persona = self.persona(settings);
+        if let (Some(name), None) = (self.persona.as_deref(), persona) {
+            return Err(format!(
+                "There's no persona called `{}` on this server.",
+                name.trim()
+            ));
+        }
+        self.effects().map_err(|e| e.to_string())?;
 
-        let message = match message {
-            Some(m) => {
-                if m.is_empty() {
-                    return Ok("Must supply a string with at least one character".into());
-                } else {
-                    m
-                }
-            }
-            None => return Ok("Must supply a string with at least one character".into()),
-        };
+        let script = script::parse(&self.message).map_err(|e| e.to_string())?;
+        Ok(script.map(|s| match persona {
+            Some(p) => p.transform_script(s),
+            None => s,
+        }))
+    }
 
-        let language_code = language.unwrap_or_else(|| "en-US".to_owned());
-
-        let res = {
-            let data = ctx.data.read().await;
-            let voices = data
-                .get::<Voices>()
-                .expect("There should have been voices here.")
-                .get(&language_code)
-                .context("No voices found for this language code!")?
-                .iter()
-                .filter_map(|v| match gender {
-                    // if the gender is present, only filter out voices that
-                    // have that same gender. otherwise, return all voices.
-                    Some(ref g) => {
-                        if g == v
-                            .ssml_gender
-                            .as_ref()
-                            .expect("Should have been a gender here")
-                            .as_str()
-                        {
-                            Some(
-                                v.name
-                                    .as_ref()
-                                    .expect("Should have been a name here")
-                                    .clone(),
-                            )
-                        } else {
-                            None
-                        }
-                    }
-                    None => Some(
-                        v.name
-                            .as_ref()
-                            .expect("Should have been a name here")
-                            .clone(),
-                    ),
-                })
-                .collect::<Vec<_>>();
-
-            let voice = voices[fastrand::usize(..voices.len())].clone();
-
-            let tts_service = data
-                .get::<TtsService>()
-                .expect("There should have been a TTS service here.");
-
-            let req = SynthesizeSpeechRequest {
-                audio_config: Some(AudioConfig {
-                    audio_encoding: Some("LINEAR16".to_string()),
-                    effects_profile_id: None,
-                    pitch: Some(0.0),
-                    sample_rate_hertz: None,
-                    speaking_rate: None,
-                    volume_gain_db: None,
-                }),
-                input: Some(SynthesisInput {
-                    ssml: Some(format!("<speak>{}</speak>", message)),
-                    text: None,
-                }),
-                voice: Some(VoiceSelectionParams {
-                    language_code: Some(language_code),
-                    name: Some(voice),
-                    ssml_gender: None,
-                }),
-            };
-
-            let (_, res) = tts_service
-                .text()
-                .synthesize(req)
-                .doit()
-                .await
-                .context("Could not make TTS API call")?;
-
-            match res.audio_content {
-                Some(c) => general_purpose::STANDARD
-                    .decode(c)
-                    .context("Could not decode base64 audio content!")?,
-                None => return Err(anyhow!("No audio content returned from API!")),
-            }
+    /// Build the utterance to say on behalf of `user`. Effects that can't be parsed are left
+    /// off, so check them with `effects` first. If they didn't ask for a voice in
+    /// particular, their persona's voice is used, and failing that their own voice profile.
+    pub fn into_utterance(self, settings: Option<&GuildSettings>, user: &User) -> Utterance {
+        let persona = self.persona(settings);
+        let effects = self.effects().unwrap_or_default();
+        let profile = settings.and_then(|s| s.voice_profiles.get(&user.id));
+        let voice = match (persona, profile) {
+            _ if !self.voice.is_empty() => self.voice,
+            (Some(p), _) if !p.voice.is_empty() => p.voice.clone(),
+            (_, Some(profile)) => profile.clone(),
+            _ => self.voice,
         };
 
-        if let Some(handler_lock) = manager.get(guild.id) {
-            let mut file = tempfile::NamedTempFile::new()?;
-            file.write_all(&res)?;
-
-            let input = songbird::ffmpeg(file.path())
-                .await
-                .map_err(|_| anyhow!("Could not create ffmpeg player"))?;
-
-            let (track, track_handle) = create_player(input);
-
-            let maybe_idle_tracking = {
-                ctx.data
-                    .read()
-                    .await
-                    .get::<IdleDurations>()
-                    .expect("idle duration should be present")
-                    .get(&GuildId::from(guild.id))
-                    .cloned()
-            };
-
-            match maybe_idle_tracking {
-                Some(c) => {
-                    track_handle.add_event(
-                        Event::Track(TrackEvent::End),
-                        TrackCleanup {
-                            _tmpfile: file,
-                            idle_tracking: c,
-                        },
-                    )?;
-                }
-                None => {
-                    file.close()?;
-                    return Err(anyhow!(
-                    "Unexpected error. Please contact bot admin and tell them \"Blue Rhinoceros\""
-                        ));
-                }
-            }
-
-            {
-                let mut handler = handler_lock.lock().await;
-                handler.enqueue(track);
-            }
-        } else {
-            return Ok("Not in a voice channel right now.".into());
+        Utterance {
+            text: match persona {
+                Some(p) => p.transform(&self.message, true),
+                None => self.message,
+            },
+            is_ssml: true,
+            voice,
+            voice_seed: None,
+            requester: Some(user.id),
+            prosody: persona.map(|p| p.prosody.clone()).unwrap_or_default(),
+            effects,
         }
-
-        Ok(message)
     }
+}
 
-    fn create_command(&self) -> CreateApplicationCommandOption {
-        CreateApplicationCommandOption::default()
-        .name("say")
-        .description("Say something into the voice channel you are currently in")
-        .kind(CommandOptionType::SubCommand)
+/// Add the options `say` takes to a subcommand.
+pub(crate) fn add_say_options(
+    command: &mut CreateApplicationCommandOption,
+) -> &mut CreateApplicationCommandOption {
+    command
         .create_sub_option(|o| {
             o.name("message")
                 .description("What you want the bot to say")
@@ -298,6 +200,255 @@ impl TugboatCommand for SayCommand {
                 .add_string_choice("Male", "MALE")
                 .add_string_choice("Female", "FEMALE")
         })
-        .clone()
+}
+
+/// Set up the `persona` option, which should autocomplete with `persona_choices`.
+pub(crate) fn add_persona_option(
+    option: &mut CreateApplicationCommandOption,
+) -> &mut CreateApplicationCommandOption {
+    option
+        .name("persona")
+        .description("Speak as one of this server's personas")
+        .kind(CommandOptionType::String)
+        .set_autocomplete(true)
+        .required(false)
+}
+
+/// Set up the `effects` option taken by anything that plays speech, which should autocomplete
+/// with `effect_choices`.
+pub(crate) fn add_effects_option(
+    option: &mut CreateApplicationCommandOption,
+) -> &mut CreateApplicationCommandOption {
+    option
+        .name("effects")
+        .description("Effects to run on the speech, in order, e.g. robot, echo")
+        .kind(CommandOptionType::String)
+        .set_autocomplete(true)
+        .required(false)
+}
+
+/// Autocomplete choices for the `effects` option.
+pub(crate) fn effect_choices(typed: &str) -> Vec<(String, String)> {
+    complete_chain(typed)
+        .into_iter()
+        .map(|chain| (chain.clone(), chain))
+        .collect()
+}
+
+/// Say something in `channel_id` on behalf of `user`, joining it first if we aren't connected
+/// in this guild yet. This is everything `say` does once it has its options.
+pub(crate) async fn say(
+    ctx: &Context,
+    guild: Guild,
+    channel_id: ChannelId,
+    user: &User,
+    say: SayOptions,
+) -> anyhow::Result<CommandResponse> {
+    let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+    let script = match say.check(Some(&settings)) {
+        Ok(script) => script,
+        Err(e) => return Ok(CommandResponse::Ephemeral(e)),
+    };
+
+    // being in another voice channel in the same guild is taken care of by our preconditions.
+    let session = match join_if_needed(ctx, &guild, channel_id, user).await? {
+        Some(s) => s,
+        None => return Ok("Not in a voice channel right now.".into()),
+    };
+
+    let message = say.message.clone();
+    let utterance = say.into_utterance(Some(&settings), user);
+
+    let spoken = match script {
+        Some(ref segments) => speak_script(ctx, &session, &utterance, segments).await?,
+        None => speak(ctx, &session, &utterance).await?,
+    };
+    let id = match spoken {
+        Some(id) => id,
+        None => return Ok("I'm not allowed to say that on this server.".into()),
+    };
+
+    let mut components = CreateComponents::default();
+    components.create_action_row(|r| {
+        r.create_button(|b| {
+            b.custom_id(Button::Replay.custom_id(guild.id, id))
+                .label("Replay")
+                .style(ButtonStyle::Primary)
+        })
+        .create_button(|b| {
+            b.custom_id(Button::Skip.custom_id(guild.id, id))
+                .label("Skip")
+                .style(ButtonStyle::Danger)
+        })
+        .create_button(|b| {
+            b.custom_id(Button::SameVoice.custom_id(guild.id, id))
+                .label("Same voice again")
+                .style(ButtonStyle::Secondary)
+                // a script has as many voices as it has characters.
+                .disabled(script.is_some())
+        })
+    });
+
+    let track = get_replays_from_ctx(ctx)
+        .await
+        .find(guild.id, id)
+        .map(|recent| recent.track);
+    Ok(CommandResponse::Interactive {
+        content: message,
+        components,
+        disable_after: track.map(|t| (t, Button::Skip.custom_id(guild.id, id))),
+    })
+}
+
+/// The buttons attached to what we said. Their custom IDs look like
+/// `say:<button>:<guild id>:<utterance id>`, the utterance ID being the one it's
+/// remembered under in the replay buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Button {
+    Replay,
+    Skip,
+    SameVoice,
+}
+
+impl Button {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Replay => "replay",
+            Self::Skip => "skip",
+            Self::SameVoice => "same-voice",
+        }
+    }
+
+    fn custom_id(self, guild_id: GuildId, id: u64) -> String {
+        format!("say:{}:{}:{}", self.name(), guild_id.0, id)
+    }
+
+    fn parse(custom_id: &str) -> Option<(Self, GuildId, u64)> {
+        let mut parts = custom_id.split(':').skip(1);
+        let button = match parts.next()? {
+            "replay" => Self::Replay,
+            "skip" => Self::Skip,
+            "same-voice" => Self::SameVoice,
+            _ => return None,
+        };
+        let guild_id = GuildId(parts.next()?.parse().ok()?);
+        let id = parts.next()?.parse().ok()?;
+        Some((button, guild_id, id))
+    }
+}
+
+pub struct SayCommand;
+
+#[async_trait]
+impl TugboatCommand for SayCommand {
+    fn get_name(&self) -> String {
+        String::from("say")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::UserInVoice,
+            Precondition::BotInSameChannel,
+        ]
+    }
+
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let channel_id = channel_id.context("Voice channel precondition not met")?;
+        match SayOptions::from_options(options) {
+            Some(say_options) => say(ctx, guild, channel_id, user, say_options).await,
+            None => Ok("Must supply a string with at least one character".into()),
+        }
+    }
+
+    fn permission_name_for(&self, custom_id: &str) -> String {
+        // each button is allowed to whoever could run the command that does the same thing.
+        match Button::parse(custom_id) {
+            Some((Button::Replay, _, _)) => String::from("replay"),
+            Some((Button::Skip, _, _)) => String::from("skip"),
+            _ => self.get_name(),
+        }
+    }
+
+    async fn component(
+        &self,
+        ctx: &Context,
+        custom_id: &str,
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let (button, guild_id, id) =
+            Button::parse(custom_id).ok_or_else(|| anyhow!("Bad say button {}", custom_id))?;
+        if guild_id != guild.id {
+            return Err(anyhow!(
+                "Say button for {:?} clicked in {:?}",
+                guild_id,
+                guild.id
+            ));
+        }
+
+        let recent = get_replays_from_ctx(ctx).await.find(guild_id, id);
+        let session = get_sessions_from_ctx(ctx).await.get(guild_id).await;
+        match (button, recent, session) {
+            // whether it was skipped just now or had already been said, there's nothing left
+            // to skip, so take the button away.
+            (Button::Skip, Some(recent), Some(session)) => {
+                session.skip_track(&recent.track).await?;
+                Ok(CommandResponse::DisableButton)
+            }
+            // once it's been forgotten or we've left, it can't still be playing either.
+            (Button::Skip, _, _) => Ok(CommandResponse::DisableButton),
+            (_, None, _) => Ok(CommandResponse::Ephemeral(
+                "I don't remember saying that anymore.".into(),
+            )),
+            (Button::SameVoice, Some(recent), _) => Ok(say_modal(Some(&recent.voice))),
+            (Button::Replay, Some(_), None) => Ok(CommandResponse::Ephemeral(
+                "Not in a voice channel right now.".into(),
+            )),
+            (Button::Replay, Some(recent), Some(session)) => {
+                enqueue_audio(
+                    ctx,
+                    &session,
+                    &effects::apply(&recent.audio, &recent.effects).await?,
+                )
+                .await?;
+                Ok(CommandResponse::Ephemeral("Replaying.".into()))
+            }
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        let mut command = CreateApplicationCommandOption::default();
+        command
+            .name("say")
+            .description("Say something into the voice channel you are currently in")
+            .kind(CommandOptionType::SubCommand);
+        add_say_options(&mut command)
+            .create_sub_option(add_persona_option)
+            .create_sub_option(add_effects_option)
+            .clone()
+    }
+
+    async fn autocomplete(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        match (guild, get_focused_option(options)) {
+            (Some(guild), Some(("persona", typed))) => persona_choices(ctx, guild.id, typed).await,
+            (_, Some(("effects", typed))) => Ok(effect_choices(typed)),
+            _ => Ok(Vec::new()),
+        }
     }
 }
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    builder::{CreateApplicationCommandOption, CreateComponents},
+    client::Context,
+    model::{
+        application::{command::CommandOptionType, component::InputTextStyle},
+        guild::Guild,
+        prelude::interaction::application_command::CommandDataOption,
+        user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{
+    say::{say, SayOptions},
+    CommandResponse, Precondition, TugboatCommand,
+};
+
+/// Discord's limit on how much can be typed into a single modal field.
+const MAX_MESSAGE_LENGTH: u64 = 4000;
+
+/// The form for writing something for the bot to say, optionally with the voice filled in.
+pub(crate) fn say_modal(voice: Option<&str>) -> CommandResponse {
+    let mut components = CreateComponents::default();
+    components
+        .create_action_row(|r| {
+            r.create_input_text(|t| {
+                t.custom_id("message")
+                    .label("What you want the bot to say")
+                    .style(InputTextStyle::Paragraph)
+                    .max_length(MAX_MESSAGE_LENGTH)
+                    .required(true)
+            })
+        })
+        .create_action_row(|r| {
+            r.create_input_text(|t| {
+                t.custom_id("language")
+                    .label("Language")
+                    .placeholder("en-US")
+                    .style(InputTextStyle::Short)
+                    .required(false)
+            })
+        })
+        .create_action_row(|r| {
+            r.create_input_text(|t| {
+                t.custom_id("gender")
+                    .label("Gender")
+                    .placeholder("Male or Female, picked randomly if left blank")
+                    .style(InputTextStyle::Short)
+                    .required(false)
+            })
+        })
+        .create_action_row(|r| {
+            r.create_input_text(|t| {
+                t.custom_id("voice")
+                    .label("Voice")
+                    .placeholder("A specific voice, e.g. en-GB-Wavenet-A")
+                    .style(InputTextStyle::Short)
+                    .required(false);
+                if let Some(v) = voice {
+                    t.value(v);
+                }
+                t
+            })
+        })
+        .create_action_row(|r| {
+            r.create_input_text(|t| {
+                t.custom_id("persona")
+                    .label("Persona")
+                    .placeholder("One of this server's personas, see /tugboat persona list")
+                    .style(InputTextStyle::Short)
+                    .required(false)
+            })
+        });
+
+    CommandResponse::Modal {
+        custom_id: String::from("say-long"),
+        title: "Say something long".into(),
+        components,
+    }
+}
+
+pub struct SayLongCommand;
+
+#[async_trait]
+impl TugboatCommand for SayLongCommand {
+    async fn execute(
+        &self,
+        _ctx: &Context,
+        _options: &[CommandDataOption],
+        _guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        Ok(say_modal(None))
+    }
+
+    async fn submit(
+        &self,
+        ctx: &Context,
+        _custom_id: &str,
+        fields: &HashMap<String, String>,
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let channel_id = channel_id.context("Voice channel precondition not met")?;
+        match SayOptions::from_fields(fields) {
+            Some(say_options) => say(ctx, guild, channel_id, user, say_options).await,
+            None => Ok("Must supply a string with at least one character".into()),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("say-long")
+            .description("Open a form to write something longer for the bot to say")
+            .kind(CommandOptionType::SubCommand)
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("say-long")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::UserInVoice,
+            Precondition::BotInSameChannel,
+        ]
+    }
+}
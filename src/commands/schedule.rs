@@ -0,0 +1,273 @@
+use std::convert::TryFrom;
+
+use anyhow::{anyhow, Context as anyhowContext};
+use chrono::Utc;
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        channel::ChannelType,
+        guild::Guild,
+        id::ChannelId as SerenityChannelId,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+        Permissions,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{
+    get_string_option,
+    say::{add_say_options, SayOptions},
+    CommandResponse, Precondition, TugboatCommand,
+};
+use crate::{
+    schedule::{get_schedules_from_ctx, parse_when, Announcement},
+    settings::get_settings_from_ctx,
+    text::normalize::user_name,
+};
+
+const MAX_ANNOUNCEMENTS: usize = 25;
+/// Leave some room under Discord's 2000 character limit.
+const MAX_LIST_LENGTH: usize = 1800;
+/// How much of each announcement's text to show when listing them.
+const MAX_ANNOUNCEMENT_PREVIEW: usize = 80;
+const WHEN_HELP: &str = "I don't understand when that should be. Try something like `in 15m`, `at 18:00`, `at 2024-05-01 18:00`, `every 2h` or `every weekday at 18:00`.";
+
+pub struct ScheduleCommand;
+
+#[async_trait]
+impl TugboatCommand for ScheduleCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No schedule subcommand given"))?;
+        let store = get_schedules_from_ctx(ctx).await;
+
+        match subcommand.name.as_str() {
+            "add" => {
+                let channel_id = match channel_id {
+                    Some(c) => c,
+                    None => {
+                        return Ok(
+                            "Tell me which voice channel to make the announcement in, or join one first."
+                                .into(),
+                        )
+                    }
+                };
+                // the bot joins the channel on their behalf, so it has to be one they could join.
+                let member = guild.member(ctx, user.id).await?;
+                let can_connect = guild
+                    .channels
+                    .get(&SerenityChannelId(channel_id.0))
+                    .and_then(|c| c.clone().guild())
+                    .and_then(|c| guild.user_permissions_in(&c, &member).ok())
+                    .is_some_and(|p| p.connect());
+                if !can_connect {
+                    return Ok(CommandResponse::Ephemeral(format!(
+                        "You can't join <#{}> yourself, so I won't announce anything there for you.",
+                        channel_id.0
+                    )));
+                }
+
+                let when = get_string_option(&subcommand.options, "when").unwrap_or_default();
+                let say = match SayOptions::from_options(&subcommand.options) {
+                    Some(s) => s,
+                    None => return Ok("Must supply a string with at least one character".into()),
+                };
+
+                let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+                let (next, recurrence) = match parse_when(when, Utc::now(), settings.utc_offset()) {
+                    Some(w) => w,
+                    None => return Ok(WHEN_HELP.into()),
+                };
+                let utterance = say.into_utterance(Some(&settings), user);
+
+                let added = store
+                    .update(guild.id, |schedule| {
+                        if schedule.announcements.len() >= MAX_ANNOUNCEMENTS {
+                            return None;
+                        }
+                        schedule.next_id += 1;
+                        schedule.announcements.push(Announcement {
+                            id: schedule.next_id,
+                            text: utterance.text,
+                            voice: utterance.voice,
+                            created_by: user.id,
+                            channel: channel_id.0.into(),
+                            next: next.into(),
+                            recurrence,
+                            when: when.trim().to_owned(),
+                        });
+                        Some(schedule.next_id)
+                    })
+                    .await?;
+
+                Ok(match added {
+                    Some(id) => format!(
+                        "Scheduled announcement #{} for <t:{}:f> in <#{}>.",
+                        id,
+                        next.timestamp(),
+                        channel_id.0
+                    ),
+                    None => format!(
+                        "This server already has {} announcements scheduled, cancel some first.",
+                        MAX_ANNOUNCEMENTS
+                    ),
+                }
+                .into())
+            }
+            "list" => {
+                let schedule = store.get(guild.id).await?;
+                if schedule.announcements.is_empty() {
+                    return Ok("There's nothing scheduled.".into());
+                }
+
+                let mut announcements = schedule.announcements;
+                announcements.sort_by_key(|a| a.next.unix_timestamp());
+
+                let mut response = String::new();
+                for a in announcements {
+                    let mut preview = a
+                        .text
+                        .chars()
+                        .take(MAX_ANNOUNCEMENT_PREVIEW)
+                        .collect::<String>();
+                    if preview.len() < a.text.len() {
+                        preview.push('…');
+                    }
+                    let line = format!(
+                        "#{} <t:{}:R> in <#{}> (`{}`, from {}): {}\n",
+                        a.id,
+                        a.next.unix_timestamp(),
+                        a.channel.0,
+                        a.when,
+                        user_name(ctx, Some(&guild), a.created_by),
+                        preview
+                    );
+
+                    if response.len() + line.len() > MAX_LIST_LENGTH {
+                        response.push_str("…and more.");
+                        break;
+                    }
+                    response.push_str(&line);
+                }
+
+                Ok(response.into())
+            }
+            "cancel" => {
+                let id = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "id")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Integer(i)) => u64::try_from(i).ok(),
+                        _ => None,
+                    })
+                    .ok_or_else(|| anyhow!("id option is required"))?;
+
+                let removed = store
+                    .update(guild.id, |schedule| {
+                        let before = schedule.announcements.len();
+                        schedule.announcements.retain(|a| a.id != id);
+                        before != schedule.announcements.len()
+                    })
+                    .await?;
+
+                Ok(if removed {
+                    format!("Cancelled announcement #{}.", id)
+                } else {
+                    format!("There's no announcement #{}.", id)
+                }
+                .into())
+            }
+            other => Err(anyhow!("Unknown schedule subcommand {}", other)),
+        }
+    }
+
+    fn requested_channel(&self, options: &[CommandDataOption]) -> Option<ChannelId> {
+        options
+            .first()?
+            .options
+            .iter()
+            .find(|o| o.name == "channel")
+            .and_then(|o| match o.resolved {
+                Some(CommandDataOptionValue::Channel(ref c)) => Some(ChannelId::from(c.id)),
+                _ => None,
+            })
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("schedule")
+            .description("Have the bot say something later, or regularly")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("add")
+                    .description("Schedule an announcement")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("when")
+                            .description(
+                                "e.g. `in 15m`, `at 18:00`, `every 2h` or `every weekday at 18:00`",
+                            )
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    });
+                add_say_options(s).create_sub_option(|o| {
+                    o.name("channel")
+                        .description("The voice channel to announce in, if not the one you're in")
+                        .kind(CommandOptionType::Channel)
+                        .channel_types(&[ChannelType::Voice, ChannelType::Stage])
+                        .required(false)
+                })
+            })
+            .create_sub_option(|s| {
+                s.name("list")
+                    .description("List upcoming announcements")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_sub_option(|s| {
+                s.name("cancel")
+                    .description("Cancel an announcement")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("id")
+                            .description("The announcement's number, from `schedule list`")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .required(true)
+                    })
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("schedule")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+
+    fn preconditions_for(&self, options: &[CommandDataOption]) -> Vec<Precondition> {
+        match options.first().map(|s| s.name.as_str()) {
+            // announcements keep on being made to everyone, long after whoever set them up.
+            Some("add" | "cancel") => vec![
+                Precondition::Guild,
+                Precondition::Permission(Permissions::MANAGE_GUILD),
+            ],
+            _ => self.preconditions(),
+        }
+    }
+}
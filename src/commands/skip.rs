@@ -1,15 +1,18 @@
+use anyhow::Context as anyhowContext;
 use serenity::{
     async_trait,
     builder::CreateApplicationCommandOption,
     client::Context,
     model::{
         application::command::CommandOptionType, guild::Guild,
-        prelude::interaction::application_command::CommandDataOption,
+        prelude::interaction::application_command::CommandDataOption, user::User,
     },
 };
 use songbird::id::ChannelId;
 
-use crate::commands::{get_songbird_from_ctx, NOT_IN_SAME_VOICE_CHANNEL_MESSAGE};
+use super::{CommandResponse, Precondition};
+
+use crate::session::get_sessions_from_ctx;
 
 pub struct SkipCommand;
 
@@ -19,20 +22,16 @@ impl super::TugboatCommand for SkipCommand {
         &self,
         ctx: &Context,
         _options: &[CommandDataOption],
-        guild: Guild,
-        channel_id: ChannelId,
-    ) -> anyhow::Result<String> {
-        let manager = get_songbird_from_ctx(ctx).await;
-
-        if let Some(call_lock) = manager.get(guild.id) {
-            // don't allow the action if the user is not in the same channel.
-            let r = call_lock.lock().await;
-            let c = r.current_channel().expect("there should be a channel here");
-            if c != channel_id {
-                return Ok(NOT_IN_SAME_VOICE_CHANNEL_MESSAGE.into());
-            }
-
-            r.queue().skip()?;
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        _user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let sessions = get_sessions_from_ctx(ctx).await;
+
+        // our preconditions have already made sure the user is in the same channel as us.
+        if let Some(session) = sessions.get(guild.id).await {
+            session.skip().await?;
         } else {
             return Ok("Not in a voice channel right now.".into());
         }
@@ -51,4 +50,12 @@ impl super::TugboatCommand for SkipCommand {
     fn get_name(&self) -> String {
         String::from("skip")
     }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![
+            Precondition::Guild,
+            Precondition::UserInVoice,
+            Precondition::BotInSameChannel,
+        ]
+    }
 }
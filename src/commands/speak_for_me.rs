@@ -0,0 +1,79 @@
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{CommandResponse, Precondition, TugboatCommand};
+use crate::settings::get_settings_from_ctx;
+
+pub struct SpeakForMeCommand;
+
+#[async_trait]
+impl TugboatCommand for SpeakForMeCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let enabled = options
+            .iter()
+            .find(|o| o.name == "enabled")
+            .and_then(|o| match o.resolved {
+                Some(CommandDataOptionValue::Boolean(b)) => Some(b),
+                _ => None,
+            })
+            .unwrap_or(true);
+
+        get_settings_from_ctx(ctx)
+            .await
+            .update(guild.id, |s| {
+                if enabled {
+                    s.speak_for_me.insert(user.id);
+                } else {
+                    s.speak_for_me.remove(&user.id);
+                }
+            })
+            .await?;
+
+        if enabled {
+            Ok("While you're in my voice channel, I'll say everything you type on this server in your voice.".into())
+        } else {
+            Ok("I'll stop speaking for you.".into())
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("speak-for-me")
+            .description("Have the bot say everything you type while you're in its voice channel")
+            .kind(CommandOptionType::SubCommand)
+            .create_sub_option(|o| {
+                o.name("enabled")
+                    .description("Turn speaking for you on or off (default on)")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("speak-for-me")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+}
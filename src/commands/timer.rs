@@ -0,0 +1,260 @@
+use std::convert::TryFrom;
+
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{
+    get_string_option,
+    join::join_if_needed,
+    say::{add_say_options, SayOptions},
+    CommandResponse, Precondition, TugboatCommand,
+};
+use crate::{
+    schedule::parse_duration,
+    settings::get_settings_from_ctx,
+    text::{filter::redact, normalize::user_name},
+    timer::get_timers_from_ctx,
+};
+
+const DEFAULT_CHECKPOINTS: &str = "1m, 10s";
+const MAX_TIMER_SECONDS: u64 = 24 * 60 * 60;
+const MAX_TIMERS: usize = 10;
+/// Each checkpoint is synthesized up front, so a timer can't have too many of them.
+const MAX_CHECKPOINTS: usize = 10;
+/// Leave some room under Discord's 2000 character limit.
+const MAX_LIST_LENGTH: usize = 1800;
+/// How much of each timer's message to show when listing them.
+const MAX_MESSAGE_PREVIEW: usize = 80;
+
+fn parse_std_duration(input: &str) -> Option<std::time::Duration> {
+    parse_duration(input)?.to_std().ok()
+}
+
+pub struct TimerCommand;
+
+#[async_trait]
+impl TugboatCommand for TimerCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No timer subcommand given"))?;
+        let timers = get_timers_from_ctx(ctx).await;
+
+        match subcommand.name.as_str() {
+            "start" => {
+                let channel_id = channel_id.context("Voice channel precondition not met")?;
+                let duration = get_string_option(&subcommand.options, "duration")
+                    .and_then(parse_std_duration)
+                    .filter(|d| d.as_secs() > 0 && d.as_secs() <= MAX_TIMER_SECONDS);
+                let duration = match duration {
+                    Some(d) => d,
+                    None => {
+                        return Ok(
+                            "Timers need a duration like `5m` or `1h30m`, up to a day long.".into(),
+                        )
+                    }
+                };
+                let checkpoints = get_string_option(&subcommand.options, "checkpoints")
+                    .unwrap_or(DEFAULT_CHECKPOINTS)
+                    .split(',')
+                    .filter(|c| !c.trim().is_empty())
+                    .map(parse_std_duration)
+                    .collect::<Option<Vec<_>>>();
+                let checkpoints = match checkpoints {
+                    Some(c) => c,
+                    None => {
+                        return Ok(
+                            "Checkpoints should be a list of durations, like `5m, 1m, 10s`.".into(),
+                        )
+                    }
+                };
+                if checkpoints.len() > MAX_CHECKPOINTS {
+                    return Ok(
+                        format!("Timers can have at most {} checkpoints.", MAX_CHECKPOINTS).into(),
+                    );
+                }
+                let say = match SayOptions::from_options(&subcommand.options) {
+                    Some(s) => s,
+                    None => return Ok("Must supply a string with at least one character".into()),
+                };
+                if timers.list(guild.id).len() >= MAX_TIMERS {
+                    return Ok(format!(
+                        "This server already has {} timers running, cancel some first.",
+                        MAX_TIMERS
+                    )
+                    .into());
+                }
+
+                let session = match join_if_needed(ctx, &guild, channel_id, user).await? {
+                    Some(s) => s,
+                    None => return Ok("Not in a voice channel right now.".into()),
+                };
+                let settings = session.settings().await?;
+                let mut utterance = say.into_utterance(Some(&settings), user);
+                // keeps the voice the same for everything this user's timers say.
+                utterance.voice_seed = Some(user.id.0);
+
+                let timer = timers
+                    .start(ctx, guild.id, duration, &checkpoints, utterance)
+                    .await?;
+
+                Ok(format!(
+                    "Timer #{} goes off <t:{}:R>.",
+                    timer.id,
+                    timer.ends_at.unix_timestamp()
+                )
+                .into())
+            }
+            "list" => {
+                let running = timers.list(guild.id);
+                if running.is_empty() {
+                    return Ok("There aren't any timers running.".into());
+                }
+
+                let settings = get_settings_from_ctx(ctx).await.get(guild.id).await?;
+                let mut response = String::new();
+                for t in running {
+                    let message = redact(&settings.filter, &t.message);
+                    let mut preview = message
+                        .chars()
+                        .take(MAX_MESSAGE_PREVIEW)
+                        .collect::<String>();
+                    if preview.len() < message.len() {
+                        preview.push('…');
+                    }
+                    let line = format!(
+                        "#{} <t:{}:R> (from {}): {}\n",
+                        t.id,
+                        t.ends_at.unix_timestamp(),
+                        t.created_by
+                            .map(|u| user_name(ctx, Some(&guild), u))
+                            .unwrap_or_else(|| "someone".into()),
+                        preview
+                    );
+
+                    if response.len() + line.len() > MAX_LIST_LENGTH {
+                        response.push_str("…and more.");
+                        break;
+                    }
+                    response.push_str(&line);
+                }
+
+                Ok(response.into())
+            }
+            "cancel" => {
+                let id = subcommand
+                    .options
+                    .iter()
+                    .find(|o| o.name == "id")
+                    .and_then(|o| match o.resolved {
+                        Some(CommandDataOptionValue::Integer(i)) => u64::try_from(i).ok(),
+                        _ => None,
+                    })
+                    .ok_or_else(|| anyhow!("id option is required"))?;
+
+                let timer = match timers.list(guild.id).into_iter().find(|t| t.id == id) {
+                    Some(t) => t,
+                    None => return Ok(format!("There's no timer #{}.", id).into()),
+                };
+                if timer.created_by != Some(user.id) {
+                    let permissions = guild.member_permissions(ctx, user.id).await?;
+                    if !(permissions.administrator() || permissions.manage_guild()) {
+                        return Ok(CommandResponse::Ephemeral(format!(
+                            "Only whoever started timer #{}, or someone who can manage the server, can cancel it.",
+                            id
+                        )));
+                    }
+                }
+
+                Ok(if timers.cancel(guild.id, id) {
+                    format!("Cancelled timer #{}.", id)
+                } else {
+                    // it went off while we were checking.
+                    format!("There's no timer #{}.", id)
+                }
+                .into())
+            }
+            other => Err(anyhow!("Unknown timer subcommand {}", other)),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("timer")
+            .description("Count down out loud")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("start")
+                    .description("Start a timer, with a message for when it goes off")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("duration")
+                            .description("How long the timer runs for, e.g. `5m` or `1h30m`")
+                            .kind(CommandOptionType::String)
+                            .required(true)
+                    });
+                add_say_options(s).create_sub_option(|o| {
+                    o.name("checkpoints")
+                        .description("When to say how long is left (default `1m, 10s`)")
+                        .kind(CommandOptionType::String)
+                        .required(false)
+                })
+            })
+            .create_sub_option(|s| {
+                s.name("list")
+                    .description("List the timers that are running")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_sub_option(|s| {
+                s.name("cancel")
+                    .description("Stop a timer")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("id")
+                            .description("The timer's number, from `timer list`")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .required(true)
+                    })
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("timer")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+
+    fn preconditions_for(&self, options: &[CommandDataOption]) -> Vec<Precondition> {
+        match options.first().map(|s| s.name.as_str()) {
+            Some("start") => vec![
+                Precondition::Guild,
+                Precondition::UserInVoice,
+                Precondition::BotInSameChannel,
+            ],
+            _ => self.preconditions(),
+        }
+    }
+}
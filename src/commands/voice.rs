@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Context as anyhowContext};
+use serenity::{
+    async_trait,
+    builder::CreateApplicationCommandOption,
+    client::Context,
+    model::{
+        application::command::CommandOptionType,
+        guild::Guild,
+        prelude::interaction::application_command::{CommandDataOption, CommandDataOptionValue},
+        user::User,
+    },
+};
+use songbird::id::ChannelId;
+
+use super::{say::Voices, CommandResponse, Precondition, TugboatCommand};
+use crate::{settings::get_settings_from_ctx, speech::VoiceOptions};
+
+pub(super) fn describe(voice: &VoiceOptions) -> String {
+    match voice.name {
+        Some(ref name) => format!("the {} voice", name),
+        None => format!(
+            "a {} {} voice",
+            voice
+                .gender
+                .as_deref()
+                .map(|g| g.to_lowercase())
+                .unwrap_or_else(|| "random".into()),
+            voice.language.as_deref().unwrap_or("en-US")
+        ),
+    }
+}
+
+pub struct VoiceCommand;
+
+#[async_trait]
+impl TugboatCommand for VoiceCommand {
+    async fn execute(
+        &self,
+        ctx: &Context,
+        options: &[CommandDataOption],
+        guild: Option<Guild>,
+        _channel_id: Option<ChannelId>,
+        user: &User,
+    ) -> anyhow::Result<CommandResponse> {
+        let guild = guild.context("Guild precondition not met")?;
+        let subcommand = options
+            .first()
+            .ok_or_else(|| anyhow!("No voice subcommand given"))?;
+        let settings = get_settings_from_ctx(ctx).await;
+
+        match subcommand.name.as_str() {
+            "set" => {
+                let mut voice = VoiceOptions::default();
+                for option in &subcommand.options {
+                    let value = match option.resolved {
+                        Some(CommandDataOptionValue::String(ref s)) => Some(s.to_owned()),
+                        _ => None,
+                    };
+                    match option.name.as_str() {
+                        "language" => voice.language = value,
+                        "gender" => voice.gender = value,
+                        "name" => voice.name = value,
+                        _ => continue,
+                    }
+                }
+
+                // make sure we can actually find a voice that fits before saving it.
+                let valid = {
+                    let data = ctx.data.read().await;
+                    let voices = data.get::<Voices>().expect("Should have been voices here");
+                    voice.resolve(voices, None)
+                };
+                if let Err(e) = valid {
+                    return Ok(format!("I can't use that voice: {}", e).into());
+                }
+
+                let description = describe(&voice);
+                settings
+                    .update(guild.id, |s| s.voice_profiles.insert(user.id, voice))
+                    .await?;
+
+                Ok(format!("I'll speak for you in {}.", description).into())
+            }
+            "show" => {
+                let s = settings.get(guild.id).await?;
+                Ok(match s.voice_profiles.get(&user.id) {
+                    Some(voice) => format!("I speak for you in {}.", describe(voice)).into(),
+                    None => "You haven't picked a voice, so I'll pick one for you.".into(),
+                })
+            }
+            "clear" => {
+                settings
+                    .update(guild.id, |s| s.voice_profiles.remove(&user.id))
+                    .await?;
+                Ok("Forgot your voice, I'll pick one for you from now on.".into())
+            }
+            other => Err(anyhow!("Unknown voice subcommand {}", other)),
+        }
+    }
+
+    fn create_command(&self) -> CreateApplicationCommandOption {
+        CreateApplicationCommandOption::default()
+            .name("voice")
+            .description("Choose the voice the bot uses when speaking for you")
+            .kind(CommandOptionType::SubCommandGroup)
+            .create_sub_option(|s| {
+                s.name("set")
+                    .description("Set your voice")
+                    .kind(CommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("language")
+                            .description("A language to use (default en-US)")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+                    .create_sub_option(|o| {
+                        o.name("gender")
+                            .description("The gender of the voice")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                            .add_string_choice("Male", "MALE")
+                            .add_string_choice("Female", "FEMALE")
+                    })
+                    .create_sub_option(|o| {
+                        o.name("name")
+                            .description("A specific voice, e.g. en-GB-Wavenet-A")
+                            .kind(CommandOptionType::String)
+                            .required(false)
+                    })
+            })
+            .create_sub_option(|s| {
+                s.name("show")
+                    .description("Show your current voice")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .create_sub_option(|s| {
+                s.name("clear")
+                    .description("Go back to having a voice picked for you")
+                    .kind(CommandOptionType::SubCommand)
+            })
+            .clone()
+    }
+
+    fn get_name(&self) -> String {
+        String::from("voice")
+    }
+
+    fn preconditions(&self) -> Vec<Precondition> {
+        vec![Precondition::Guild]
+    }
+}
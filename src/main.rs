@@ -1,4 +1,4 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Arc};
 
 use anyhow::Context as anyhowContext;
 
@@ -16,14 +16,40 @@ use serenity::{
     model::prelude::Ready,
     Client,
 };
-use songbird::SerenityInit;
+use songbird::{SerenityInit, Songbird};
 use tracing_subscriber::EnvFilter;
 
+mod audio;
+mod audit;
+mod chimes;
 mod commands;
-
-use commands::{say::*, ApplicationCommandHandler, IdleDurations};
-
-use crate::commands::CommandsMap;
+mod messages;
+mod persona;
+mod phrases;
+mod replay;
+mod schedule;
+mod session;
+mod settings;
+mod speech;
+mod storage;
+mod text;
+mod timer;
+mod voice_state;
+
+use commands::{say::*, ApplicationCommandHandler};
+
+use crate::audit::{AuditLogStore, AuditLogs, AuditPruner};
+use crate::chimes::{ChimeStore, Chimes};
+use crate::commands::{CommandsMap, MessageCommandsMap};
+use crate::messages::MessageHandler;
+use crate::phrases::{PhraseStore, Phrases};
+use crate::replay::{ReplayBuffer, Replays};
+use crate::schedule::{Scheduler, Schedules};
+use crate::session::{SessionManager, Sessions};
+use crate::settings::Settings;
+use crate::storage::GuildStore;
+use crate::timer::{TimerManager, Timers};
+use crate::voice_state::VoiceStateHandler;
 
 #[tracing::instrument(skip(hub))]
 async fn get_voices(
@@ -90,6 +116,8 @@ async fn main() -> anyhow::Result<()> {
     let app_command_scope = CommandScope::from_str(
         &std::env::var("APPLICATION_COMMAND_SCOPE").unwrap_or_else(|_| "global".into()),
     )?;
+    let data_directory =
+        PathBuf::from(std::env::var("DATA_DIRECTORY").unwrap_or_else(|_| "data".into()));
 
     let secret = oauth2::read_service_account_key(&api_path)
         .await
@@ -114,8 +142,15 @@ async fn main() -> anyhow::Result<()> {
     let voices = get_voices(&hub).await?;
 
     let framework = StandardFramework::new();
+    let songbird = Songbird::serenity();
+    let settings = Arc::new(GuildStore::new(data_directory.join("settings"))?);
+    let audit_logs = Arc::new(AuditLogStore::new(data_directory.join("audit"))?);
+    let phrases = Arc::new(PhraseStore::new(data_directory.join("phrases"))?);
+    let schedules = Arc::new(GuildStore::new(data_directory.join("schedules"))?);
+    let chimes = Arc::new(ChimeStore::new(data_directory.join("chimes"))?);
 
-    let intents = GatewayIntents::non_privileged();
+    // message content is needed to read out messages from text channels.
+    let intents = GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT;
 
     let mut client = Client::builder(&discord_token, intents)
         .event_handler(ReadyNotifier)
@@ -123,9 +158,13 @@ async fn main() -> anyhow::Result<()> {
             prefix: app_command_prefix,
             scope: app_command_scope,
         })
+        .event_handler(VoiceStateHandler)
+        .event_handler(MessageHandler)
+        .event_handler(Scheduler::default())
+        .event_handler(AuditPruner::default())
         .framework(framework)
         .application_id(application_id)
-        .register_songbird()
+        .register_songbird_with(songbird.clone())
         .await
         .context("Could not initialize Discord client")?;
 
@@ -133,8 +172,16 @@ async fn main() -> anyhow::Result<()> {
         let mut data = client.data.write().await;
         data.insert::<TtsService>(hub);
         data.insert::<Voices>(voices);
-        data.insert::<IdleDurations>(HashMap::new());
         data.insert::<CommandsMap>(commands::register_commands());
+        data.insert::<MessageCommandsMap>(commands::register_message_commands());
+        data.insert::<Sessions>(Arc::new(SessionManager::new(songbird, settings.clone())));
+        data.insert::<Settings>(settings);
+        data.insert::<AuditLogs>(audit_logs);
+        data.insert::<Replays>(Arc::new(ReplayBuffer::default()));
+        data.insert::<Phrases>(phrases);
+        data.insert::<Schedules>(schedules);
+        data.insert::<Chimes>(chimes);
+        data.insert::<Timers>(Arc::new(TimerManager::default()));
     }
 
     let _ = client.start().await.map_err(|why| {
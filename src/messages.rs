@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use serenity::{
+    async_trait,
+    client::{Context, EventHandler},
+    model::channel::Message,
+};
+use songbird::id::ChannelId;
+
+use crate::{
+    session::{get_sessions_from_ctx, GuildSession},
+    settings::GuildSettings,
+    speech::{speak, Utterance},
+};
+
+/// Whether the author of `msg` is currently sitting in the same voice channel as the bot.
+async fn author_is_listening(ctx: &Context, session: &GuildSession, msg: &Message) -> bool {
+    let author_channel = msg
+        .guild_id
+        .and_then(|g| g.to_guild_cached(&ctx.cache))
+        .and_then(|g| {
+            g.voice_states
+                .get(&msg.author.id)
+                .and_then(|vs| vs.channel_id)
+        })
+        .map(ChannelId::from);
+
+    author_channel.is_some() && author_channel == session.current_channel().await
+}
+
+/// Speak a message in its author's voice. If `announce_author` is set, the message is
+/// prefixed with who said it.
+async fn read_message(
+    ctx: &Context,
+    session: &Arc<GuildSession>,
+    settings: &GuildSettings,
+    msg: &Message,
+    announce_author: bool,
+) -> anyhow::Result<()> {
+    // mentions and the like are resolved when the utterance is prepared, per the guild's settings.
+    let content = msg.content.clone();
+    let text = if announce_author {
+        let name = msg
+            .author_nick(&ctx.http)
+            .await
+            .unwrap_or_else(|| msg.author.name.clone());
+        format!("{} says: {}", name, content)
+    } else {
+        content
+    };
+
+    let utterance = Utterance {
+        text,
+        is_ssml: false,
+        voice: settings
+            .voice_profiles
+            .get(&msg.author.id)
+            .cloned()
+            .unwrap_or_default(),
+        // so that everyone who hasn't picked a voice still sounds like themselves.
+        voice_seed: Some(msg.author.id.0),
+        requester: Some(msg.author.id),
+        prosody: Default::default(),
+        effects: Vec::new(),
+    };
+
+    speak(ctx, session, &utterance).await?;
+    Ok(())
+}
+
+/// Reads out messages while the bot is connected to voice: everything posted in the guild's
+/// autoread channel, and everything typed by users who've asked us to speak for them.
+pub struct MessageHandler;
+
+#[async_trait]
+impl EventHandler for MessageHandler {
+    async fn message(&self, ctx: Context, msg: Message) {
+        if msg.author.bot || msg.content.trim().is_empty() {
+            return;
+        }
+
+        let guild_id = match msg.guild_id {
+            Some(g) => g,
+            None => return,
+        };
+
+        let session = match get_sessions_from_ctx(&ctx).await.get(guild_id).await {
+            Some(s) => s,
+            None => return,
+        };
+
+        let settings = match session.settings().await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::error!(?e, ?guild_id, "Could not load guild settings");
+                return;
+            }
+        };
+
+        // speaking for someone takes priority, since then there's no need to say who it was.
+        let announce_author = if settings.speak_for_me.contains(&msg.author.id)
+            && author_is_listening(&ctx, &session, &msg).await
+        {
+            false
+        } else if settings.autoread_channel == Some(msg.channel_id) {
+            true
+        } else {
+            return;
+        };
+
+        tracing::debug!(?guild_id, channel_id=?msg.channel_id, "Reading out message");
+        if let Err(e) = read_message(&ctx, &session, &settings, &msg, announce_author).await {
+            tracing::error!(?e, ?guild_id, "Could not read out message");
+        }
+    }
+}
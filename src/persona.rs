@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    speech::{escape_ssml, Prosody, VoiceOptions},
+    text::{markup_matches, pronounce::dictionary_pattern, script::Segment},
+};
+
+/// A character the bot can speak as, bundling a voice and way of speaking with a way of
+/// talking. Guilds define their own, keyed by `persona_key`.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Persona {
+    /// The name as it was given, for showing back to people.
+    pub name: String,
+    pub voice: VoiceOptions,
+    pub prosody: Prosody,
+    /// Plain text said before anything the persona says.
+    pub prefix: Option<String>,
+    /// Plain text said after anything the persona says.
+    pub suffix: Option<String>,
+    /// Words the persona says differently, keyed by the lowercased word.
+    pub substitutions: BTreeMap<String, String>,
+}
+
+/// Personas keyed by their lowercased name.
+pub type Personas = BTreeMap<String, Persona>;
+
+/// The key a persona or substituted word is stored under, so that matching ignores case.
+pub fn persona_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+impl Persona {
+    /// Swap out every word this persona says differently. The result is always SSML.
+    pub fn substitute(&self, text: &str, is_ssml: bool) -> String {
+        match dictionary_pattern(self.substitutions.keys()) {
+            Some(pattern) => markup_matches(&pattern, text, is_ssml, |raw, original| {
+                match self.substitutions.get(&persona_key(raw)) {
+                    Some(replacement) => escape_ssml(replacement),
+                    None => original.to_owned(),
+                }
+            }),
+            None if is_ssml => text.to_owned(),
+            None => escape_ssml(text),
+        }
+    }
+
+    /// Say `text` the way this persona talks, with its substitutions, prefix and suffix.
+    /// The result is always SSML.
+    pub fn transform(&self, text: &str, is_ssml: bool) -> String {
+        let mut transformed = String::new();
+        if let Some(ref prefix) = self.prefix {
+            transformed.push_str(&escape_ssml(prefix));
+            transformed.push(' ');
+        }
+        transformed.push_str(&self.substitute(text, is_ssml));
+        if let Some(ref suffix) = self.suffix {
+            transformed.push(' ');
+            transformed.push_str(&escape_ssml(suffix));
+        }
+        transformed
+    }
+
+    /// Have this persona narrate a dialogue script. Every line gets its substitutions, while
+    /// the prefix and suffix are said on their own in the persona's voice.
+    pub fn transform_script(&self, segments: Vec<Segment>) -> Vec<Segment> {
+        let narrate = |text: &String| Segment {
+            character: None,
+            voice: None,
+            text: escape_ssml(text),
+        };
+
+        self.prefix
+            .iter()
+            .map(narrate)
+            .chain(segments.into_iter().map(|s| Segment {
+                text: self.substitute(&s.text, true),
+                ..s
+            }))
+            .chain(self.suffix.iter().map(narrate))
+            .collect()
+    }
+}
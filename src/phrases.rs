@@ -0,0 +1,183 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context as anyhowContext;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::id::{GuildId, UserId},
+    prelude::TypeMapKey,
+};
+
+use crate::storage::GuildStore;
+
+pub struct Phrases;
+impl TypeMapKey for Phrases {
+    type Value = Arc<PhraseStore>;
+}
+
+/// Something said often enough that it's worth keeping the audio around.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Phrase {
+    /// The name as it was given, for display.
+    pub name: String,
+    /// The text as it was given to us, before any processing.
+    pub text: String,
+    /// The name of the voice it was synthesized with.
+    pub voice: String,
+    pub created_by: UserId,
+    /// Locked phrases can't be changed or deleted until they're unlocked again.
+    #[serde(default)]
+    pub locked: bool,
+}
+
+/// A guild's saved phrases, keyed by [`phrase_key`].
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Phrasebook {
+    pub phrases: BTreeMap<String, Phrase>,
+}
+
+pub fn phrase_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// What came of trying to change a phrase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhraseChange {
+    Done,
+    Missing,
+    Locked,
+}
+
+/// Phrasebooks are kept like any other per-guild document, but the audio is too big for
+/// that, so each phrase's audio gets a file of its own in a directory per guild.
+pub struct PhraseStore {
+    books: GuildStore<Phrasebook>,
+    directory: PathBuf,
+}
+
+impl PhraseStore {
+    pub fn new(directory: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        Ok(Self {
+            books: GuildStore::new(&directory)?,
+            directory,
+        })
+    }
+
+    /// Phrase names can be anything, so they're hex encoded to make a safe file name.
+    fn audio_path(&self, guild_id: GuildId, key: &str) -> PathBuf {
+        let file_name = key
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        self.directory
+            .join(guild_id.0.to_string())
+            .join(file_name)
+            .with_extension("wav")
+    }
+
+    pub async fn get(&self, guild_id: GuildId) -> anyhow::Result<Phrasebook> {
+        self.books.get(guild_id).await
+    }
+
+    pub async fn audio(&self, guild_id: GuildId, key: &str) -> anyhow::Result<Vec<u8>> {
+        let path = self.audio_path(guild_id, key);
+        tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Could not read phrase audio at {:?}", path))
+    }
+
+    /// Save a phrase along with its audio, replacing any unlocked phrase of the same name.
+    pub async fn save(
+        &self,
+        guild_id: GuildId,
+        phrase: Phrase,
+        audio: &[u8],
+    ) -> anyhow::Result<PhraseChange> {
+        let key = phrase_key(&phrase.name);
+        let path = self.audio_path(guild_id, &key);
+        let tmp_path = path.with_extension("wav.tmp");
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Could not create phrase directory {:?}", parent))?;
+        }
+        tokio::fs::write(&tmp_path, audio)
+            .await
+            .with_context(|| format!("Could not write phrase audio to {:?}", tmp_path))?;
+
+        let change = self
+            .books
+            .update(guild_id, |book| match book.phrases.get(&key) {
+                Some(p) if p.locked => PhraseChange::Locked,
+                _ => {
+                    book.phrases.insert(key, phrase);
+                    PhraseChange::Done
+                }
+            })
+            .await?;
+
+        if change == PhraseChange::Done {
+            tokio::fs::rename(&tmp_path, &path)
+                .await
+                .with_context(|| format!("Could not move phrase audio into place at {:?}", path))?;
+        } else {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+        Ok(change)
+    }
+
+    pub async fn delete(&self, guild_id: GuildId, key: &str) -> anyhow::Result<PhraseChange> {
+        let change = self
+            .books
+            .update(guild_id, |book| match book.phrases.get(key) {
+                None => PhraseChange::Missing,
+                Some(p) if p.locked => PhraseChange::Locked,
+                Some(_) => {
+                    book.phrases.remove(key);
+                    PhraseChange::Done
+                }
+            })
+            .await?;
+
+        if change == PhraseChange::Done {
+            let path = self.audio_path(guild_id, key);
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                tracing::warn!(?e, ?path, "Could not remove audio for deleted phrase");
+            }
+        }
+        Ok(change)
+    }
+
+    /// Lock or unlock a phrase. Returns whether there was such a phrase.
+    pub async fn set_locked(
+        &self,
+        guild_id: GuildId,
+        key: &str,
+        locked: bool,
+    ) -> anyhow::Result<bool> {
+        self.books
+            .update(guild_id, |book| match book.phrases.get_mut(key) {
+                Some(p) => {
+                    p.locked = locked;
+                    true
+                }
+                None => false,
+            })
+            .await
+    }
+}
+
+pub async fn get_phrases_from_ctx(ctx: &Context) -> Arc<PhraseStore> {
+    ctx.data
+        .read()
+        .await
+        .get::<Phrases>()
+        .expect("Phrase store should be present")
+        .clone()
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serenity::{
+    client::Context,
+    model::{
+        id::{GuildId, UserId},
+        Timestamp,
+    },
+    prelude::TypeMapKey,
+};
+use songbird::tracks::TrackHandle;
+
+use crate::audio::effects::Effect;
+
+/// How many utterances to hold on to per guild. The audio is uncompressed, so keep this small.
+pub const RECENT_UTTERANCES: usize = 10;
+
+/// Something that was said recently, along with the audio it was said with.
+#[derive(Clone, Debug)]
+pub struct RecentUtterance {
+    /// Unique for as long as the bot is running. See [`ReplayBuffer::next_id`].
+    pub id: u64,
+    pub timestamp: Timestamp,
+    pub user: Option<UserId>,
+    /// The text as it was given to us, before any processing.
+    pub text: String,
+    pub voice: String,
+    /// The audio as it was synthesized, before `effects` were run on it.
+    pub audio: Arc<[u8]>,
+    pub effects: Vec<Effect>,
+    /// The track it was first played as, which tells us whether it's still queued up.
+    pub track: TrackHandle,
+}
+
+/// The last few things said in each guild, so they can be played again without
+/// another trip to the TTS API.
+#[derive(Default)]
+pub struct ReplayBuffer {
+    guilds: Mutex<HashMap<GuildId, VecDeque<RecentUtterance>>>,
+    next_id: AtomicU64,
+}
+
+impl ReplayBuffer {
+    /// An ID to remember a new utterance under, so it can be found again after newer
+    /// ones have been said.
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn remember(&self, guild_id: GuildId, utterance: RecentUtterance) {
+        let mut guilds = self.guilds.lock().unwrap();
+        let recent = guilds.entry(guild_id).or_default();
+        if recent.len() == RECENT_UTTERANCES {
+            recent.pop_back();
+        }
+        recent.push_front(utterance);
+    }
+
+    /// The `n`th most recent utterance in this guild, starting from 1.
+    pub fn get(&self, guild_id: GuildId, n: usize) -> Option<RecentUtterance> {
+        self.guilds
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .and_then(|r| r.get(n.checked_sub(1)?))
+            .cloned()
+    }
+
+    /// The utterance remembered under `id`, if it hasn't been pushed out yet.
+    pub fn find(&self, guild_id: GuildId, id: u64) -> Option<RecentUtterance> {
+        self.guilds
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .and_then(|r| r.iter().find(|u| u.id == id))
+            .cloned()
+    }
+
+    /// Everything remembered for this guild, most recent first.
+    pub fn list(&self, guild_id: GuildId) -> Vec<RecentUtterance> {
+        self.guilds
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .map(|r| r.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+pub struct Replays;
+impl TypeMapKey for Replays {
+    type Value = Arc<ReplayBuffer>;
+}
+
+pub async fn get_replays_from_ctx(ctx: &Context) -> Arc<ReplayBuffer> {
+    ctx.data
+        .read()
+        .await
+        .get::<Replays>()
+        .expect("Replay buffer should be present")
+        .clone()
+}
@@ -0,0 +1,510 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context as anyhowContext;
+use chrono::{
+    DateTime, Datelike, Duration as ChronoDuration, FixedOffset, NaiveDateTime, NaiveTime,
+    TimeZone, Timelike, Utc, Weekday,
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    async_trait,
+    client::{Context, EventHandler},
+    model::{
+        gateway::Ready,
+        id::{ChannelId, GuildId, UserId},
+        Timestamp,
+    },
+    prelude::TypeMapKey,
+};
+
+use crate::{
+    commands::join::join_if_needed,
+    settings::{get_settings_from_ctx, GuildSettings},
+    speech::{speak, Utterance, VoiceOptions},
+    storage::GuildStore,
+};
+
+/// How often to look for announcements that are due.
+const TICK: Duration = Duration::from_secs(15);
+/// Announcements that were due longer ago than this, say because the bot was down at the
+/// time, are skipped rather than said late.
+const MISSED_GRACE_SECONDS: i64 = 5 * 60;
+/// Anything more frequent than this is just spam.
+const MIN_INTERVAL_SECONDS: i64 = 5 * 60;
+
+static DURATION_PART: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d+)\s*([smhd])").unwrap());
+
+pub struct Schedules;
+impl TypeMapKey for Schedules {
+    type Value = Arc<GuildStore<Schedule>>;
+}
+
+/// How an announcement repeats.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Recurrence {
+    /// Every so many seconds.
+    Interval { seconds: i64 },
+    /// At a time of day, in the guild's UTC offset, on some days of the week.
+    Weekly {
+        /// Days counted from Monday.
+        days: Vec<u32>,
+        hour: u32,
+        minute: u32,
+    },
+}
+
+impl Recurrence {
+    /// The first time this comes around after `now`, given it last came around at `previous`.
+    pub fn next_after(
+        &self,
+        previous: DateTime<Utc>,
+        now: DateTime<Utc>,
+        offset: FixedOffset,
+    ) -> DateTime<Utc> {
+        match self {
+            Self::Interval { seconds } => {
+                let elapsed = (now - previous).num_seconds().max(0);
+                previous + ChronoDuration::seconds((elapsed / seconds + 1) * seconds)
+            }
+            Self::Weekly { days, hour, minute } => {
+                let today = now.with_timezone(&offset).naive_local().date();
+                (0..=7)
+                    .map(|i| today + ChronoDuration::days(i))
+                    .filter(|d| days.contains(&d.weekday().num_days_from_monday()))
+                    .filter_map(|d| d.and_hms_opt(*hour, *minute, 0))
+                    .filter_map(|t| offset.from_local_datetime(&t).single())
+                    .map(|t| t.with_timezone(&Utc))
+                    .find(|t| *t > now)
+                    .unwrap_or_else(|| now + ChronoDuration::weeks(1))
+            }
+        }
+    }
+}
+
+/// Something to say at a later time, and maybe again after that.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Announcement {
+    pub id: u64,
+    /// The text as it was given to us, before any processing.
+    pub text: String,
+    pub voice: VoiceOptions,
+    pub created_by: UserId,
+    /// The voice channel to join if we aren't connected when it's due.
+    pub channel: ChannelId,
+    pub next: Timestamp,
+    pub recurrence: Option<Recurrence>,
+    /// When it was asked for, as it was written.
+    pub when: String,
+}
+
+/// A guild's upcoming announcements.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Schedule {
+    pub next_id: u64,
+    pub announcements: Vec<Announcement>,
+}
+
+pub async fn get_schedules_from_ctx(ctx: &Context) -> Arc<GuildStore<Schedule>> {
+    ctx.data
+        .read()
+        .await
+        .get::<Schedules>()
+        .expect("Schedule store should be present")
+        .clone()
+}
+
+/// Parse a duration like `15m`, `1h30m` or `2d`.
+pub fn parse_duration(input: &str) -> Option<ChronoDuration> {
+    let input = input.trim();
+    let mut seconds = 0i64;
+    let mut end = 0;
+    for c in DURATION_PART.captures_iter(input) {
+        let whole = c.get(0)?;
+        if !input[end..whole.start()].trim().is_empty() {
+            return None;
+        }
+        end = whole.end();
+
+        let n = c[1].parse::<i64>().ok()?;
+        let unit = match &c[2] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            _ => 24 * 60 * 60,
+        };
+        seconds = seconds.checked_add(n.checked_mul(unit)?)?;
+    }
+
+    if end == 0 || end != input.len() || seconds == 0 {
+        return None;
+    }
+    Some(ChronoDuration::seconds(seconds))
+}
+
+fn parse_time(input: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(input.trim(), "%H:%M").ok()
+}
+
+/// Days of the week, counted from Monday, from things like `weekday` or `mon, wed`.
+fn parse_days(input: &str) -> Option<Vec<u32>> {
+    let days = match input.trim().trim_end_matches('s') {
+        "day" => (0..7).collect(),
+        "weekday" => (0..5).collect(),
+        "weekend" => vec![5, 6],
+        days => days
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|d| !d.is_empty() && *d != "and")
+            .map(|d| Weekday::from_str(d.trim_end_matches('s')).ok())
+            .map(|d| d.map(|d| d.num_days_from_monday()))
+            .collect::<Option<Vec<_>>>()?,
+    };
+
+    if days.is_empty() {
+        None
+    } else {
+        Some(days)
+    }
+}
+
+/// Work out when an announcement should first go off, and how it repeats, from things like
+/// `in 15m`, `at 18:00`, `at 2024-05-01 18:00`, `every 2h` or `every weekday at 18:00`.
+/// Times of day are in the guild's UTC offset.
+pub fn parse_when(
+    input: &str,
+    now: DateTime<Utc>,
+    offset: FixedOffset,
+) -> Option<(DateTime<Utc>, Option<Recurrence>)> {
+    let input = input.trim().to_lowercase();
+
+    if let Some(delay) = input.strip_prefix("in ") {
+        return Some((now + parse_duration(delay)?, None));
+    }
+
+    if let Some(at) = input.strip_prefix("at ") {
+        if let Some(time) = parse_time(at) {
+            let daily = Recurrence::Weekly {
+                days: (0..7).collect(),
+                hour: time.hour(),
+                minute: time.minute(),
+            };
+            return Some((daily.next_after(now, now, offset), None));
+        }
+
+        let local = NaiveDateTime::parse_from_str(at.trim(), "%Y-%m-%d %H:%M").ok()?;
+        let at = offset
+            .from_local_datetime(&local)
+            .single()?
+            .with_timezone(&Utc);
+        return if at > now { Some((at, None)) } else { None };
+    }
+
+    let every = input.strip_prefix("every ")?;
+    let recurrence = match parse_duration(every) {
+        Some(interval) if interval.num_seconds() < MIN_INTERVAL_SECONDS => return None,
+        Some(interval) => Recurrence::Interval {
+            seconds: interval.num_seconds(),
+        },
+        None => {
+            let (days, time) = every.split_once(" at ")?;
+            let time = parse_time(time)?;
+            Recurrence::Weekly {
+                days: parse_days(days)?,
+                hour: time.hour(),
+                minute: time.minute(),
+            }
+        }
+    };
+
+    Some((recurrence.next_after(now, now, offset), Some(recurrence)))
+}
+
+async fn announce(
+    ctx: &Context,
+    guild_id: GuildId,
+    announcement: &Announcement,
+) -> anyhow::Result<()> {
+    let guild = guild_id
+        .to_guild_cached(&ctx.cache)
+        .context("Guild for announcement isn't cached")?;
+    let creator = announcement
+        .created_by
+        .to_user(ctx)
+        .await
+        .context("Could not look up who scheduled the announcement")?;
+
+    let session = join_if_needed(ctx, &guild, announcement.channel.into(), &creator)
+        .await?
+        .context("Could not join the voice channel for the announcement")?;
+
+    let utterance = Utterance {
+        text: announcement.text.clone(),
+        is_ssml: true,
+        voice: announcement.voice.clone(),
+        voice_seed: None,
+        requester: Some(announcement.created_by),
+        prosody: Default::default(),
+        effects: Vec::new(),
+    };
+    speak(ctx, &session, &utterance).await?;
+
+    Ok(())
+}
+
+async fn run_due(ctx: &Context, guild_id: GuildId) -> anyhow::Result<()> {
+    let store = get_schedules_from_ctx(ctx).await;
+    let now = Utc::now();
+    let due = store
+        .get(guild_id)
+        .await?
+        .announcements
+        .into_iter()
+        .filter(|a| *a.next <= now)
+        .collect::<Vec<_>>();
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    let settings: GuildSettings = get_settings_from_ctx(ctx).await.get(guild_id).await?;
+    let offset = settings.utc_offset();
+
+    // move everything along before saying anything, so that one failing doesn't
+    // have it go off again on every tick.
+    store
+        .update(guild_id, |schedule| {
+            schedule
+                .announcements
+                .retain(|a| a.recurrence.is_some() || !due.iter().any(|d| d.id == a.id));
+            for a in schedule.announcements.iter_mut() {
+                if let (Some(r), true) = (&a.recurrence, due.iter().any(|d| d.id == a.id)) {
+                    a.next = r.next_after(*a.next, now, offset).into();
+                }
+            }
+        })
+        .await?;
+
+    for announcement in due {
+        if (now - *announcement.next).num_seconds() > MISSED_GRACE_SECONDS {
+            tracing::info!(
+                ?guild_id,
+                id = announcement.id,
+                "Skipping missed announcement"
+            );
+            continue;
+        }
+        if let Err(e) = announce(ctx, guild_id, &announcement).await {
+            tracing::error!(
+                ?e,
+                ?guild_id,
+                id = announcement.id,
+                "Could not make announcement"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Says scheduled announcements when they come due.
+#[derive(Default)]
+pub struct Scheduler {
+    started: AtomicBool,
+}
+
+#[async_trait]
+impl EventHandler for Scheduler {
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        // ready fires again whenever we reconnect, but one loop is plenty.
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TICK);
+            loop {
+                interval.tick().await;
+                for guild_id in ctx.cache.guilds() {
+                    if let Err(e) = run_due(&ctx, guild_id).await {
+                        tracing::error!(?e, ?guild_id, "Could not run scheduled announcements");
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Wednesday.
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2024, 5, 1).and_hms(12, 0, 0)
+    }
+
+    fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2024, 5, day).and_hms(hour, minute, 0)
+    }
+
+    fn hours(h: i32) -> FixedOffset {
+        FixedOffset::east(h * 60 * 60)
+    }
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("15m", Some(15 * 60)),
+            ("1h30m", Some(90 * 60)),
+            ("1h 30m", Some(90 * 60)),
+            (" 10s ", Some(10)),
+            ("2d", Some(2 * 24 * 60 * 60)),
+            ("0m", None),
+            ("5", None),
+            ("5x", None),
+            ("m5", None),
+            ("1h foo", None),
+            ("foo 1h", None),
+            ("", None),
+            ("99999999999999999999d", None),
+            ("9223372036854775807d", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_duration(input).map(|d| d.num_seconds()),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parses_days() {
+        let cases = [
+            ("days", Some(vec![0, 1, 2, 3, 4, 5, 6])),
+            ("weekdays", Some(vec![0, 1, 2, 3, 4])),
+            ("weekend", Some(vec![5, 6])),
+            ("mon, wed and fri", Some(vec![0, 2, 4])),
+            ("mondays", Some(vec![0])),
+            ("tue sun", Some(vec![1, 6])),
+            ("blursday", None),
+            ("mon, blursday", None),
+            ("", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_days(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn parses_one_off_times() {
+        let cases = [
+            ("in 15m", hours(0), Some(utc(1, 12, 15))),
+            ("In 1h30m", hours(0), Some(utc(1, 13, 30))),
+            // later today, in the guild's time.
+            ("at 18:00", hours(2), Some(utc(1, 16, 0))),
+            // already gone by today, so tomorrow.
+            ("at 10:00", hours(2), Some(utc(2, 8, 0))),
+            ("at 06:00", hours(-5), Some(utc(2, 11, 0))),
+            ("at 2024-05-01 18:00", hours(0), Some(utc(1, 18, 0))),
+            ("at 2024-05-02 01:00", hours(3), Some(utc(1, 22, 0))),
+            ("at 2024-05-01 11:00", hours(0), None),
+            ("at 2024-04-30 18:00", hours(0), None),
+            ("at 25:00", hours(0), None),
+            ("in 0m", hours(0), None),
+            ("sometime", hours(0), None),
+        ];
+        for (input, offset, expected) in cases {
+            assert_eq!(
+                parse_when(input, now(), offset),
+                expected.map(|t| (t, None)),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn parses_recurring_times() {
+        let weekly = |days: Vec<u32>, hour, minute| Recurrence::Weekly { days, hour, minute };
+        let cases = [
+            (
+                "every 2h",
+                hours(0),
+                Some((utc(1, 14, 0), Recurrence::Interval { seconds: 7200 })),
+            ),
+            (
+                "every 5m",
+                hours(0),
+                Some((utc(1, 12, 5), Recurrence::Interval { seconds: 300 })),
+            ),
+            ("every 4m", hours(0), None),
+            ("every 30s", hours(0), None),
+            (
+                "every weekday at 09:00",
+                hours(0),
+                Some((utc(2, 9, 0), weekly(vec![0, 1, 2, 3, 4], 9, 0))),
+            ),
+            (
+                "every fri at 09:30",
+                hours(0),
+                Some((utc(3, 9, 30), weekly(vec![4], 9, 30))),
+            ),
+            // wraps around past the end of the week.
+            (
+                "every mon at 09:00",
+                hours(0),
+                Some((utc(6, 9, 0), weekly(vec![0], 9, 0))),
+            ),
+            // today's has already gone, so it's a whole week away.
+            (
+                "every wed at 11:00",
+                hours(0),
+                Some((utc(8, 11, 0), weekly(vec![2], 11, 0))),
+            ),
+            // it's already Thursday in UTC+14.
+            (
+                "every wed at 09:00",
+                hours(14),
+                Some((utc(7, 19, 0), weekly(vec![2], 9, 0))),
+            ),
+            ("every blursday at 09:00", hours(0), None),
+            ("every weekday at 25:00", hours(0), None),
+            ("every weekday", hours(0), None),
+        ];
+        for (input, offset, expected) in cases {
+            assert_eq!(
+                parse_when(input, now(), offset),
+                expected.map(|(t, r)| (t, Some(r))),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn intervals_skip_ahead_past_missed_runs() {
+        let every_half_hour = Recurrence::Interval { seconds: 30 * 60 };
+        let previous = now() - ChronoDuration::hours(1);
+        assert_eq!(
+            every_half_hour.next_after(previous, now(), hours(0)),
+            utc(1, 12, 30)
+        );
+        // a clock that hasn't caught up yet doesn't make it go off early.
+        let previous = now() + ChronoDuration::minutes(10);
+        assert_eq!(
+            every_half_hour.next_after(previous, now(), hours(0)),
+            utc(1, 12, 40)
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use anyhow::Context as anyhowContext;
+use serenity::{
+    async_trait,
+    client::Context,
+    model::id::{GuildId, UserId},
+    prelude::TypeMapKey,
+};
+use songbird::{
+    events::EventHandler as VoiceEventHandler,
+    id::ChannelId,
+    tracks::{Track, TrackHandle},
+    Call, Event, EventContext, Songbird,
+};
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use crate::{settings::GuildSettings, storage::GuildStore};
+
+/// How many minutes the bot can sit in a voice channel without playing anything before it leaves.
+const IDLE_TIMEOUT_MINUTES: usize = 10;
+
+pub struct Sessions;
+impl TypeMapKey for Sessions {
+    type Value = Arc<SessionManager>;
+}
+
+pub async fn get_sessions_from_ctx(ctx: &Context) -> Arc<SessionManager> {
+    ctx.data
+        .read()
+        .await
+        .get::<Sessions>()
+        .expect("Session manager should be present")
+        .clone()
+}
+
+/// Everything the bot holds on to while it is connected to voice in a guild. A session is
+/// created when the bot joins a channel and torn down, along with everything it owns,
+/// whenever the bot leaves for whatever reason.
+pub struct GuildSession {
+    pub guild_id: GuildId,
+    call: Arc<Mutex<Call>>,
+    idle_minutes: AtomicUsize,
+    pending_departure: std::sync::Mutex<Option<JoinHandle<()>>>,
+    settings: Arc<GuildStore<GuildSettings>>,
+    /// Whoever last brought the bot into its current channel.
+    summoner: AtomicU64,
+    /// Whether the bot should switch channels along with its summoner.
+    follow_summoner: AtomicBool,
+}
+
+impl GuildSession {
+    /// The voice channel the bot is connected to, if it is connected at all.
+    pub async fn current_channel(&self) -> Option<ChannelId> {
+        self.call.lock().await.current_channel()
+    }
+
+    /// The current settings for this session's guild.
+    pub async fn settings(&self) -> anyhow::Result<GuildSettings> {
+        self.settings.get(self.guild_id).await
+    }
+
+    /// Add a track to the end of the playback queue.
+    pub async fn enqueue(&self, track: Track) {
+        self.call.lock().await.enqueue(track);
+    }
+
+    /// Skip whatever is currently playing.
+    pub async fn skip(&self) -> anyhow::Result<()> {
+        self.call.lock().await.queue().skip()?;
+        Ok(())
+    }
+
+    /// Skip a particular track, whether it's playing or still waiting its turn. Returns
+    /// whether it was found in the queue.
+    pub async fn skip_track(&self, track: &TrackHandle) -> anyhow::Result<bool> {
+        let call = self.call.lock().await;
+        let queue = call.queue();
+        let position = queue
+            .current_queue()
+            .iter()
+            .position(|t| t.uuid() == track.uuid());
+
+        match position {
+            None => Ok(false),
+            Some(0) => {
+                queue.skip()?;
+                Ok(true)
+            }
+            // dropping it from the queue drops the track, and whatever it was holding onto.
+            Some(i) => Ok(queue.dequeue(i).is_some()),
+        }
+    }
+
+    pub fn summoner(&self) -> UserId {
+        UserId(self.summoner.load(Ordering::SeqCst))
+    }
+
+    pub fn is_following_summoner(&self) -> bool {
+        self.follow_summoner.load(Ordering::SeqCst)
+    }
+
+    pub fn set_follow_summoner(&self, follow: bool) {
+        self.follow_summoner.store(follow, Ordering::SeqCst);
+    }
+
+    /// Reset the idle timer, e.g. because something just finished playing.
+    pub fn mark_active(&self) {
+        self.idle_minutes.store(0, Ordering::SeqCst);
+    }
+
+    /// Arrange for `handle` to be aborted if this session is torn down or the departure
+    /// is cancelled. Replaces any departure that was already pending.
+    pub fn set_pending_departure(&self, handle: JoinHandle<()>) {
+        let old = self
+            .pending_departure
+            .lock()
+            .expect("pending departure lock poisoned")
+            .replace(handle);
+        if let Some(old) = old {
+            old.abort();
+        }
+    }
+
+    pub fn has_pending_departure(&self) -> bool {
+        self.pending_departure
+            .lock()
+            .expect("pending departure lock poisoned")
+            .is_some()
+    }
+
+    /// Called by the departure task itself once it wakes up, so that it won't be aborted
+    /// halfway through leaving and a new departure can be scheduled later on.
+    pub fn departure_started(&self) {
+        self.pending_departure
+            .lock()
+            .expect("pending departure lock poisoned")
+            .take();
+    }
+
+    /// Cancel a pending departure, returning whether there was one.
+    pub fn cancel_departure(&self) -> bool {
+        match self
+            .pending_departure
+            .lock()
+            .expect("pending departure lock poisoned")
+            .take()
+        {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Owns every [`GuildSession`] and is the only thing that should be joining or leaving voice channels.
+pub struct SessionManager {
+    songbird: Arc<Songbird>,
+    settings: Arc<GuildStore<GuildSettings>>,
+    sessions: Mutex<HashMap<GuildId, Arc<GuildSession>>>,
+}
+
+impl SessionManager {
+    pub fn new(songbird: Arc<Songbird>, settings: Arc<GuildStore<GuildSettings>>) -> Self {
+        Self {
+            songbird,
+            settings,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get(&self, guild_id: GuildId) -> Option<Arc<GuildSession>> {
+        self.sessions.lock().await.get(&guild_id).cloned()
+    }
+
+    /// Join `channel_id` on behalf of `summoner`, reusing the existing session (and moving it
+    /// if need be) if the bot is already connected in this guild.
+    pub async fn join(
+        self: &Arc<Self>,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        summoner: UserId,
+    ) -> anyhow::Result<Arc<GuildSession>> {
+        // joining is a round trip to the gateway, so don't hold up every other guild's
+        // sessions while it happens.
+        if let Some(session) = self.get(guild_id).await {
+            if session.current_channel().await != Some(channel_id) {
+                tracing::debug!(?guild_id, ?channel_id, "Moving existing session");
+                let (_, success) = self.songbird.join(guild_id, channel_id).await;
+                success.context("Could not move to voice channel")?;
+            }
+            session.summoner.store(summoner.0, Ordering::SeqCst);
+            session.mark_active();
+            return Ok(session);
+        }
+
+        tracing::debug!(?guild_id, ?channel_id, "Starting new session");
+        let (call, success) = self.songbird.join(guild_id, channel_id).await;
+        if let Err(e) = success {
+            // don't leave a half-connected call lying around, unless someone else's join
+            // got there first and it's theirs now.
+            if self.get(guild_id).await.is_none() {
+                let _ = self.songbird.remove(guild_id).await;
+            }
+            return Err(e).context("Could not join voice channel");
+        }
+
+        let mut sessions = self.sessions.lock().await;
+        // another join may have started a session for this guild while we were connecting,
+        // in which case it's on the same call and we just use that one.
+        if let Some(session) = sessions.get(&guild_id).cloned() {
+            session.summoner.store(summoner.0, Ordering::SeqCst);
+            session.mark_active();
+            return Ok(session);
+        }
+
+        let session = Arc::new(GuildSession {
+            guild_id,
+            call: call.clone(),
+            idle_minutes: AtomicUsize::new(0),
+            pending_departure: std::sync::Mutex::new(None),
+            settings: self.settings.clone(),
+            summoner: AtomicU64::new(summoner.0),
+            follow_summoner: AtomicBool::new(false),
+        });
+        sessions.insert(guild_id, session.clone());
+        drop(sessions);
+
+        call.lock().await.add_global_event(
+            Event::Periodic(Duration::from_secs(60), None),
+            IdleTracker {
+                session: Arc::downgrade(&session),
+                manager: Arc::downgrade(self),
+            },
+        );
+
+        Ok(session)
+    }
+
+    /// Tear down the session for this guild, if there is one, and disconnect from voice.
+    /// Returns whether there was a session to tear down.
+    pub async fn leave(&self, guild_id: GuildId) -> anyhow::Result<bool> {
+        let session = match self.sessions.lock().await.remove(&guild_id) {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+
+        tracing::info!(?guild_id, "Ending voice session");
+        session.cancel_departure();
+        session.call.lock().await.queue().stop();
+
+        // removing the call drops the driver, and every event handler registered on it along with it.
+        match self.songbird.remove(guild_id).await {
+            Ok(()) | Err(songbird::error::JoinError::NoCall) => Ok(true),
+            Err(e) => Err(e).context("Could not leave voice channel"),
+        }
+    }
+}
+
+/// Counts up the minutes spent idle in a voice channel and ends the session once it's been too long.
+struct IdleTracker {
+    session: Weak<GuildSession>,
+    manager: Weak<SessionManager>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for IdleTracker {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        let (session, manager) = match (self.session.upgrade(), self.manager.upgrade()) {
+            (Some(s), Some(m)) => (s, m),
+            _ => return Some(Event::Cancel),
+        };
+
+        let idle_minutes = session.idle_minutes.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::debug!("Idle in voice channel for {} minutes!", idle_minutes);
+
+        if idle_minutes >= IDLE_TIMEOUT_MINUTES {
+            tracing::info!(
+                "Idle for {}+ minutes in guild {:?}, leaving!",
+                IDLE_TIMEOUT_MINUTES,
+                session.guild_id
+            );
+            if let Err(e) = manager.leave(session.guild_id).await {
+                tracing::error!(?e, guild_id=?session.guild_id, "Could not end idle session");
+            }
+        }
+
+        None
+    }
+}
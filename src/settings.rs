@@ -0,0 +1,97 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::FixedOffset;
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, UserId},
+    prelude::TypeMapKey,
+};
+
+use crate::{
+    audio::{earcon::EarconSettings, loudness::LoudnessSettings},
+    commands::permissions::CommandPermissions,
+    persona::Personas,
+    speech::VoiceOptions,
+    storage::GuildStore,
+    text::{filter::FilterSettings, normalize::NormalizationSettings, pronounce::Dictionary},
+};
+
+/// How long the bot sticks around in a voice channel with nobody else in it, unless configured otherwise.
+const DEFAULT_ALONE_TIMEOUT_SECONDS: u64 = 60;
+/// How long the record of what the bot said is kept, unless configured otherwise.
+const DEFAULT_HISTORY_RETENTION_DAYS: u64 = 30;
+
+pub struct Settings;
+impl TypeMapKey for Settings {
+    type Value = Arc<GuildStore<GuildSettings>>;
+}
+
+/// Everything a guild can configure about the bot. Every field needs a sensible
+/// default, since guilds start out with nothing stored at all.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// Who is allowed to run which subcommand, keyed by subcommand name.
+    pub permissions: HashMap<String, CommandPermissions>,
+    /// How long to wait before leaving once nobody else is in the voice channel.
+    pub alone_timeout_seconds: Option<u64>,
+    /// The voice each user would like to be spoken in.
+    pub voice_profiles: HashMap<UserId, VoiceOptions>,
+    /// A text channel whose messages get read out while the bot is in voice.
+    pub autoread_channel: Option<ChannelId>,
+    /// Users who want everything they type spoken while they're in the bot's voice channel.
+    pub speak_for_me: BTreeSet<UserId>,
+    /// How message text gets cleaned up before it's spoken.
+    pub normalization: NormalizationSettings,
+    /// How synthesized audio is evened out before it's played.
+    pub loudness: LoudnessSettings,
+    /// How consecutive utterances are set apart from each other.
+    pub earcon: EarconSettings,
+    /// How to pronounce words the synthesizer gets wrong, keyed by the lowercased word.
+    pub pronunciations: Dictionary,
+    /// Characters the bot can be asked to speak as, keyed by their lowercased name.
+    pub personas: Personas,
+    /// Words that shouldn't be said, and where to report attempts to say them.
+    pub filter: FilterSettings,
+    /// How many days to keep the record of what the bot said. Zero turns the record off.
+    pub history_retention_days: Option<u64>,
+    /// The guild's time zone as an offset from UTC, for scheduling things at a time of day.
+    pub utc_offset_minutes: i32,
+}
+
+impl GuildSettings {
+    pub fn alone_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.alone_timeout_seconds
+                .unwrap_or(DEFAULT_ALONE_TIMEOUT_SECONDS),
+        )
+    }
+
+    pub fn utc_offset(&self) -> FixedOffset {
+        FixedOffset::east_opt(self.utc_offset_minutes * 60).unwrap_or_else(|| FixedOffset::east(0))
+    }
+
+    pub fn history_retention(&self) -> Duration {
+        Duration::from_secs(
+            self.history_retention_days
+                .unwrap_or(DEFAULT_HISTORY_RETENTION_DAYS)
+                * 24
+                * 60
+                * 60,
+        )
+    }
+}
+
+pub async fn get_settings_from_ctx(ctx: &Context) -> Arc<GuildStore<GuildSettings>> {
+    ctx.data
+        .read()
+        .await
+        .get::<Settings>()
+        .expect("Settings store should be present")
+        .clone()
+}
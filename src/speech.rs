@@ -0,0 +1,630 @@
+use std::{
+    io::Write,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context as anyhowContext};
+use base64::{engine::general_purpose, Engine as _};
+use google_texttospeech1::api::{
+    AudioConfig, SynthesisInput, SynthesizeSpeechRequest, VoiceSelectionParams,
+};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    async_trait,
+    client::Context,
+    model::{
+        guild::Guild,
+        id::{ChannelId as SerenityChannelId, GuildId, UserId},
+        Timestamp,
+    },
+};
+use songbird::{
+    create_player, events::EventHandler as VoiceEventHandler, tracks::TrackHandle, Event,
+    EventContext, TrackEvent,
+};
+
+use crate::{
+    audio::{
+        earcon::{self, builtin_chime, level_chime, Chime},
+        effects::{self, Effect},
+        loudness, Pcm, SAMPLE_RATE,
+    },
+    audit::{self, AuditEntry},
+    chimes::get_chimes_from_ctx,
+    commands::say::{TtsService, VoiceValues, Voices},
+    replay::{get_replays_from_ctx, RecentUtterance},
+    session::GuildSession,
+    settings::GuildSettings,
+    text::{
+        filter::{self, FilterOutcome, FilterRule},
+        normalize::{normalize, user_name},
+        pronounce,
+        script::Segment,
+    },
+};
+
+const DEFAULT_LANGUAGE: &str = "en-US";
+/// How much of a blocked message to quote in the moderation channel.
+const MAX_REPORTED_LENGTH: usize = 1500;
+
+/// Which voice to speak with. Anything left unset is filled in when the voice is resolved.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct VoiceOptions {
+    /// Language code, e.g. `en-US`. Ignored if `name` is set.
+    pub language: Option<String>,
+    /// `MALE` or `FEMALE`. Ignored if `name` is set.
+    pub gender: Option<String>,
+    /// A specific voice, e.g. `en-GB-Wavenet-A`.
+    pub name: Option<String>,
+}
+
+impl VoiceOptions {
+    pub fn is_empty(&self) -> bool {
+        self.language.is_none() && self.gender.is_none() && self.name.is_none()
+    }
+
+    /// Pick a concrete voice out of `voices` that satisfies these options, returning its
+    /// language code and name. If more than one voice fits, `seed` is used to pick one
+    /// consistently, otherwise one is picked at random.
+    pub fn resolve(
+        &self,
+        voices: &VoiceValues,
+        seed: Option<u64>,
+    ) -> anyhow::Result<(String, String)> {
+        if let Some(ref name) = self.name {
+            return voices
+                .iter()
+                .find(|(_, vs)| vs.iter().any(|v| v.name.as_ref() == Some(name)))
+                .map(|(language, _)| (language.clone(), name.clone()))
+                .ok_or_else(|| anyhow!("No voice called {}", name));
+        }
+
+        let language_code = self
+            .language
+            .clone()
+            .unwrap_or_else(|| DEFAULT_LANGUAGE.to_owned());
+
+        let candidates = voices
+            .get(&language_code)
+            .context("No voices found for this language code!")?
+            .iter()
+            // if the gender is present, only keep voices that have that same gender.
+            .filter(|v| match self.gender {
+                Some(ref g) => v.ssml_gender.as_ref() == Some(g),
+                None => true,
+            })
+            .map(|v| v.name.clone().expect("Should have been a name here"))
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            return Err(anyhow!("No voices match the requested options"));
+        }
+
+        let index = match seed {
+            Some(s) => (s % candidates.len() as u64) as usize,
+            None => fastrand::usize(..candidates.len()),
+        };
+
+        Ok((language_code, candidates[index].clone()))
+    }
+}
+
+/// How something is said, beyond which voice says it.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Prosody {
+    /// How fast to speak, from 0.25 to 4.0 with 1.0 being the voice's normal speed.
+    pub speaking_rate: Option<f64>,
+    /// How far to shift the voice's pitch, in semitones from -20 to 20.
+    pub pitch: Option<f64>,
+    /// The kind of device to tune the audio for, e.g. `headphone-class-device`.
+    pub effects_profile: Option<String>,
+}
+
+/// Something for the bot to say.
+#[derive(Clone, Debug)]
+pub struct Utterance {
+    /// What to say. Treated as SSML markup if `is_ssml` is set, and as plain text otherwise.
+    pub text: String,
+    pub is_ssml: bool,
+    pub voice: VoiceOptions,
+    /// Used to consistently pick the same voice for the same speaker when `voice` leaves some leeway.
+    pub voice_seed: Option<u64>,
+    /// Who asked for this to be said, if anyone in particular.
+    pub requester: Option<UserId>,
+    pub prosody: Prosody,
+    /// Run on the audio once it's been synthesized, in order.
+    pub effects: Vec<Effect>,
+}
+
+/// Escape plain text so it can be embedded in SSML.
+pub fn escape_ssml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Let a guild's moderators know that someone tried to get us to say something blocked.
+async fn report_blocked(
+    ctx: &Context,
+    guild: Option<&Guild>,
+    settings: &GuildSettings,
+    utterance: &Utterance,
+    matched: &[FilterRule],
+    dropped: bool,
+) {
+    let channel = match settings.filter.moderation_channel {
+        Some(c) => c,
+        None => return,
+    };
+
+    let requester = match utterance.requester {
+        Some(id) => format!("{} (<@{}>)", user_name(ctx, guild, id), id.0),
+        None => "Someone".into(),
+    };
+    let patterns = matched
+        .iter()
+        .map(|r| format!("`{}`", r.pattern))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let quoted = utterance
+        .text
+        .chars()
+        .take(MAX_REPORTED_LENGTH)
+        .collect::<String>();
+    let content = format!(
+        "{} tried to make me say something matching {}, so I {}:\n```\n{}\n```",
+        requester,
+        patterns,
+        if dropped {
+            "didn't say it"
+        } else {
+            "censored it"
+        },
+        quoted.replace("```", "'''")
+    );
+
+    if let Err(e) = channel
+        .send_message(&ctx.http, |m| {
+            m.content(content).allowed_mentions(|a| a.empty_parse())
+        })
+        .await
+    {
+        tracing::error!(?e, ?channel, "Could not report blocked utterance");
+    }
+}
+
+/// Apply a guild's text processing to an utterance, so that it's ready to be synthesized.
+/// Returns `None` if the guild's filter says it shouldn't be said at all.
+pub async fn prepare(
+    ctx: &Context,
+    guild_id: GuildId,
+    settings: &GuildSettings,
+    utterance: &Utterance,
+) -> Option<Utterance> {
+    let guild = guild_id.to_guild_cached(&ctx.cache);
+    let mut text = normalize(
+        ctx,
+        guild.as_ref(),
+        &settings.normalization,
+        &utterance.text,
+        utterance.is_ssml,
+    );
+    let mut is_ssml = utterance.is_ssml;
+
+    // substitutions hide what's actually said in attributes the filter can't rewrite.
+    if is_ssml && !settings.filter.rules.is_empty() {
+        text = filter::strip_substitutions(&text);
+    }
+
+    // the filter runs on normalized text, so that it sees names rather than mentions.
+    match filter::apply(&settings.filter, &text, is_ssml) {
+        FilterOutcome::Unchanged => {}
+        FilterOutcome::Rewritten {
+            text: filtered,
+            matched,
+        } => {
+            report_blocked(ctx, guild.as_ref(), settings, utterance, &matched, false).await;
+            text = filtered;
+            is_ssml = true;
+        }
+        FilterOutcome::Dropped { matched } => {
+            tracing::info!(?guild_id, requester=?utterance.requester, "Dropping filtered utterance");
+            report_blocked(ctx, guild.as_ref(), settings, utterance, &matched, true).await;
+            return None;
+        }
+        FilterOutcome::Unreadable => {
+            tracing::info!(?guild_id, requester=?utterance.requester, "Dropping SSML the filter can't check");
+            return None;
+        }
+    }
+
+    // pronunciations come last, so they also apply to names that mentions were resolved to.
+    if let Some(pronounced) = pronounce::apply(&settings.pronunciations, &text, is_ssml) {
+        text = pronounced;
+        is_ssml = true;
+    }
+
+    Some(Utterance {
+        text,
+        is_ssml,
+        ..utterance.clone()
+    })
+}
+
+/// The audio formats the TTS API can hand back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    /// Uncompressed WAV, which is what we play back ourselves.
+    Wav,
+    Mp3,
+    OggOpus,
+}
+
+impl AudioFormat {
+    fn api_encoding(&self) -> &'static str {
+        match self {
+            Self::Wav => "LINEAR16",
+            Self::Mp3 => "MP3",
+            Self::OggOpus => "OGG_OPUS",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Mp3 => "mp3",
+            Self::OggOpus => "ogg",
+        }
+    }
+}
+
+/// Audio that came back from the TTS API.
+pub struct Speech {
+    pub audio: Vec<u8>,
+    /// The name of the voice it was spoken in.
+    pub voice: String,
+}
+
+/// Run an utterance through the TTS API, returning the synthesized audio.
+pub async fn synthesize(
+    ctx: &Context,
+    utterance: &Utterance,
+    format: AudioFormat,
+) -> anyhow::Result<Speech> {
+    let data = ctx.data.read().await;
+    let voices = data
+        .get::<Voices>()
+        .expect("There should have been voices here.");
+    let (language_code, voice) = utterance.voice.resolve(voices, utterance.voice_seed)?;
+
+    let tts_service = data
+        .get::<TtsService>()
+        .expect("There should have been a TTS service here.");
+
+    let ssml = if utterance.is_ssml {
+        utterance.text.clone()
+    } else {
+        escape_ssml(&utterance.text)
+    };
+
+    let req = SynthesizeSpeechRequest {
+        audio_config: Some(AudioConfig {
+            audio_encoding: Some(format.api_encoding().to_string()),
+            effects_profile_id: utterance.prosody.effects_profile.clone().map(|p| vec![p]),
+            pitch: Some(utterance.prosody.pitch.unwrap_or(0.0)),
+            // fixed, so that separately synthesized clips can be joined together.
+            sample_rate_hertz: (format == AudioFormat::Wav).then_some(SAMPLE_RATE as i32),
+            speaking_rate: utterance.prosody.speaking_rate,
+            volume_gain_db: None,
+        }),
+        input: Some(SynthesisInput {
+            ssml: Some(format!("<speak>{}</speak>", ssml)),
+            text: None,
+        }),
+        voice: Some(VoiceSelectionParams {
+            language_code: Some(language_code),
+            name: Some(voice.clone()),
+            ssml_gender: None,
+        }),
+    };
+
+    let (_, res) = tts_service
+        .text()
+        .synthesize(req)
+        .doit()
+        .await
+        .context("Could not make TTS API call")?;
+
+    let audio = match res.audio_content {
+        Some(c) => general_purpose::STANDARD
+            .decode(c)
+            .context("Could not decode base64 audio content!")?,
+        None => return Err(anyhow!("No audio content returned from API!")),
+    };
+
+    Ok(Speech { audio, voice })
+}
+
+struct TrackCleanup {
+    session: Weak<GuildSession>,
+    /// Unused, we want to tie the lifetimes together so that the temp file is cleaned up.
+    _tmpfile: tempfile::NamedTempFile,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackCleanup {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        // reset the idle tracker for this guild.
+        if let Some(session) = self.session.upgrade() {
+            session.mark_active();
+        }
+        None
+    }
+}
+
+/// Queue up synthesized audio for playback in this session, trimmed and normalized the way
+/// the guild has configured, with the guild's chime before it and its gap after it.
+pub async fn enqueue_audio(
+    ctx: &Context,
+    session: &Arc<GuildSession>,
+    audio: &[u8],
+) -> anyhow::Result<TrackHandle> {
+    let settings = session.settings().await?;
+    let chime = match settings.earcon.chime {
+        Chime::Off => None,
+        Chime::Builtin => Some(builtin_chime()),
+        Chime::Custom => get_chimes_from_ctx(ctx)
+            .await
+            .get(session.guild_id)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!(?e, guild_id=?session.guild_id, "Could not load chime");
+                None
+            }),
+    }
+    .map(|mut chime| {
+        level_chime(&mut chime, &settings.loudness);
+        chime
+    });
+
+    enqueue(
+        session,
+        &settings,
+        audio,
+        chime.as_ref(),
+        settings.earcon.gap(),
+    )
+    .await
+}
+
+/// Queue up audio that's part of something longer, like a timer's countdown. It's leveled
+/// like everything else, but goes without the guild's chime and gap.
+pub async fn enqueue_cue(session: &Arc<GuildSession>, audio: &[u8]) -> anyhow::Result<TrackHandle> {
+    let settings = session.settings().await?;
+    enqueue(session, &settings, audio, None, Duration::ZERO).await
+}
+
+async fn enqueue(
+    session: &Arc<GuildSession>,
+    settings: &GuildSettings,
+    audio: &[u8],
+    chime: Option<&Pcm>,
+    gap: Duration,
+) -> anyhow::Result<TrackHandle> {
+    let mastered = if settings.loudness.is_enabled() || chime.is_some() || !gap.is_zero() {
+        match Pcm::from_wav(audio) {
+            Ok(mut pcm) => {
+                // measuring true peaks means oversampling the whole clip, so keep it off the executor.
+                let loudness = settings.loudness.clone();
+                let chime = chime.cloned();
+                let mastered = tokio::task::spawn_blocking(move || {
+                    loudness::level(&mut pcm, &loudness);
+                    earcon::surround(&mut pcm, chime.as_ref(), gap);
+                    pcm.to_wav()
+                })
+                .await?;
+                Some(mastered)
+            }
+            Err(e) => {
+                tracing::warn!(?e, guild_id=?session.guild_id, "Could not process audio, playing it as it is");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut file = tempfile::NamedTempFile::new()?;
+    file.write_all(mastered.as_deref().unwrap_or(audio))?;
+
+    let input = songbird::ffmpeg(file.path())
+        .await
+        .map_err(|_| anyhow!("Could not create ffmpeg player"))?;
+
+    let (track, track_handle) = create_player(input);
+
+    track_handle.add_event(
+        Event::Track(TrackEvent::End),
+        TrackCleanup {
+            _tmpfile: file,
+            session: Arc::downgrade(session),
+        },
+    )?;
+
+    session.enqueue(track).await;
+    Ok(track_handle)
+}
+
+/// Prepare and synthesize an utterance, then queue it up for playback in this session.
+/// Returns the ID it was remembered under in the replay buffer, or `None` if nothing was
+/// queued up, since there may be nothing left to say.
+pub async fn speak(
+    ctx: &Context,
+    session: &Arc<GuildSession>,
+    utterance: &Utterance,
+) -> anyhow::Result<Option<u64>> {
+    let settings = session.settings().await?;
+    let prepared = match prepare(ctx, session.guild_id, &settings, utterance).await {
+        Some(u) if !u.text.trim().is_empty() => u,
+        _ => return Ok(None),
+    };
+
+    let speech = synthesize(ctx, &prepared, AudioFormat::Wav).await?;
+    play(
+        ctx,
+        session,
+        &settings,
+        utterance.requester,
+        &utterance.text,
+        speech,
+        &utterance.effects,
+    )
+    .await
+    .map(Some)
+}
+
+/// Synthesize a dialogue script, each segment in its character's voice, and join them
+/// into a single WAV clip. Segments without a voice of their own use `utterance`'s. In a
+/// guild, every segment is prepared with its settings first. Returns `None` if the
+/// guild's filter dropped any part of it, or there was nothing left to say.
+pub async fn synthesize_script(
+    ctx: &Context,
+    guild: Option<(GuildId, &GuildSettings)>,
+    utterance: &Utterance,
+    segments: &[Segment],
+) -> anyhow::Result<Option<Speech>> {
+    let mut audio: Option<Pcm> = None;
+    let mut voices: Vec<String> = Vec::new();
+
+    for segment in segments {
+        let line = Utterance {
+            text: segment.text.clone(),
+            voice: segment
+                .voice
+                .clone()
+                .unwrap_or_else(|| utterance.voice.clone()),
+            ..utterance.clone()
+        };
+        let prepared = match guild {
+            Some((guild_id, settings)) => match prepare(ctx, guild_id, settings, &line).await {
+                Some(u) if u.text.trim().is_empty() => continue,
+                Some(u) => u,
+                None => return Ok(None),
+            },
+            None => line,
+        };
+
+        let speech = synthesize(ctx, &prepared, AudioFormat::Wav).await?;
+        let clip = Pcm::from_wav(&speech.audio).context("Could not read synthesized audio")?;
+        match audio {
+            Some(ref mut joined) => joined.append(&clip)?,
+            None => audio = Some(clip),
+        }
+        if !voices.contains(&speech.voice) {
+            voices.push(speech.voice);
+        }
+    }
+
+    Ok(audio.map(|audio| Speech {
+        audio: audio.to_wav(),
+        voice: voices.join(", "),
+    }))
+}
+
+/// Say a dialogue script, synthesized with `synthesize_script` into a single track.
+/// Returns `None` if the guild's filter dropped any part of it.
+pub async fn speak_script(
+    ctx: &Context,
+    session: &Arc<GuildSession>,
+    utterance: &Utterance,
+    segments: &[Segment],
+) -> anyhow::Result<Option<u64>> {
+    let settings = session.settings().await?;
+    let speech = match synthesize_script(
+        ctx,
+        Some((session.guild_id, &settings)),
+        utterance,
+        segments,
+    )
+    .await?
+    {
+        Some(speech) => speech,
+        None => return Ok(None),
+    };
+    play(
+        ctx,
+        session,
+        &settings,
+        utterance.requester,
+        &utterance.text,
+        speech,
+        &utterance.effects,
+    )
+    .await
+    .map(Some)
+}
+
+/// Queue up audio that's already been synthesized, keeping track of it in the replay
+/// buffer and audit log the same as anything else we say. `text` is what was asked for,
+/// before any processing. The replay buffer keeps the audio from before `effects` were
+/// run, so it can be played again with others. Returns the ID it was remembered under in
+/// the replay buffer.
+pub async fn play(
+    ctx: &Context,
+    session: &Arc<GuildSession>,
+    settings: &GuildSettings,
+    requester: Option<UserId>,
+    text: &str,
+    speech: Speech,
+    effects: &[Effect],
+) -> anyhow::Result<u64> {
+    let Speech { audio, voice } = speech;
+    let audio: Arc<[u8]> = audio.into();
+    let track = enqueue_audio(ctx, session, &effects::apply(&audio, effects).await?).await?;
+
+    let timestamp = Timestamp::now();
+    let replays = get_replays_from_ctx(ctx).await;
+    let id = replays.next_id();
+    replays.remember(
+        session.guild_id,
+        RecentUtterance {
+            id,
+            timestamp,
+            user: requester,
+            text: text.to_owned(),
+            voice: voice.clone(),
+            audio,
+            effects: effects.to_vec(),
+            track,
+        },
+    );
+
+    let entry = AuditEntry {
+        timestamp,
+        guild_id: session.guild_id,
+        user: requester,
+        channel: session
+            .current_channel()
+            .await
+            .map(|c| SerenityChannelId(c.0)),
+        text: text.to_owned(),
+        voice,
+    };
+    if let Err(e) = audit::record(ctx, session.guild_id, settings, entry).await {
+        tracing::error!(?e, guild_id=?session.guild_id, "Could not record utterance in the audit log");
+    }
+
+    Ok(id)
+}
@@ -0,0 +1,99 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as anyhowContext;
+use serde::{de::DeserializeOwned, Serialize};
+use serenity::model::id::GuildId;
+use tokio::sync::RwLock;
+
+/// A very simple persistent key-value store that keeps one JSON document per guild
+/// on disk, with an in-memory cache in front of it. Good enough for the small amounts
+/// of per-guild state this bot needs to hold on to across restarts.
+pub struct GuildStore<T> {
+    directory: PathBuf,
+    entries: RwLock<HashMap<GuildId, T>>,
+}
+
+impl<T> GuildStore<T>
+where
+    T: Serialize + DeserializeOwned + Default + Clone + Send + Sync,
+{
+    pub fn new(directory: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Could not create data directory {:?}", directory))?;
+
+        Ok(Self {
+            directory,
+            entries: RwLock::new(HashMap::new()),
+        })
+    }
+
+    fn path_for(&self, guild_id: GuildId) -> PathBuf {
+        self.directory.join(format!("{}.json", guild_id.0))
+    }
+
+    async fn load(&self, guild_id: GuildId) -> anyhow::Result<T> {
+        let path = self.path_for(guild_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Could not parse stored data at {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+            Err(e) => Err(e).with_context(|| format!("Could not read stored data at {:?}", path)),
+        }
+    }
+
+    async fn persist(&self, guild_id: GuildId, value: &T) -> anyhow::Result<()> {
+        let path = self.path_for(guild_id);
+        let tmp_path = path.with_extension("json.tmp");
+        let bytes = serde_json::to_vec_pretty(value).context("Could not serialize data")?;
+
+        // write to a temporary file first so that a crash halfway through
+        // doesn't leave us with a truncated document.
+        tokio::fs::write(&tmp_path, bytes)
+            .await
+            .with_context(|| format!("Could not write stored data to {:?}", tmp_path))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .with_context(|| format!("Could not move stored data into place at {:?}", path))?;
+
+        Ok(())
+    }
+
+    /// Get a copy of the stored value for this guild, or the default if nothing has been stored yet.
+    pub async fn get(&self, guild_id: GuildId) -> anyhow::Result<T> {
+        if let Some(v) = self.entries.read().await.get(&guild_id) {
+            return Ok(v.clone());
+        }
+
+        let mut entries = self.entries.write().await;
+        // someone may have loaded it while we were waiting on the write lock.
+        if let Some(v) = entries.get(&guild_id) {
+            return Ok(v.clone());
+        }
+
+        let v = self.load(guild_id).await?;
+        entries.insert(guild_id, v.clone());
+        Ok(v)
+    }
+
+    /// Modify the stored value for this guild in place and write the result back to disk.
+    pub async fn update<F, R>(&self, guild_id: GuildId, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        let mut entries = self.entries.write().await;
+        let mut value = match entries.get(&guild_id) {
+            Some(v) => v.clone(),
+            None => self.load(guild_id).await?,
+        };
+
+        let r = f(&mut value);
+        self.persist(guild_id, &value).await?;
+        entries.insert(guild_id, value);
+
+        Ok(r)
+    }
+}
@@ -0,0 +1,430 @@
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::ChannelId;
+
+use super::{markup_matches, word_pattern};
+use crate::speech::escape_ssml;
+
+/// Keeps anyone from making us compile something enormous.
+const MAX_PATTERN_SIZE: usize = 1 << 16;
+
+/// Tags that make the synthesizer say something other than the text they wrap.
+static SUBSTITUTION_TAG: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)</?\s*(sub|phoneme)\b[^>]*>").unwrap());
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<\s*/?\s*([A-Za-z][\w:-]*)\b[^<>]*>").unwrap());
+static ENTITY: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"&(?:#([0-9]{1,7})|#[xX]([0-9A-Fa-f]{1,6})|([A-Za-z]+));").unwrap());
+
+/// Tags that sit between separate words or sentences, rather than possibly inside a word.
+const SEPARATING_TAGS: &[&str] = &["speak", "p", "s", "par", "seq", "media"];
+/// Tags that make the synthesizer say or play something we can't see in the text.
+const OPAQUE_TAGS: &[&str] = &["audio", "sub", "phoneme"];
+
+/// What to do with text that matches a filter rule.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterAction {
+    /// Bleep out the matching words.
+    Beep,
+    /// Don't say anything at all.
+    Drop,
+    /// Say something else in place of the matching words.
+    Replace,
+}
+
+impl FilterAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Beep => "beep",
+            Self::Drop => "drop",
+            Self::Replace => "replace",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "beep" => Some(Self::Beep),
+            "drop" => Some(Self::Drop),
+            "replace" => Some(Self::Replace),
+            _ => None,
+        }
+    }
+}
+
+/// A single entry in a guild's blocklist.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FilterRule {
+    /// A word or phrase, or a regular expression if `is_regex` is set. Matched regardless of case.
+    pub pattern: String,
+    #[serde(default)]
+    pub is_regex: bool,
+    pub action: FilterAction,
+    /// What to say instead, for rules that replace.
+    #[serde(default)]
+    pub replacement: Option<String>,
+}
+
+impl FilterRule {
+    /// Compile the rule's pattern. Literal patterns only match whole words.
+    pub fn compile(&self) -> Result<Regex, regex::Error> {
+        let pattern = if self.is_regex {
+            self.pattern.clone()
+        } else {
+            word_pattern(&self.pattern)
+        };
+
+        RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .size_limit(MAX_PATTERN_SIZE)
+            .build()
+    }
+}
+
+/// A guild's blocklist for spoken text.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct FilterSettings {
+    pub rules: Vec<FilterRule>,
+    /// Where to report blocked attempts, if anywhere.
+    pub moderation_channel: Option<ChannelId>,
+}
+
+/// What the filter did to some text.
+pub enum FilterOutcome {
+    /// Nothing matched.
+    Unchanged,
+    /// Some words were beeped or replaced. The new text is always SSML.
+    Rewritten {
+        text: String,
+        matched: Vec<FilterRule>,
+    },
+    /// The whole thing shouldn't be said.
+    Dropped { matched: Vec<FilterRule> },
+    /// The text is SSML we couldn't work out the spoken words of, so it can't be checked.
+    Unreadable,
+}
+
+/// Take out any `<sub>` and `<phoneme>` tags, keeping the text inside them. What they say
+/// instead lives in their attributes, which the filter can't rewrite, so they'd otherwise be
+/// a way to say a blocked word.
+pub fn strip_substitutions(ssml: &str) -> String {
+    SUBSTITUTION_TAG.replace_all(ssml, "").into_owned()
+}
+
+fn decode_entity(entity: &regex::Captures) -> Option<char> {
+    if let Some(decimal) = entity.get(1) {
+        return char::from_u32(decimal.as_str().parse().ok()?);
+    }
+    if let Some(hex) = entity.get(2) {
+        return char::from_u32(u32::from_str_radix(hex.as_str(), 16).ok()?);
+    }
+    match entity.get(3)?.as_str() {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => None,
+    }
+}
+
+/// Decode the character entities in a run of SSML text, or `None` if there's one we don't know.
+fn decode_entities(text: &str) -> Option<String> {
+    let mut decoded = String::with_capacity(text.len());
+    let mut last = 0;
+    for entity in ENTITY.captures_iter(text) {
+        let whole = entity.get(0).unwrap();
+        decoded.push_str(&text[last..whole.start()]);
+        decoded.push(decode_entity(&entity)?);
+        last = whole.end();
+    }
+    decoded.push_str(&text[last..]);
+
+    // anything left over is a stray ampersand or an entity we couldn't read.
+    let undecoded = ENTITY.replace_all(text, "").contains('&');
+    (!undecoded).then_some(decoded)
+}
+
+/// The plain text the synthesizer will actually say for some SSML: every tag taken out and
+/// character entities decoded. Tags that only change how words are said are removed
+/// outright, so they can't split a word in two. Returns `None` for markup we can't be
+/// sure of, like comments, stray angle brackets, unknown entities or tags that say
+/// something that isn't in the text.
+pub fn spoken_text(ssml: &str) -> Option<String> {
+    let mut spoken = String::with_capacity(ssml.len());
+    let mut last = 0;
+    for tag in TAG.captures_iter(ssml) {
+        let whole = tag.get(0).unwrap();
+        let name = tag[1].to_ascii_lowercase();
+        if OPAQUE_TAGS.contains(&name.as_str()) {
+            return None;
+        }
+
+        let between = &ssml[last..whole.start()];
+        if between.contains(['<', '>']) {
+            return None;
+        }
+        spoken.push_str(&decode_entities(between)?);
+        if SEPARATING_TAGS.contains(&name.as_str()) {
+            spoken.push(' ');
+        }
+        last = whole.end();
+    }
+
+    let rest = &ssml[last..];
+    if rest.contains(['<', '>']) {
+        return None;
+    }
+    spoken.push_str(&decode_entities(rest)?);
+    Some(spoken)
+}
+
+/// Run `text` past a guild's blocklist. SSML should have been through
+/// `strip_substitutions` first, and is checked as the text it'll actually say. If any of
+/// that matches, the rewritten text is built from those words, leaving the markup behind,
+/// since there's no telling where in it a match that spans tags belongs.
+pub fn apply(settings: &FilterSettings, text: &str, is_ssml: bool) -> FilterOutcome {
+    if settings.rules.is_empty() {
+        return FilterOutcome::Unchanged;
+    }
+    if is_ssml {
+        return match spoken_text(text) {
+            Some(spoken) => apply(settings, &spoken, false),
+            None => FilterOutcome::Unreadable,
+        };
+    }
+
+    let compiled = settings
+        .rules
+        .iter()
+        .filter_map(|rule| match rule.compile() {
+            Ok(pattern) => Some((rule, pattern)),
+            Err(e) => {
+                // rules are checked when they're added, so this only happens if the file was edited.
+                tracing::warn!(
+                    ?e,
+                    pattern = rule.pattern.as_str(),
+                    "Skipping invalid filter rule"
+                );
+                None
+            }
+        })
+        .filter(|(_, pattern)| pattern.is_match(text))
+        .collect::<Vec<_>>();
+
+    if compiled.is_empty() {
+        return FilterOutcome::Unchanged;
+    }
+
+    let matched = compiled
+        .iter()
+        .map(|(rule, _)| (*rule).clone())
+        .collect::<Vec<_>>();
+    if matched.iter().any(|r| r.action == FilterAction::Drop) {
+        return FilterOutcome::Dropped { matched };
+    }
+
+    let mut text = text.to_owned();
+    let mut is_ssml = is_ssml;
+    for (rule, pattern) in compiled {
+        text = markup_matches(&pattern, &text, is_ssml, |_, original| match rule.action {
+            FilterAction::Beep => {
+                format!("<say-as interpret-as=\"expletive\">{}</say-as>", original)
+            }
+            FilterAction::Replace => escape_ssml(rule.replacement.as_deref().unwrap_or_default()),
+            FilterAction::Drop => original.to_owned(),
+        });
+        is_ssml = true;
+    }
+
+    FilterOutcome::Rewritten { text, matched }
+}
+
+/// Text as it's safe to show back in a channel, with whatever the filter caught in it
+/// swapped out for what was said instead.
+pub fn redact(settings: &FilterSettings, text: &str) -> String {
+    let mut text = text.to_owned();
+    for rule in &settings.rules {
+        let pattern = match rule.compile() {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        let shown = match rule.action {
+            FilterAction::Beep => "[beep]",
+            FilterAction::Drop => "[filtered]",
+            FilterAction::Replace => rule.replacement.as_deref().unwrap_or_default(),
+        };
+        text = pattern
+            .replace_all(&text, regex::NoExpand(shown))
+            .into_owned();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(action: FilterAction) -> FilterSettings {
+        FilterSettings {
+            rules: vec![FilterRule {
+                pattern: "badword".into(),
+                is_regex: false,
+                action,
+                replacement: Some("something nice".into()),
+            }],
+            moderation_channel: None,
+        }
+    }
+
+    fn rewritten(outcome: FilterOutcome) -> String {
+        match outcome {
+            FilterOutcome::Rewritten { text, .. } => text,
+            FilterOutcome::Unchanged => panic!("expected a rewrite, nothing matched"),
+            FilterOutcome::Dropped { .. } => panic!("expected a rewrite, it was dropped"),
+            FilterOutcome::Unreadable => panic!("expected a rewrite, it couldn't be read"),
+        }
+    }
+
+    #[test]
+    fn beeps_plain_text() {
+        let text = rewritten(apply(
+            &settings(FilterAction::Beep),
+            "a BadWord here",
+            false,
+        ));
+        assert_eq!(
+            text,
+            "a <say-as interpret-as=\"expletive\">BadWord</say-as> here"
+        );
+    }
+
+    #[test]
+    fn replaces_only_whole_words() {
+        let filter = settings(FilterAction::Replace);
+        assert!(matches!(
+            apply(&filter, "badwords", false),
+            FilterOutcome::Unchanged
+        ));
+        assert_eq!(
+            rewritten(apply(&filter, "<speak>badword</speak>", true)),
+            " something nice "
+        );
+    }
+
+    #[test]
+    fn redacts_for_display() {
+        assert_eq!(
+            redact(&settings(FilterAction::Beep), "a badword and $1"),
+            "a [beep] and $1"
+        );
+        assert_eq!(
+            redact(&settings(FilterAction::Replace), "BADWORD!"),
+            "something nice!"
+        );
+    }
+
+    #[test]
+    fn substitution_tags_cannot_hide_blocked_words() {
+        let ssml = strip_substitutions(
+            "<speak><sub alias=\"badword\">x</sub> <phoneme alphabet=\"ipa\" ph=\"b\">y</phoneme></speak>",
+        );
+        assert_eq!(ssml, "<speak>x y</speak>");
+        assert!(matches!(
+            apply(&settings(FilterAction::Beep), &ssml, true),
+            FilterOutcome::Unchanged
+        ));
+
+        let ssml = strip_substitutions("<SUB ALIAS='x'>badword</SUB>");
+        assert!(matches!(
+            apply(&settings(FilterAction::Drop), &ssml, true),
+            FilterOutcome::Dropped { .. }
+        ));
+    }
+
+    #[test]
+    fn empty_tags_cannot_split_blocked_words() {
+        for ssml in [
+            "bad<break time=\"0ms\"/>word",
+            "bad<mark name=\"x\"/>word",
+            "<speak>b<say-as interpret-as=\"characters\">ad</say-as>word</speak>",
+            "<prosody rate=\"fast\">bad</prosody><emphasis>word</emphasis>",
+        ] {
+            assert!(
+                matches!(
+                    apply(&settings(FilterAction::Drop), ssml, true),
+                    FilterOutcome::Dropped { .. }
+                ),
+                "{} got through",
+                ssml
+            );
+        }
+    }
+
+    #[test]
+    fn entities_cannot_hide_blocked_words() {
+        for ssml in ["b&#97;dword", "b&#x61;dword", "&#X62;adword"] {
+            assert!(
+                matches!(
+                    apply(&settings(FilterAction::Drop), ssml, true),
+                    FilterOutcome::Dropped { .. }
+                ),
+                "{} got through",
+                ssml
+            );
+        }
+        assert_eq!(
+            rewritten(apply(
+                &settings(FilterAction::Beep),
+                "<p>fish &amp; b&#97;dword</p>",
+                true
+            )),
+            " fish &amp; <say-as interpret-as=\"expletive\">badword</say-as> "
+        );
+    }
+
+    #[test]
+    fn separate_sentences_stay_separate_words() {
+        assert_eq!(
+            spoken_text("<s>good</s><s>badword</s>").as_deref(),
+            Some(" good  badword ")
+        );
+        assert!(matches!(
+            apply(
+                &settings(FilterAction::Drop),
+                "<p>fine</p><p>badword</p>",
+                true
+            ),
+            FilterOutcome::Dropped { .. }
+        ));
+    }
+
+    #[test]
+    fn rejects_ssml_it_cannot_read() {
+        for ssml in [
+            "bad<!-- -->word",
+            "<audio src=\"https://example.com/a.mp3\">hi</audio>",
+            "a < b",
+            "fish & chips",
+            "b&nbsp;adword",
+        ] {
+            assert!(
+                matches!(
+                    apply(&settings(FilterAction::Beep), ssml, true),
+                    FilterOutcome::Unreadable
+                ),
+                "{} was accepted",
+                ssml
+            );
+        }
+        assert!(matches!(
+            apply(
+                &settings(FilterAction::Beep),
+                "<speak>hello &lt;3</speak>",
+                true
+            ),
+            FilterOutcome::Unchanged
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::speech::escape_ssml;
+
+pub mod filter;
+pub mod normalize;
+pub mod pronounce;
+pub mod script;
+
+static SSML_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// A pattern that matches `word` on its own, not as part of a longer word.
+pub fn word_pattern(word: &str) -> String {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    // words that start or end with punctuation can't have a word boundary there.
+    let start = if word.starts_with(is_word_char) {
+        r"\b"
+    } else {
+        ""
+    };
+    let end = if word.ends_with(is_word_char) {
+        r"\b"
+    } else {
+        ""
+    };
+    format!("{}{}{}", start, regex::escape(word), end)
+}
+
+fn markup_segment<F>(pattern: &Regex, text: &str, escape: bool, markup: &mut F) -> String
+where
+    F: FnMut(&str, &str) -> String,
+{
+    let escape_if_needed = |s: &str| {
+        if escape {
+            escape_ssml(s)
+        } else {
+            s.to_owned()
+        }
+    };
+
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for m in pattern.find_iter(text) {
+        result.push_str(&escape_if_needed(&text[last..m.start()]));
+        result.push_str(&markup(m.as_str(), &escape_if_needed(m.as_str())));
+        last = m.end();
+    }
+    result.push_str(&escape_if_needed(&text[last..]));
+    result
+}
+
+/// Replace everything `pattern` matches in `text` with SSML produced by `markup`, which is
+/// given both the raw match and the match as it's safe to embed in SSML. The result is always
+/// SSML, so plain text is escaped along the way, and in SSML only the text between tags is
+/// touched so that attributes are left alone.
+pub fn markup_matches<F>(pattern: &Regex, text: &str, is_ssml: bool, mut markup: F) -> String
+where
+    F: FnMut(&str, &str) -> String,
+{
+    if !is_ssml {
+        return markup_segment(pattern, text, true, &mut markup);
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for tag in SSML_TAG.find_iter(text) {
+        result.push_str(&markup_segment(
+            pattern,
+            &text[last..tag.start()],
+            false,
+            &mut markup,
+        ));
+        result.push_str(tag.as_str());
+        last = tag.end();
+    }
+    result.push_str(&markup_segment(pattern, &text[last..], false, &mut markup));
+    result
+}
@@ -0,0 +1,342 @@
+use std::borrow::Cow;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::{
+        channel::Channel,
+        guild::Guild,
+        id::{ChannelId, RoleId, UserId},
+    },
+};
+
+use crate::speech::escape_ssml;
+
+/// The longest run of characters a single emoji can take up, e.g. a family with skin tones.
+const MAX_EMOJI_CHARS: usize = 10;
+
+static CODE_BLOCK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)```.*?```").unwrap());
+static SPOILER: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)\|\|(.+?)\|\|").unwrap());
+static MASKED_LINK: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\[([^\]]+)\]\(<?https?://[^\s)]+>?\)").unwrap());
+static URL: Lazy<Regex> = Lazy::new(|| Regex::new(r"<?https?://([^\s/?#<>:]+)[^\s<>]*>?").unwrap());
+static USER_MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"<@!?(\d+)>").unwrap());
+static ROLE_MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"<@&(\d+)>").unwrap());
+static CHANNEL_MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"<#(\d+)>").unwrap());
+static COMMAND_MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"</([^:>]+):\d+>").unwrap());
+static EVERYONE_MENTION: Lazy<Regex> = Lazy::new(|| Regex::new(r"@(everyone|here)\b").unwrap());
+static CUSTOM_EMOJI: Lazy<Regex> = Lazy::new(|| Regex::new(r"<a?:(\w+):\d+>").unwrap());
+static INLINE_CODE: Lazy<Regex> = Lazy::new(|| Regex::new(r"`([^`]+)`").unwrap());
+static BOLD: Lazy<Regex> = Lazy::new(|| Regex::new(r"\*\*(.+?)\*\*").unwrap());
+static UNDERLINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"__(.+?)__").unwrap());
+static STRIKETHROUGH: Lazy<Regex> = Lazy::new(|| Regex::new(r"~~(.+?)~~").unwrap());
+static ITALIC_STAR: Lazy<Regex> = Lazy::new(|| Regex::new(r"\*(\S(?:[^*]*\S)?)\*").unwrap());
+static ITALIC_UNDERSCORE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b_([^_]+)_\b").unwrap());
+static LINE_PREFIX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?m)^\s*(?:#{1,3}\s+|>>>\s?|>\s?|[-*]\s+)").unwrap());
+static WHITESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+").unwrap());
+
+/// Which normalization steps a guild wants applied to text before it's spoken.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct NormalizationSettings {
+    /// Replace user, role and channel mentions with their names.
+    pub mentions: bool,
+    /// Replace custom emoji with their names and Unicode emoji with a description.
+    pub emoji: bool,
+    /// Replace URLs with "link to" and the site they point to.
+    pub links: bool,
+    /// Strip markdown formatting and drop code blocks.
+    pub markdown: bool,
+    /// Drop spoilered text entirely rather than reading it out.
+    pub skip_spoilers: bool,
+}
+
+impl Default for NormalizationSettings {
+    fn default() -> Self {
+        Self {
+            mentions: true,
+            emoji: true,
+            links: true,
+            markdown: true,
+            skip_spoilers: false,
+        }
+    }
+}
+
+/// Something we put into the text ourselves, which has to be escaped if the text is SSML.
+fn insert(text: &str, is_ssml: bool) -> String {
+    if is_ssml {
+        escape_ssml(text)
+    } else {
+        text.to_owned()
+    }
+}
+
+/// The name a user goes by in `guild`, falling back on their username.
+pub fn user_name(ctx: &Context, guild: Option<&Guild>, id: UserId) -> String {
+    guild
+        .and_then(|g| g.members.get(&id))
+        .map(|m| m.display_name().into_owned())
+        .or_else(|| ctx.cache.user(id).map(|u| u.name))
+        .unwrap_or_else(|| "someone".into())
+}
+
+fn role_name(guild: Option<&Guild>, id: RoleId) -> String {
+    guild
+        .and_then(|g| g.roles.get(&id))
+        .map(|r| r.name.clone())
+        .unwrap_or_else(|| "a role".into())
+}
+
+fn channel_name(ctx: &Context, guild: Option<&Guild>, id: ChannelId) -> String {
+    guild
+        .and_then(|g| g.channels.get(&id))
+        .and_then(|c| match c {
+            Channel::Guild(gc) => Some(gc.name.clone()),
+            _ => None,
+        })
+        .or_else(|| ctx.cache.guild_channel(id).map(|gc| gc.name))
+        .unwrap_or_else(|| "a channel".into())
+}
+
+/// Something a mention refers to, to be swapped for its name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mention {
+    User(UserId),
+    Role(RoleId),
+    Channel(ChannelId),
+}
+
+fn parse_id(caps: &Captures) -> u64 {
+    caps[1].parse().unwrap_or_default()
+}
+
+fn replace_mentions<F>(text: &str, is_ssml: bool, name: &F) -> String
+where
+    F: Fn(Mention) -> String,
+{
+    let text = USER_MENTION.replace_all(text, |caps: &Captures| {
+        insert(&name(Mention::User(UserId(parse_id(caps)))), is_ssml)
+    });
+    let text = ROLE_MENTION.replace_all(&text, |caps: &Captures| {
+        insert(&name(Mention::Role(RoleId(parse_id(caps)))), is_ssml)
+    });
+    let text = CHANNEL_MENTION.replace_all(&text, |caps: &Captures| {
+        insert(&name(Mention::Channel(ChannelId(parse_id(caps)))), is_ssml)
+    });
+    let text = COMMAND_MENTION.replace_all(&text, "slash $1");
+    EVERYONE_MENTION.replace_all(&text, "$1").into_owned()
+}
+
+/// Replace every Unicode emoji in `text` with its name, always taking the longest sequence
+/// that is still a single emoji so that skin tones and ZWJ sequences are read as one.
+fn describe_unicode_emoji(text: &str, is_ssml: bool) -> String {
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let (start, c) = chars[i];
+        let found = if c.is_ascii() {
+            None
+        } else {
+            (1..=MAX_EMOJI_CHARS.min(chars.len() - i))
+                .rev()
+                .find_map(|len| {
+                    let end = chars.get(i + len).map_or(text.len(), |(e, _)| *e);
+                    emojis::get(&text[start..end]).map(|emoji| (len, emoji))
+                })
+        };
+
+        match found {
+            Some((len, emoji)) => {
+                result.push(' ');
+                result.push_str(&insert(emoji.name(), is_ssml));
+                result.push(' ');
+                i += len;
+            }
+            None => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    result
+}
+
+fn replace_emoji(text: &str, is_ssml: bool) -> String {
+    let text = CUSTOM_EMOJI.replace_all(text, |caps: &Captures| {
+        format!(" {} ", caps[1].replace('_', " "))
+    });
+    describe_unicode_emoji(&text, is_ssml)
+}
+
+fn replace_links(text: &str, is_ssml: bool) -> String {
+    URL.replace_all(text, |caps: &Captures| {
+        let host = caps[1].trim_start_matches("www.");
+        insert(&format!("link to {}", host), is_ssml)
+    })
+    .into_owned()
+}
+
+fn strip_formatting(text: &str) -> String {
+    let text = INLINE_CODE.replace_all(text, "$1");
+    let text = BOLD.replace_all(&text, "$1");
+    let text = UNDERLINE.replace_all(&text, "$1");
+    let text = STRIKETHROUGH.replace_all(&text, "$1");
+    let text = ITALIC_STAR.replace_all(&text, "$1");
+    let text = ITALIC_UNDERSCORE.replace_all(&text, "$1");
+    LINE_PREFIX.replace_all(&text, "").into_owned()
+}
+
+/// Turn Discord message content into something that sounds right when read out, applying
+/// whichever steps are enabled in `settings`. `guild` is used to look up names for mentions.
+pub fn normalize(
+    ctx: &Context,
+    guild: Option<&Guild>,
+    settings: &NormalizationSettings,
+    text: &str,
+    is_ssml: bool,
+) -> String {
+    normalize_with(settings, text, is_ssml, &|mention| match mention {
+        Mention::User(id) => user_name(ctx, guild, id),
+        Mention::Role(id) => role_name(guild, id),
+        Mention::Channel(id) => channel_name(ctx, guild, id),
+    })
+}
+
+/// Everything `normalize` does, with `name` to look up what to say for each mention.
+fn normalize_with<F>(
+    settings: &NormalizationSettings,
+    text: &str,
+    is_ssml: bool,
+    name: &F,
+) -> String
+where
+    F: Fn(Mention) -> String,
+{
+    let mut text = Cow::Borrowed(text);
+
+    // code blocks and spoilers go first, so that nothing inside them is rewritten for nothing.
+    if settings.markdown {
+        text = Cow::Owned(CODE_BLOCK.replace_all(&text, " ").into_owned());
+    }
+    if settings.skip_spoilers {
+        text = Cow::Owned(SPOILER.replace_all(&text, " ").into_owned());
+    } else if settings.markdown {
+        text = Cow::Owned(SPOILER.replace_all(&text, "$1").into_owned());
+    }
+    // masked links have to go before bare links would swallow them.
+    if settings.markdown {
+        text = Cow::Owned(MASKED_LINK.replace_all(&text, "$1").into_owned());
+    }
+    if settings.links {
+        text = Cow::Owned(replace_links(&text, is_ssml));
+    }
+    if settings.mentions {
+        text = Cow::Owned(replace_mentions(&text, is_ssml, name));
+    }
+    if settings.emoji {
+        text = Cow::Owned(replace_emoji(&text, is_ssml));
+    }
+    // formatting last, since links and emoji names are full of underscores.
+    if settings.markdown {
+        text = Cow::Owned(strip_formatting(&text));
+    }
+
+    WHITESPACE.replace_all(text.trim(), " ").into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(mention: Mention) -> String {
+        match mention {
+            Mention::User(UserId(1)) => "Alice".into(),
+            Mention::User(_) => "Bob & co".into(),
+            Mention::Role(_) => "Moderators".into(),
+            Mention::Channel(_) => "general".into(),
+        }
+    }
+
+    fn normalized(text: &str) -> String {
+        normalize_with(&NormalizationSettings::default(), text, false, &name)
+    }
+
+    #[test]
+    fn says_names_for_mentions() {
+        let cases = [
+            ("hi <@1>", "hi Alice"),
+            ("hi <@!1> and <@2>", "hi Alice and Bob & co"),
+            ("ping <@&3> in <#4>", "ping Moderators in general"),
+            ("try </roll:5>", "try slash roll"),
+            ("@everyone look", "everyone look"),
+            ("<@not-an-id>", "<@not-an-id>"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(normalized(text), expected, "{:?}", text);
+        }
+
+        // names are escaped when they go into SSML.
+        assert_eq!(
+            normalize_with(&NormalizationSettings::default(), "<@2>", true, &name),
+            "Bob &amp; co"
+        );
+        let off = NormalizationSettings {
+            mentions: false,
+            ..Default::default()
+        };
+        assert_eq!(normalize_with(&off, "hi <@1>", false, &name), "hi <@1>");
+    }
+
+    #[test]
+    fn names_emoji() {
+        let cases = [
+            ("nice <:party_parrot:123>", "nice party parrot"),
+            ("<a:dance:456>!", "dance !"),
+            ("ok 👍", "ok thumbs up"),
+            ("👍👍", "thumbs up thumbs up"),
+            ("héllo", "héllo"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(normalized(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn strips_markdown_and_code_blocks() {
+        let cases = [
+            ("look ```rust\nfn main() {}\n``` done", "look done"),
+            ("run `cargo test` now", "run cargo test now"),
+            (
+                "**bold** __under__ ~~gone~~ *it* _al_",
+                "bold under gone it al",
+            ),
+            ("> quoted\n- listed", "quoted listed"),
+            ("a ||secret|| here", "a secret here"),
+            ("snake_case_name stays", "snake_case_name stays"),
+            ("[the docs](https://docs.rs/regex)", "the docs"),
+            (
+                "see https://www.example.com/a_b?c=d",
+                "see link to example.com",
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(normalized(text), expected, "{:?}", text);
+        }
+
+        let skip = NormalizationSettings {
+            skip_spoilers: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize_with(&skip, "a ||secret|| here", false, &name),
+            "a here"
+        );
+    }
+}
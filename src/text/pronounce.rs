@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::{markup_matches, word_pattern};
+use crate::speech::escape_ssml;
+
+/// How a pronunciation's replacement should be read.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PronunciationKind {
+    /// Plain text that is said in place of the word.
+    #[default]
+    Text,
+    /// A phoneme in the International Phonetic Alphabet.
+    Ipa,
+    /// A phoneme in X-SAMPA.
+    XSampa,
+}
+
+impl PronunciationKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Ipa => "ipa",
+            Self::XSampa => "x-sampa",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "ipa" => Some(Self::Ipa),
+            "x-sampa" => Some(Self::XSampa),
+            _ => None,
+        }
+    }
+}
+
+/// How to say a word the synthesizer would otherwise get wrong.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Pronunciation {
+    pub replacement: String,
+    #[serde(default)]
+    pub kind: PronunciationKind,
+}
+
+/// Pronunciations keyed by the lowercased word they apply to.
+pub type Dictionary = BTreeMap<String, Pronunciation>;
+
+/// The key a word is stored under, so that matching ignores case.
+pub fn dictionary_key(word: &str) -> String {
+    word.trim().to_lowercase()
+}
+
+/// Build a single case-insensitive pattern matching any of `words`, trying longer words
+/// first so that multi-word entries win over the words they contain. Returns `None` if
+/// there are no words.
+pub(crate) fn dictionary_pattern<'a, I>(words: I) -> Option<Regex>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let mut words = words.into_iter().collect::<Vec<_>>();
+    if words.is_empty() {
+        return None;
+    }
+    words.sort_by_key(|w| std::cmp::Reverse(w.len()));
+    let alternatives = words
+        .into_iter()
+        .map(|w| word_pattern(w))
+        .collect::<Vec<_>>();
+
+    Regex::new(&format!("(?i){}", alternatives.join("|")))
+        .map_err(|e| tracing::error!(?e, "Could not build word pattern"))
+        .ok()
+}
+
+fn render(pronunciation: &Pronunciation, original: &str) -> String {
+    let replacement = escape_ssml(&pronunciation.replacement);
+    match pronunciation.kind {
+        PronunciationKind::Text => format!("<sub alias=\"{}\">{}</sub>", replacement, original),
+        kind => format!(
+            "<phoneme alphabet=\"{}\" ph=\"{}\">{}</phoneme>",
+            kind.name(),
+            replacement,
+            original
+        ),
+    }
+}
+
+/// Mark up every dictionary word in `text` with how it should be pronounced. The result is
+/// always SSML. Returns `None` if there was nothing to do.
+pub fn apply(dictionary: &Dictionary, text: &str, is_ssml: bool) -> Option<String> {
+    let pattern = dictionary_pattern(dictionary.keys())?;
+
+    Some(markup_matches(
+        &pattern,
+        text,
+        is_ssml,
+        |raw, original| match dictionary.get(&dictionary_key(raw)) {
+            Some(p) => render(p, original),
+            None => original.to_owned(),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary_of(entries: &[(&str, &str, PronunciationKind)]) -> Dictionary {
+        entries
+            .iter()
+            .map(|(word, replacement, kind)| {
+                (
+                    dictionary_key(word),
+                    Pronunciation {
+                        replacement: replacement.to_string(),
+                        kind: *kind,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn longer_entries_win_over_words_they_contain() {
+        let dictionary = dictionary_of(&[
+            ("york", "yawk", PronunciationKind::Text),
+            ("new york", "noo yawk", PronunciationKind::Text),
+        ]);
+        assert_eq!(
+            apply(&dictionary, "New York, not York", false).unwrap(),
+            "<sub alias=\"noo yawk\">New York</sub>, not <sub alias=\"yawk\">York</sub>"
+        );
+    }
+
+    #[test]
+    fn only_matches_whole_words() {
+        let dictionary = dictionary_of(&[("gif", "jif", PronunciationKind::Text)]);
+        assert_eq!(
+            apply(&dictionary, "gifs of a GIF", false).unwrap(),
+            "gifs of a <sub alias=\"jif\">GIF</sub>"
+        );
+        // punctuation at the edge of an entry has no word boundary to check.
+        let dictionary = dictionary_of(&[("c++", "see plus plus", PronunciationKind::Text)]);
+        assert_eq!(
+            apply(&dictionary, "c++ and c++17", false).unwrap(),
+            "<sub alias=\"see plus plus\">c++</sub> and <sub alias=\"see plus plus\">c++</sub>17"
+        );
+    }
+
+    #[test]
+    fn escapes_and_leaves_markup_alone() {
+        let dictionary = dictionary_of(&[
+            ("tomato", "təˈmɑːtəʊ", PronunciationKind::Ipa),
+            ("q&a", "Q and A", PronunciationKind::Text),
+        ]);
+        assert_eq!(
+            apply(&dictionary, "a q&a on <tomato>", false).unwrap(),
+            "a <sub alias=\"Q and A\">q&amp;a</sub> on &lt;<phoneme alphabet=\"ipa\" ph=\"təˈmɑːtəʊ\">tomato</phoneme>&gt;"
+        );
+        // attributes aren't words to be pronounced.
+        assert_eq!(
+            apply(&dictionary, "<prosody rate=\"tomato\">tomato</prosody>", true).unwrap(),
+            "<prosody rate=\"tomato\"><phoneme alphabet=\"ipa\" ph=\"təˈmɑːtəʊ\">tomato</phoneme></prosody>"
+        );
+        assert_eq!(apply(&Dictionary::new(), "tomato", false), None);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{collections::HashMap, fmt};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::speech::VoiceOptions;
+
+/// A script can't be split into more pieces than this, since each is its own API call.
+pub const MAX_SEGMENTS: usize = 20;
+
+/// Character definitions at the very start of a script, like `{Alice=en-GB-Wavenet-A, Bob=en-US}`.
+static HEADER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*\{([^{}]*)\}").unwrap());
+/// A switch to another character, defining them on the way if a voice is given, like
+/// `[Alice]` or `[Alice:en-GB-Wavenet-A]`.
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[([^\[\]:]+)(?::([^\[\]]+))?\]").unwrap());
+/// A language and region, like `en-GB` or `cmn-CN`.
+static LANGUAGE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[A-Za-z]{2,3}-[A-Za-z0-9]{2,4}$").unwrap());
+
+/// A run of text said by one character.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    /// Who's saying it, or `None` for anything before the first character speaks.
+    pub character: Option<String>,
+    /// The character's voice. `None` for the same voice as anything else would be said in.
+    pub voice: Option<VoiceOptions>,
+    pub text: String,
+}
+
+/// Why a script couldn't be understood, in a way that can be shown to whoever wrote it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    TooLong,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLong => write!(
+                f,
+                "Scripts can switch voices at most {} times.",
+                MAX_SEGMENTS
+            ),
+        }
+    }
+}
+
+/// Parse a voice like `en-GB-Wavenet-A`, `en-GB`, `en-GB/female` or just `male`.
+fn parse_voice(spec: &str) -> Option<VoiceOptions> {
+    let mut voice = VoiceOptions::default();
+    for part in spec.split('/').map(str::trim) {
+        match part.to_uppercase().as_str() {
+            "" => continue,
+            g @ ("MALE" | "FEMALE") => voice.gender = Some(g.to_owned()),
+            // voice names are the language followed by the kind of voice and a letter.
+            _ if part.split('-').count() >= 3 => voice.name = Some(part.to_owned()),
+            _ if LANGUAGE.is_match(part) => voice.language = Some(part.to_owned()),
+            _ => return None,
+        }
+    }
+
+    (!voice.is_empty()).then_some(voice)
+}
+
+/// Add what a character says to the script, skipping over any blank space between tags.
+fn push_segment(segments: &mut Vec<Segment>, current: &Option<(String, VoiceOptions)>, text: &str) {
+    if text.trim().is_empty() {
+        return;
+    }
+    segments.push(Segment {
+        character: current.as_ref().map(|(name, _)| name.clone()),
+        voice: current.as_ref().map(|(_, voice)| voice.clone()),
+        text: text.trim().to_owned(),
+    });
+}
+
+fn character_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Parse the character definitions in a `{...}` header, or `None` if it isn't one.
+fn parse_header(header: &str) -> Option<HashMap<String, (String, VoiceOptions)>> {
+    header
+        .split([',', ';'])
+        .filter(|definition| !definition.trim().is_empty())
+        .map(|definition| {
+            let (name, spec) = definition.split_once(['=', ':'])?;
+            Some((
+                character_key(name),
+                (name.trim().to_owned(), parse_voice(spec)?),
+            ))
+        })
+        .collect::<Option<HashMap<_, _>>>()
+        .filter(|characters| !characters.is_empty())
+}
+
+/// Split a dialogue script into what each character says. Only text that starts with a
+/// `{...}` header or a `[Character:voice]` tag that can be understood is taken to be a
+/// script, so that brackets in everyday messages are left alone. Returns `Ok(None)` for
+/// anything else. Within a script, brackets that don't switch to a known character, or
+/// give one a voice, are just part of what's said.
+pub fn parse(text: &str) -> Result<Option<Vec<Segment>>, ScriptError> {
+    let mut characters = HashMap::new();
+    let mut body = text;
+
+    if let Some(header) = HEADER.captures(text) {
+        characters = match parse_header(&header[1]) {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        body = &text[header.get(0).unwrap().end()..];
+    } else if !TAG
+        .captures(text)
+        .map(|t| {
+            text[..t.get(0).unwrap().start()].trim().is_empty()
+                && t.get(2)
+                    .and_then(|spec| parse_voice(spec.as_str()))
+                    .is_some()
+        })
+        .unwrap_or(false)
+    {
+        return Ok(None);
+    }
+
+    let mut segments = Vec::new();
+    let mut current: Option<(String, VoiceOptions)> = None;
+    let mut last = 0;
+    for tag in TAG.captures_iter(body) {
+        let key = character_key(&tag[1]);
+        let character = match tag.get(2) {
+            Some(spec) => match parse_voice(spec.as_str()) {
+                Some(voice) => (tag[1].trim().to_owned(), voice),
+                None => continue,
+            },
+            None => match characters.get(&key) {
+                Some(c) => c.clone(),
+                None => continue,
+            },
+        };
+
+        let whole = tag.get(0).unwrap();
+        push_segment(&mut segments, &current, &body[last..whole.start()]);
+        last = whole.end();
+        characters.insert(key, character.clone());
+        current = Some(character);
+    }
+    push_segment(&mut segments, &current, &body[last..]);
+
+    if segments.len() > MAX_SEGMENTS {
+        return Err(ScriptError::TooLong);
+    }
+    Ok(Some(segments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice(name: &str) -> Option<VoiceOptions> {
+        Some(VoiceOptions {
+            name: Some(name.to_owned()),
+            ..Default::default()
+        })
+    }
+
+    fn segment(character: &str, voice_name: &str, text: &str) -> Segment {
+        Segment {
+            character: Some(character.to_owned()),
+            voice: voice(voice_name),
+            text: text.to_owned(),
+        }
+    }
+
+    #[test]
+    fn leaves_everyday_messages_alone() {
+        for text in [
+            "hello everyone",
+            "{hello} everyone",
+            "{not a=voice!} hi",
+            "[sarcasm] sure",
+            "[Bob: hi there] hello",
+            "[Note: important] read this",
+            "{} hi",
+            "well [Alice:en-GB-Wavenet-A] hi",
+        ] {
+            assert_eq!(parse(text), Ok(None), "{}", text);
+        }
+    }
+
+    #[test]
+    fn header_defines_characters() {
+        let script = parse("{Alice=en-GB-Wavenet-A, Bob=en-US-Wavenet-B} [Alice] Hi. [bob] Hello!")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            script,
+            vec![
+                segment("Alice", "en-GB-Wavenet-A", "Hi."),
+                segment("Bob", "en-US-Wavenet-B", "Hello!"),
+            ]
+        );
+    }
+
+    #[test]
+    fn tags_define_characters_as_they_go() {
+        let script = parse("[Alice:en-GB-Wavenet-A] Hi. [Bob:en-US/male] Hey. [Alice] Bye.")
+            .unwrap()
+            .unwrap();
+        assert_eq!(script.len(), 3);
+        assert_eq!(
+            script[1].voice,
+            Some(VoiceOptions {
+                language: Some("en-US".into()),
+                gender: Some("MALE".into()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(script[2], segment("Alice", "en-GB-Wavenet-A", "Bye."));
+    }
+
+    #[test]
+    fn unknown_tags_are_said_as_they_are() {
+        let script = parse("[Alice:en-GB-Wavenet-A] I [really] mean it [Bob: or not]")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            script,
+            vec![segment(
+                "Alice",
+                "en-GB-Wavenet-A",
+                "I [really] mean it [Bob: or not]"
+            )]
+        );
+    }
+
+    #[test]
+    fn too_many_segments() {
+        let text = "[A:en-GB] a [B:en-US] b ".repeat(MAX_SEGMENTS);
+        assert_eq!(parse(&text), Err(ScriptError::TooLong));
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serenity::{
+    client::Context,
+    model::{
+        id::{GuildId, UserId},
+        Timestamp,
+    },
+    prelude::TypeMapKey,
+};
+use tokio::{task::JoinHandle, time::Instant};
+
+use crate::{
+    session::get_sessions_from_ctx,
+    speech::{enqueue_cue, speak, synthesize, AudioFormat, Utterance, VoiceOptions},
+};
+
+/// The last few seconds are counted down one by one.
+const COUNTDOWN_SECONDS: u64 = 5;
+/// Cached clips are only a few words each, but there's no sense letting them pile up forever.
+const MAX_CACHED_CLIPS: usize = 500;
+
+/// How long is left, the way someone would say it.
+fn describe_remaining(remaining: Duration) -> String {
+    let seconds = remaining.as_secs();
+    let parts = [
+        (seconds / 3600, "hour"),
+        (seconds / 60 % 60, "minute"),
+        (seconds % 60, "second"),
+    ];
+    let description = parts
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{} {}{}", n, unit, if *n == 1 { "" } else { "s" }))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{} left", description)
+}
+
+/// What a timer says on the way, as how long before the end to say it and what to say.
+/// Later cues come first.
+fn cues(duration: Duration, checkpoints: &[Duration]) -> Vec<(Duration, String)> {
+    let countdown = Duration::from_secs(COUNTDOWN_SECONDS);
+    let mut checkpoints = checkpoints
+        .iter()
+        .filter(|c| **c < duration && **c > countdown)
+        .map(|c| (*c, describe_remaining(*c)))
+        .collect::<Vec<_>>();
+    checkpoints.sort_by_key(|c| Reverse(c.0));
+    checkpoints.dedup_by_key(|c| c.0);
+
+    let countdown = (1..=COUNTDOWN_SECONDS)
+        .rev()
+        .map(Duration::from_secs)
+        .filter(|s| *s < duration)
+        .map(|s| (s, s.as_secs().to_string()));
+    checkpoints.extend(countdown);
+    checkpoints
+}
+
+/// What a clip says, in which voice, and the seed that voice was picked with.
+type ClipKey = (String, VoiceOptions, u64);
+
+/// A timer that's counting down in a guild.
+#[derive(Clone, Debug)]
+pub struct TimerInfo {
+    pub id: u64,
+    pub created_by: Option<UserId>,
+    pub message: String,
+    pub ends_at: Timestamp,
+}
+
+struct RunningTimer {
+    info: TimerInfo,
+    handle: JoinHandle<()>,
+}
+
+/// Keeps track of running timers, along with the clips they announce checkpoints with,
+/// since the same few phrases get said over and over.
+#[derive(Default)]
+pub struct TimerManager {
+    timers: Mutex<HashMap<GuildId, Vec<RunningTimer>>>,
+    clips: Mutex<HashMap<ClipKey, Arc<[u8]>>>,
+    next_id: AtomicU64,
+}
+
+impl TimerManager {
+    /// Get a clip of `text` out of the cache, synthesizing it if this is the first time.
+    async fn clip(
+        &self,
+        ctx: &Context,
+        text: &str,
+        voice: &VoiceOptions,
+        seed: u64,
+    ) -> anyhow::Result<Arc<[u8]>> {
+        let key = (text.to_owned(), voice.clone(), seed);
+        if let Some(clip) = self.clips.lock().unwrap().get(&key) {
+            return Ok(clip.clone());
+        }
+
+        let utterance = Utterance {
+            text: text.to_owned(),
+            is_ssml: false,
+            voice: voice.clone(),
+            voice_seed: Some(seed),
+            requester: None,
+            prosody: Default::default(),
+            effects: Vec::new(),
+        };
+        let clip: Arc<[u8]> = synthesize(ctx, &utterance, AudioFormat::Wav)
+            .await?
+            .audio
+            .into();
+
+        let mut clips = self.clips.lock().unwrap();
+        if clips.len() >= MAX_CACHED_CLIPS {
+            clips.clear();
+        }
+        clips.insert(key, clip.clone());
+        Ok(clip)
+    }
+
+    /// Start a timer, saying how long is left at each checkpoint and counting down the last
+    /// few seconds, then saying `utterance`. Everything said on the way is synthesized before
+    /// the timer starts, in the same voice, so it's ready when it's needed.
+    pub async fn start(
+        self: &Arc<Self>,
+        ctx: &Context,
+        guild_id: GuildId,
+        duration: Duration,
+        checkpoints: &[Duration],
+        utterance: Utterance,
+    ) -> anyhow::Result<TimerInfo> {
+        let seed = utterance.voice_seed.unwrap_or_default();
+        let mut clips = Vec::new();
+        for (remaining, text) in cues(duration, checkpoints) {
+            clips.push((
+                remaining,
+                self.clip(ctx, &text, &utterance.voice, seed).await?,
+            ));
+        }
+
+        let info = TimerInfo {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            created_by: utterance.requester,
+            message: utterance.text.clone(),
+            ends_at: Timestamp::from_unix_timestamp(
+                Timestamp::now().unix_timestamp() + duration.as_secs() as i64,
+            )?,
+        };
+
+        let id = info.id;
+        let manager = self.clone();
+        let ctx = ctx.clone();
+        let end = Instant::now() + duration;
+        let mut timers = self.timers.lock().unwrap();
+        let handle = tokio::spawn(async move {
+            for (remaining, clip) in clips {
+                tokio::time::sleep_until(end - remaining).await;
+                match get_sessions_from_ctx(&ctx).await.get(guild_id).await {
+                    Some(session) => {
+                        if let Err(e) = enqueue_cue(&session, &clip).await {
+                            tracing::error!(?e, ?guild_id, id, "Could not play timer checkpoint");
+                        }
+                    }
+                    None => break,
+                }
+            }
+
+            tokio::time::sleep_until(end).await;
+            if let Some(session) = get_sessions_from_ctx(&ctx).await.get(guild_id).await {
+                if let Err(e) = speak(&ctx, &session, &utterance).await {
+                    tracing::error!(?e, ?guild_id, id, "Could not finish timer");
+                }
+            }
+
+            manager.remove(guild_id, id);
+        });
+        timers.entry(guild_id).or_default().push(RunningTimer {
+            info: info.clone(),
+            handle,
+        });
+
+        Ok(info)
+    }
+
+    fn remove(&self, guild_id: GuildId, id: u64) -> Option<RunningTimer> {
+        let mut timers = self.timers.lock().unwrap();
+        let running = timers.get_mut(&guild_id)?;
+        let index = running.iter().position(|t| t.info.id == id)?;
+        Some(running.remove(index))
+    }
+
+    /// Stop a timer before it goes off. Returns whether there was such a timer.
+    pub fn cancel(&self, guild_id: GuildId, id: u64) -> bool {
+        match self.remove(guild_id, id) {
+            Some(t) => {
+                t.handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every timer running in this guild, soonest first.
+    pub fn list(&self, guild_id: GuildId) -> Vec<TimerInfo> {
+        let mut timers = self
+            .timers
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .map(|t| t.iter().map(|t| t.info.clone()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        timers.sort_by_key(|t| t.ends_at.unix_timestamp());
+        timers
+    }
+}
+
+pub struct Timers;
+impl TypeMapKey for Timers {
+    type Value = Arc<TimerManager>;
+}
+
+pub async fn get_timers_from_ctx(ctx: &Context) -> Arc<TimerManager> {
+    ctx.data
+        .read()
+        .await
+        .get::<Timers>()
+        .expect("Timer manager should be present")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_remaining_time() {
+        let cases = [
+            (1, "1 second left"),
+            (45, "45 seconds left"),
+            (60, "1 minute left"),
+            (90, "1 minute 30 seconds left"),
+            (3600, "1 hour left"),
+            (2 * 3600 + 5, "2 hours 5 seconds left"),
+        ];
+        for (seconds, expected) in cases {
+            assert_eq!(describe_remaining(Duration::from_secs(seconds)), expected);
+        }
+    }
+
+    #[test]
+    fn cues_latest_first_then_count_down() {
+        let secs = Duration::from_secs;
+        let cues = cues(secs(120), &[secs(10), secs(60), secs(10)]);
+        let expected = [
+            (60, "1 minute left"),
+            (10, "10 seconds left"),
+            (5, "5"),
+            (4, "4"),
+            (3, "3"),
+            (2, "2"),
+            (1, "1"),
+        ];
+        assert_eq!(
+            cues,
+            expected
+                .iter()
+                .map(|(s, t)| (secs(*s), t.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn cues_skip_checkpoints_outside_the_timer() {
+        let secs = Duration::from_secs;
+        // the timer's own length, anything past it and anything in the countdown are left out.
+        let cues = cues(secs(3), &[secs(3), secs(60), secs(2)]);
+        assert_eq!(
+            cues,
+            vec![(secs(2), "2".to_string()), (secs(1), "1".to_string())]
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use serenity::{
+    async_trait,
+    client::{Context, EventHandler},
+    model::{id::GuildId, voice::VoiceState},
+};
+use songbird::id::ChannelId;
+
+use crate::session::{get_sessions_from_ctx, GuildSession};
+
+/// Whether there are no humans left in the given voice channel. Other bots don't count as listeners.
+pub(crate) fn is_alone(ctx: &Context, guild_id: GuildId, channel_id: ChannelId) -> bool {
+    let guild = match guild_id.to_guild_cached(&ctx.cache) {
+        Some(g) => g,
+        None => return false,
+    };
+
+    !guild.voice_states.values().any(|vs| {
+        vs.channel_id.map(ChannelId::from) == Some(channel_id)
+            && !ctx
+                .cache
+                .user(vs.user_id)
+                .map(|u| u.bot)
+                .unwrap_or_else(|| vs.user_id == ctx.cache.current_user_id())
+    })
+}
+
+async fn schedule_departure(ctx: &Context, session: Arc<GuildSession>) -> anyhow::Result<()> {
+    if session.has_pending_departure() {
+        return Ok(());
+    }
+
+    let grace_period = session.settings().await?.alone_timeout();
+    tracing::info!(
+        guild_id=?session.guild_id,
+        ?grace_period,
+        "Alone in voice channel, scheduling departure"
+    );
+
+    let task_ctx = ctx.clone();
+    let guild_id = session.guild_id;
+    session.set_pending_departure(tokio::spawn(async move {
+        tokio::time::sleep(grace_period).await;
+
+        let sessions = get_sessions_from_ctx(&task_ctx).await;
+        let session = match sessions.get(guild_id).await {
+            Some(s) => s,
+            None => return,
+        };
+        session.departure_started();
+
+        // someone may have slipped back in without us cancelling in time, check again.
+        match session.current_channel().await {
+            Some(channel_id) if !is_alone(&task_ctx, guild_id, channel_id) => {}
+            _ => {
+                tracing::info!(?guild_id, "Still alone in voice channel, leaving!");
+                if let Err(e) = sessions.leave(guild_id).await {
+                    tracing::error!(?e, ?guild_id, "Could not leave voice channel");
+                }
+            }
+        }
+    }));
+
+    Ok(())
+}
+
+/// Watches voice state changes so the bot notices when it's been disconnected by someone
+/// else, and so it can leave once it's the only one left in its channel.
+pub struct VoiceStateHandler;
+
+#[async_trait]
+impl EventHandler for VoiceStateHandler {
+    async fn voice_state_update(&self, ctx: Context, _old: Option<VoiceState>, new: VoiceState) {
+        let guild_id = match new.guild_id {
+            Some(g) => g,
+            None => return,
+        };
+
+        let sessions = get_sessions_from_ctx(&ctx).await;
+        let session = match sessions.get(guild_id).await {
+            Some(s) => s,
+            None => return,
+        };
+
+        // a moderator disconnecting us shows up as our own voice state losing its channel.
+        if new.user_id == ctx.cache.current_user_id() && new.channel_id.is_none() {
+            tracing::info!(?guild_id, "Disconnected from voice by someone else");
+            if let Err(e) = sessions.leave(guild_id).await {
+                tracing::error!(?e, ?guild_id, "Could not clean up voice session");
+            }
+            return;
+        }
+
+        let bot_channel = session.current_channel().await;
+
+        // follow our summoner around if we've been asked to.
+        if new.user_id == session.summoner() && session.is_following_summoner() {
+            if let Some(channel_id) = new.channel_id.map(ChannelId::from) {
+                if bot_channel.is_some() && bot_channel != Some(channel_id) {
+                    tracing::info!(?guild_id, ?channel_id, "Following summoner to new channel");
+                    if let Err(e) = sessions.join(guild_id, channel_id, new.user_id).await {
+                        tracing::error!(?e, ?guild_id, "Could not follow summoner");
+                    }
+                    // our own voice state update for the move will take care of the rest.
+                    return;
+                }
+            }
+        }
+
+        match bot_channel {
+            Some(channel_id) if is_alone(&ctx, guild_id, channel_id) => {
+                if let Err(e) = schedule_departure(&ctx, session).await {
+                    tracing::error!(?e, ?guild_id, "Could not schedule departure");
+                }
+            }
+            _ => {
+                if session.cancel_departure() {
+                    tracing::info!(?guild_id, "Someone came back, cancelling departure");
+                }
+            }
+        }
+    }
+}